@@ -1,6 +1,6 @@
 #![no_main]
 #[macro_use] extern crate libfuzzer_sys;
-use shlex::try_join;
+use shlex::{try_join, Dialect, Quoter, Shlex};
 use nu_pretty_hex::pretty_hex;
 
 use pyo3::prelude::*;
@@ -16,6 +16,15 @@ fn shlex_split(words: &str) -> Result<Vec<String>, String> {
     })
 }
 
+fn shlex_quote(word: &str) -> String {
+    Python::with_gil(|py| {
+        py.import("shlex").unwrap()
+            .getattr("quote").unwrap()
+            .call1((word,)).unwrap()
+            .extract().unwrap()
+    })
+}
+
 fn pretty_hex_multi<'a>(strings: impl IntoIterator<Item = &'a str>) -> String {
     let mut res = "[\n".to_owned();
     for string in strings {
@@ -52,5 +61,26 @@ fuzz_target!(|unquoted: &[u8]| {
                    err);
         },
     }
+
+    // Check that Dialect::Python matches shlex.quote exactly, and that Dialect::Python splitting
+    // can read it back.
+    let python_quoter = Quoter::new().dialect(Dialect::Python).allow_nul(true);
+    for word in &words {
+        let ours = python_quoter.quote(word).unwrap();
+        let theirs = shlex_quote(word);
+        if ours != theirs {
+            panic!("original:\n{}\nshlex.quote output:\n{}\nquoted:\n{}",
+                   pretty_hex(word), pretty_hex(&theirs), pretty_hex(&ours));
+        }
+    }
+    let python_quoted = python_quoter.join(words.iter().cloned()).unwrap();
+    let mut shl = Shlex::new(&python_quoted).dialect(Dialect::Python);
+    let split: Vec<String> = shl.by_ref().collect();
+    if shl.had_error || split != words {
+        panic!("original: {}\nsplit:{}\nquoted:\n{}",
+               pretty_hex_multi(words.iter().cloned()),
+               pretty_hex_multi(split.iter().map(|x| &**x)),
+               pretty_hex(&python_quoted));
+    }
 });
 
@@ -13,6 +13,7 @@ shells=(
     'dash +m'
     'fish --private --no-config'
     'mksh'
+    'tcsh -f'
 )
 
 running_on_linux=1
@@ -57,7 +58,16 @@ for shell in $shells; do
                     # Pipes don't have this issue.
                     continue
                 fi
-                prefix="FUZZ_USE_PTY=$pty FUZZ_SHELL=\"env LANG=$lang $shell $interactive\" "
+                flag=$interactive
+                if [[ $shell == tcsh* && $interactive == '+i' ]]; then
+                    # tcsh has no +i flag; it is noninteractive by default
+                    # when not reading from a terminal.
+                    if (( pty )); then
+                        continue
+                    fi
+                    flag=''
+                fi
+                prefix="FUZZ_USE_PTY=$pty FUZZ_SHELL=\"env LANG=$lang $shell $flag\" "
                 echo ">> ${already_set}ident='$ident' $prefix $*"
                 eval "$prefix $*" || {
                     echo "FAIL: $ident"
@@ -12,7 +12,7 @@ use rand::{distributions::Alphanumeric, Rng};
 use bstr::ByteSlice;
 use nu_pretty_hex::pretty_hex;
 
-use shlex::bytes;
+use shlex::{bytes, Dialect};
 
 #[derive(PartialEq, Debug)]
 enum CompatMode {
@@ -22,6 +22,7 @@ enum CompatMode {
     BusyboxAsh,
     Fish,
     Mksh,
+    Tcsh,
     Other
 }
 
@@ -83,6 +84,7 @@ impl Config {
                 "busybox ash" => CompatMode::BusyboxAsh,
                 "fish" => CompatMode::Fish,
                 "mksh" => CompatMode::Mksh,
+                "tcsh" => CompatMode::Tcsh,
                 "other" => CompatMode::Other,
                 "" => {
                     // default: guess the shell from the string (somewhat dumbly)
@@ -98,6 +100,8 @@ impl Config {
                         CompatMode::Fish
                     } else if fuzz_shell.contains("mksh") {
                         CompatMode::Mksh
+                    } else if fuzz_shell.contains("csh") {
+                        CompatMode::Tcsh
                     } else {
                         CompatMode::Other
                     }
@@ -326,6 +330,7 @@ fuzz_target!(|unquoted: &[u8]| {
         CompatMode::Zsh | CompatMode::BusyboxAsh => config.use_pty,
         CompatMode::Fish => config.use_pty,
         CompatMode::Mksh => config.use_pty,
+        CompatMode::Tcsh => config.use_pty,
         _ => false
     };
 
@@ -353,7 +358,8 @@ fuzz_target!(|unquoted: &[u8]| {
         CompatMode::Zsh => config.shell_is_interactive && is_invalid_utf8,
         CompatMode::BusyboxAsh |
         CompatMode::Fish |
-        CompatMode::Mksh => config.use_pty && is_invalid_utf8,
+        CompatMode::Mksh |
+        CompatMode::Tcsh => config.use_pty && is_invalid_utf8,
         CompatMode::Dash |
         CompatMode::Other => false,
     };
@@ -370,7 +376,11 @@ fuzz_target!(|unquoted: &[u8]| {
     //println!("len={}", unquoted.len());
 
     // We already filtered out nul bytes so this should be successful.
-    let quoted = bytes::try_quote(&unquoted).unwrap();
+    let dialect = match config.compat_mode {
+        CompatMode::Tcsh => Dialect::Csh,
+        _ => Dialect::Posix,
+    };
+    let quoted = bytes::Quoter::new().dialect(dialect).quote(&unquoted).unwrap();
 
     SHELL.with(|ref_shell| {
         let mut shell = ref_shell.borrow_mut();
@@ -0,0 +1,309 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Evaluation of POSIX arithmetic expressions, as in `$((...))`.
+//!
+//! Arithmetic is done on `i64`s.  Unlike in shells, which silently wrap around, overflow is an
+//! error.  As in bash, a variable whose value isn't a number is evaluated as an expression itself.
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::expand::ExpandError;
+use crate::word::{is_name_char, is_name_start};
+
+/// How many levels deep variables whose values are expressions can refer to each other.
+const MAX_DEPTH: usize = 64;
+
+/// How many levels deep operators and parentheses can nest in one expression.
+const MAX_NESTING: usize = 256;
+
+/// Where an arithmetic expression reads and assigns variables.
+pub(crate) trait Env {
+    fn get(&mut self, name: &str) -> Option<Vec<u8>>;
+    fn set(&mut self, name: &str, value: i64);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+/// Every operator, longest first.
+const OPS: &[&str] = &[
+    "<<=", ">>=",
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=",
+    "*", "/", "%", "+", "-", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=", "(", ")",
+];
+
+/// Evaluate `expr`.  An empty expression evaluates to 0.
+pub(crate) fn evaluate(expr: &[u8], env: &mut dyn Env) -> Result<i64, ExpandError> {
+    evaluate_at_depth(expr, env, 0, 0)
+}
+
+/// Evaluate `expr`, which is the value of a variable `depth` levels deep, inside `nesting` levels
+/// of operators and parentheses in the expressions that refer to it.
+fn evaluate_at_depth(expr: &[u8], env: &mut dyn Env, depth: usize, nesting: usize) -> Result<i64, ExpandError> {
+    let mut evaluator = Evaluator { expr, tokens: Vec::new(), pos: 0, env, depth, nesting };
+    if depth > MAX_DEPTH {
+        return Err(evaluator.syntax_error());
+    }
+    evaluator.tokens = evaluator.tokenize()?;
+    if evaluator.tokens.is_empty() {
+        return Ok(0);
+    }
+    let value = evaluator.assignment(true)?;
+    if evaluator.pos != evaluator.tokens.len() {
+        return Err(evaluator.syntax_error());
+    }
+    Ok(value)
+}
+
+struct Evaluator<'a> {
+    expr: &'a [u8],
+    tokens: Vec<Token>,
+    pos: usize,
+    env: &'a mut dyn Env,
+    depth: usize,
+    /// How many calls to [`Evaluator::nested`] are in progress, counting those in the expressions
+    /// that referred to this one, so that nesting spread across variables is limited too.
+    nesting: usize,
+}
+
+impl Evaluator<'_> {
+    fn syntax_error(&self) -> ExpandError {
+        ExpandError::InvalidArithmetic { expression: String::from_utf8_lossy(self.expr).into_owned() }
+    }
+
+    fn tokenize(&self) -> Result<Vec<Token>, ExpandError> {
+        let expr = self.expr;
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < expr.len() {
+            let c = expr[i];
+            let len = expr[i..].iter().position(|&c| !is_name_char(c)).unwrap_or(expr.len() - i);
+            if matches!(c, b' ' | b'\t' | b'\n') {
+                i += 1;
+                continue;
+            } else if is_name_start(c) {
+                tokens.push(Token::Name(String::from_utf8_lossy(&expr[i..i + len]).into_owned()));
+            } else if c.is_ascii_digit() {
+                tokens.push(Token::Number(self.parse_number(&expr[i..i + len])?));
+            } else {
+                let op = OPS.iter().find(|op| expr[i..].starts_with(op.as_bytes())).ok_or_else(|| self.syntax_error())?;
+                tokens.push(Token::Op(op));
+                i += op.len();
+                continue;
+            }
+            i += len;
+        }
+        Ok(tokens)
+    }
+
+    /// Parse a decimal, octal (with a leading `0`) or hexadecimal (with a leading `0x`) constant.
+    fn parse_number(&self, text: &[u8]) -> Result<i64, ExpandError> {
+        let (radix, digits) = match text {
+            [b'0', b'x', rest @ ..] | [b'0', b'X', rest @ ..] => (16, rest),
+            [b'0', rest @ ..] => (8, rest),
+            _ => (10, text),
+        };
+        if digits.is_empty() && radix == 16 {
+            return Err(self.syntax_error());
+        }
+        let mut value: i64 = 0;
+        for &c in digits {
+            let digit = (c as char).to_digit(radix).ok_or_else(|| self.syntax_error())?;
+            value = value.checked_mul(i64::from(radix))
+                .and_then(|value| value.checked_add(i64::from(digit)))
+                .ok_or(ExpandError::ArithmeticOverflow)?;
+        }
+        Ok(value)
+    }
+
+    /// Call `f`, unless the expression is already nested too deeply.
+    fn nested(&mut self, f: fn(&mut Self, bool) -> Result<i64, ExpandError>, eval: bool) -> Result<i64, ExpandError> {
+        if self.nesting == MAX_NESTING {
+            return Err(ExpandError::TooDeep);
+        }
+        self.nesting += 1;
+        let res = f(self, eval);
+        self.nesting -= 1;
+        res
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ExpandError> {
+        if self.peek_op() != Some(op) {
+            return Err(self.syntax_error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Parse, and if `eval`, evaluate, an assignment or conditional expression.  When not
+    /// evaluating, there are no side effects or errors other than syntax errors, and the result
+    /// is 0.
+    fn assignment(&mut self, eval: bool) -> Result<i64, ExpandError> {
+        let op = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Name(_)), Some(Token::Op(op))) if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") => *op,
+            _ => { return self.conditional(eval); },
+        };
+        let name = match &self.tokens[self.pos] {
+            Token::Name(name) => name.clone(),
+            _ => unreachable!(),
+        };
+        self.pos += 2;
+        let rhs = self.nested(Self::assignment, eval)?;
+        if !eval {
+            return Ok(0);
+        }
+        let value = if op == "=" {
+            rhs
+        } else {
+            let lhs = self.variable(&name)?;
+            apply(&op[..op.len() - 1], lhs, rhs)?
+        };
+        self.env.set(&name, value);
+        Ok(value)
+    }
+
+    fn conditional(&mut self, eval: bool) -> Result<i64, ExpandError> {
+        let cond = self.binary(0, eval)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.nested(Self::assignment, eval && cond != 0)?;
+        self.expect(":")?;
+        let otherwise = self.nested(Self::conditional, eval && cond == 0)?;
+        Ok(if cond != 0 { then } else { otherwise })
+    }
+
+    /// Parse a sequence of binary operators with precedence at least `min_prec`.
+    fn binary(&mut self, min_prec: u8, eval: bool) -> Result<i64, ExpandError> {
+        let mut lhs = self.unary(eval)?;
+        while let Some(op) = self.peek_op() {
+            let prec = match precedence(op) {
+                Some(prec) if prec >= min_prec => prec,
+                _ => { break; },
+            };
+            self.pos += 1;
+            // `&&` and `||` don't evaluate their right side if the left side decides the result.
+            let rhs_eval = match op {
+                "&&" => eval && lhs != 0,
+                "||" => eval && lhs == 0,
+                _ => eval,
+            };
+            let rhs = self.binary(prec + 1, rhs_eval)?;
+            lhs = if eval { apply(op, lhs, rhs)? } else { 0 };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self, eval: bool) -> Result<i64, ExpandError> {
+        let op = match self.peek_op() {
+            Some(op @ "+") | Some(op @ "-") | Some(op @ "!") | Some(op @ "~") => op,
+            _ => { return self.primary(eval); },
+        };
+        self.pos += 1;
+        let value = self.nested(Self::unary, eval)?;
+        if !eval {
+            return Ok(0);
+        }
+        Ok(match op {
+            "+" => value,
+            "-" => value.checked_neg().ok_or(ExpandError::ArithmeticOverflow)?,
+            "!" => i64::from(value == 0),
+            _ => !value,
+        })
+    }
+
+    fn primary(&mut self, eval: bool) -> Result<i64, ExpandError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or_else(|| self.syntax_error())?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(n),
+            Token::Name(name) => if eval { self.variable(&name) } else { Ok(0) },
+            Token::Op("(") => {
+                let value = self.nested(Self::assignment, eval)?;
+                self.expect(")")?;
+                Ok(value)
+            },
+            Token::Op(_) => Err(self.syntax_error()),
+        }
+    }
+
+    /// The value of a variable.  Unset and empty variables are 0.
+    fn variable(&mut self, name: &str) -> Result<i64, ExpandError> {
+        match self.env.get(name) {
+            Some(value) => evaluate_at_depth(&value, self.env, self.depth + 1, self.nesting),
+            None => Ok(0),
+        }
+    }
+}
+
+/// The precedence of a binary operator, from 0 for `||` up, or `None` if `op` isn't one.
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 0,
+        "&&" => 1,
+        "|" => 2,
+        "^" => 3,
+        "&" => 4,
+        "==" | "!=" => 5,
+        "<" | "<=" | ">" | ">=" => 6,
+        "<<" | ">>" => 7,
+        "+" | "-" => 8,
+        "*" | "/" | "%" => 9,
+        _ => { return None; },
+    })
+}
+
+/// Apply the binary operator `op`.
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, ExpandError> {
+    let overflow = ExpandError::ArithmeticOverflow;
+    Ok(match op {
+        "||" => i64::from(lhs != 0 || rhs != 0),
+        "&&" => i64::from(lhs != 0 && rhs != 0),
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => i64::from(lhs == rhs),
+        "!=" => i64::from(lhs != rhs),
+        "<" => i64::from(lhs < rhs),
+        "<=" => i64::from(lhs <= rhs),
+        ">" => i64::from(lhs > rhs),
+        ">=" => i64::from(lhs >= rhs),
+        "<<" | ">>" => {
+            let shift = if (0..64).contains(&rhs) { rhs as u32 } else { return Err(overflow); };
+            if op == ">>" {
+                lhs >> shift
+            } else {
+                // Shifting out bits other than copies of the sign bit loses them.
+                let result = lhs << shift;
+                if result >> shift != lhs {
+                    return Err(overflow);
+                }
+                result
+            }
+        },
+        "+" => lhs.checked_add(rhs).ok_or(overflow)?,
+        "-" => lhs.checked_sub(rhs).ok_or(overflow)?,
+        "*" => lhs.checked_mul(rhs).ok_or(overflow)?,
+        "/" | "%" if rhs == 0 => { return Err(ExpandError::DivisionByZero); },
+        "/" => lhs.checked_div(rhs).ok_or(overflow)?,
+        "%" => lhs.checked_rem(rhs).ok_or(overflow)?,
+        _ => unreachable!(),
+    })
+}
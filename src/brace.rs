@@ -0,0 +1,249 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Bash-style brace expansion (`a{b,c}d`, `{1..10}`, `{a..z}`), which turns one word into many.
+//!
+//! Only unquoted braces and commas count; anything quoted or expanded is treated as an opaque
+//! part of whichever alternative it's in.
+
+extern crate alloc;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::expand::ExpandError;
+use crate::word::{Segment, Spanned, Word, MAX_NESTING};
+
+/// An element of a word, as far as brace expansion is concerned.
+#[derive(Clone, Copy)]
+enum Atom<'a> {
+    /// An unquoted byte, which may be brace syntax.
+    Byte(u8),
+    /// A segment other than an unquoted literal.
+    Other(&'a Spanned<Segment>),
+}
+
+impl Atom<'_> {
+    fn is(self, c: u8) -> bool {
+        matches!(self, Atom::Byte(b) if b == c)
+    }
+}
+
+/// Brace-expand `word`.  If it contains no brace expressions, this returns just a copy of it.
+/// Fails if that would make more than `max` words, or if brace expressions are nested more than
+/// [`MAX_NESTING`] levels deep.
+pub(crate) fn expand_braces(word: &Word, max: usize) -> Result<Vec<Word>, ExpandError> {
+    let mut atoms = Vec::new();
+    for segment in &word.segments {
+        match &segment.node {
+            Segment::Literal(text) => atoms.extend(text.iter().map(|&c| Atom::Byte(c))),
+            _ => atoms.push(Atom::Other(segment)),
+        }
+    }
+    Ok(expand_atoms(&atoms, max)?.into_iter().map(|atoms| to_word(&atoms, word)).collect())
+}
+
+/// Make a word of `atoms`, which came from `original`.  Literal text in the result can come from
+/// anywhere in the original word, or from a sequence expression, so it's given the original
+/// word's span.
+fn to_word(atoms: &[Atom], original: &Word) -> Word {
+    let literal_segment = |text| Spanned { node: Segment::Literal(text), span: original.span.clone() };
+    let mut segments = Vec::new();
+    let mut literal = Vec::new();
+    for atom in atoms {
+        match *atom {
+            Atom::Byte(c) => literal.push(c),
+            Atom::Other(segment) => {
+                if !literal.is_empty() {
+                    segments.push(literal_segment(core::mem::take(&mut literal)));
+                }
+                segments.push(segment.clone());
+            },
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(literal_segment(literal));
+    }
+    Word { segments, span: original.span.clone(), source: original.source.clone() }
+}
+
+/// Where a `{` that has a matching `}` ends, and the commas directly inside it.
+#[derive(Clone)]
+struct Brace {
+    close: usize,
+    commas: Vec<usize>,
+}
+
+/// Match up the braces in `atoms`, in one pass.  The result has an entry for every atom, which
+/// is `Some` for each `{` that has a matching `}`.
+fn match_braces(atoms: &[Atom]) -> Vec<Option<Brace>> {
+    let mut braces = alloc::vec![None; atoms.len()];
+    // The unmatched `{`s so far, innermost last, with the commas directly inside each.
+    let mut open: Vec<(usize, Vec<usize>)> = Vec::new();
+    for (i, atom) in atoms.iter().enumerate() {
+        if atom.is(b'{') {
+            open.push((i, Vec::new()));
+        } else if atom.is(b'}') {
+            if let Some((start, commas)) = open.pop() {
+                braces[start] = Some(Brace { close: i, commas });
+            }
+        } else if atom.is(b',') {
+            if let Some((_, commas)) = open.last_mut() {
+                commas.push(i);
+            }
+        }
+    }
+    braces
+}
+
+fn expand_atoms<'a>(atoms: &[Atom<'a>], max: usize) -> Result<Vec<Vec<Atom<'a>>>, ExpandError> {
+    let braces = match_braces(atoms);
+    expand_range(atoms, &braces, 0..atoms.len(), max, 0)
+}
+
+/// Expand `atoms[range]`, which `depth` brace expressions contain, going from left to right and
+/// multiplying the words so far by the alternatives of each brace expression in turn.
+fn expand_range<'a>(atoms: &[Atom<'a>], braces: &[Option<Brace>], range: Range<usize>, max: usize, depth: usize) -> Result<Vec<Vec<Atom<'a>>>, ExpandError> {
+    if depth > MAX_NESTING {
+        return Err(ExpandError::TooDeep);
+    }
+    let mut results = alloc::vec![Vec::new()];
+    let mut i = range.start;
+    while i < range.end {
+        // Braces that don't start a valid brace expression (because they're unmatched, or
+        // contain neither a comma nor a sequence) are literal.
+        let brace = match &braces[i] {
+            Some(brace) => brace,
+            None => {
+                push_atom(&mut results, atoms[i]);
+                i += 1;
+                continue;
+            },
+        };
+        let alternatives: Vec<Vec<Atom>> = if !brace.commas.is_empty() {
+            let mut alternatives = Vec::new();
+            let mut start = i + 1;
+            for &end in brace.commas.iter().chain(core::iter::once(&brace.close)) {
+                alternatives.extend(expand_range(atoms, braces, start..end, max, depth + 1)?);
+                if alternatives.len() > max {
+                    return Err(ExpandError::TooManyWords);
+                }
+                start = end + 1;
+            }
+            alternatives
+        } else {
+            match sequence(&atoms[i + 1..brace.close], max) {
+                Some(terms) => terms.into_iter().map(|term| term.into_iter().map(Atom::Byte).collect()).collect(),
+                None => {
+                    push_atom(&mut results, atoms[i]);
+                    i += 1;
+                    continue;
+                },
+            }
+        };
+        if results.len().saturating_mul(alternatives.len()) > max {
+            return Err(ExpandError::TooManyWords);
+        }
+        let mut product = Vec::with_capacity(results.len() * alternatives.len());
+        for result in &results {
+            for alternative in &alternatives {
+                let mut word = result.clone();
+                word.extend_from_slice(alternative);
+                product.push(word);
+            }
+        }
+        results = product;
+        i = brace.close + 1;
+    }
+    Ok(results)
+}
+
+/// Append `atom` to each of `words`.
+fn push_atom<'a>(words: &mut Vec<Vec<Atom<'a>>>, atom: Atom<'a>) {
+    for word in words {
+        word.push(atom);
+    }
+}
+
+/// Parse and generate a sequence expression like `1..10`, `01..10..2` or `a..z`.  Returns `None`
+/// if `atoms` isn't one.  Stops after `max + 1` terms, so that the caller can tell the sequence
+/// was too long without generating all of it.
+fn sequence(atoms: &[Atom], max: usize) -> Option<Vec<Vec<u8>>> {
+    // Stopping at the first brace means that nested braces, which can't be part of a sequence,
+    // are never looked at twice.
+    let mut text = Vec::new();
+    for atom in atoms {
+        match *atom {
+            Atom::Byte(b'{') | Atom::Byte(b'}') | Atom::Other(_) => { return None; },
+            Atom::Byte(c) => text.push(c),
+        }
+    }
+    let mut parts = Vec::new();
+    let mut rest = &text[..];
+    while let Some(pos) = rest.windows(2).position(|w| w == b"..") {
+        parts.push(&rest[..pos]);
+        rest = &rest[pos + 2..];
+    }
+    parts.push(rest);
+    let (start, end, incr) = match parts[..] {
+        [start, end] => (start, end, 1),
+        [start, end, incr] => (start, end, parse_number(incr)?),
+        _ => { return None; },
+    };
+    // The sign of the increment is ignored, and 0 means 1.
+    let incr = incr.checked_abs()?.max(1);
+
+    if let (Some(start_n), Some(end_n)) = (parse_number(start), parse_number(end)) {
+        // If either end is zero-padded, pad all the terms to the same width.
+        let padded_width = |s: &[u8]| {
+            let padded = (s.len() > 1 && s[0] == b'0') || (s.len() > 2 && s[0] == b'-' && s[1] == b'0');
+            if padded { s.len() } else { 0 }
+        };
+        let width = padded_width(start).max(padded_width(end));
+        return Some(range(start_n, end_n, incr).take(max + 1).map(|n| {
+            let digits = i128::from(n).abs().to_string();
+            let mut term = Vec::with_capacity(width.max(digits.len() + 1));
+            if n < 0 {
+                term.push(b'-');
+            }
+            let len = width.saturating_sub(digits.len()).max(term.len());
+            term.resize(len, b'0');
+            term.extend_from_slice(digits.as_bytes());
+            term
+        }).collect());
+    }
+    match (start, end) {
+        (&[start], &[end]) if !start.is_ascii_digit() && !end.is_ascii_digit() => {
+            Some(range(i64::from(start), i64::from(end), incr).take(max + 1).map(|c| alloc::vec![c as u8]).collect())
+        },
+        _ => None,
+    }
+}
+
+/// The numbers from `start` to `end` inclusive, counting up or down by `incr`.
+fn range(start: i64, end: i64, incr: i64) -> impl Iterator<Item = i64> {
+    let down = end < start;
+    let mut next = Some(start);
+    core::iter::from_fn(move || {
+        let n = next?;
+        if (down && n < end) || (!down && n > end) {
+            return None;
+        }
+        next = if down { n.checked_sub(incr) } else { n.checked_add(incr) };
+        Some(n)
+    })
+}
+
+/// Parse an optionally signed decimal integer.
+fn parse_number(text: &[u8]) -> Option<i64> {
+    let digits = match text.first() {
+        Some(b'-') | Some(b'+') => &text[1..],
+        _ => text,
+    };
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    core::str::from_utf8(text).ok()?.parse().ok()
+}
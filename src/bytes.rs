@@ -26,17 +26,22 @@
 extern crate alloc;
 use alloc::vec::Vec;
 use alloc::borrow::Cow;
-#[cfg(test)]
 use alloc::vec;
 #[cfg(test)]
 use alloc::borrow::ToOwned;
 #[cfg(all(doc, not(doctest)))]
 use crate::{self as shlex, quoting_warning};
 
-use super::QuoteError;
+use super::{Arg, ArgError, Dialect, OptionPolicy, QuoteError, TranslateError};
+use crate::word::{is_name_char, is_name_start, ParseError};
 
 /// An iterator that takes an input byte string and splits it into the words using the same syntax as
 /// the POSIX shell.
+///
+/// Only quoting is interpreted.  Other shell syntax, including command substitutions like
+/// `$(...)`, is passed through as literal text and split at whitespace like any other text.  To
+/// find and handle command substitutions, use [`Expander`](crate::expand::Expander) with
+/// [`Expander::command_substitution`](crate::expand::Expander::command_substitution).
 pub struct Shlex<'a> {
     in_iter: core::slice::Iter<'a, u8>,
     /// The number of newlines read so far, plus one.
@@ -46,6 +51,30 @@ pub struct Shlex<'a> {
     /// happens, Shlex just throws out the last token, ends the iteration, and sets 'had_error' to
     /// true; best to check it after you're done iterating.
     pub had_error: bool,
+    dialect: Dialect,
+    /// The first byte read so far which, in this dialect, would have been shell syntax (such as a
+    /// variable expansion or a glob) rather than literal text.  Used by [`translate`].
+    unquoted_syntax: Option<u8>,
+    /// The indices of the bytes in the current word that were not quoted or escaped.
+    unquoted: Vec<usize>,
+    /// Whether the current word contained any quotes.
+    was_quoted: bool,
+}
+
+/// A word returned by [`Shlex::next_with_quoting`], along with which of its bytes were quoted.
+///
+/// This is what's needed to treat the word the way a shell would after splitting it: for example,
+/// only unquoted `*`s are globs, and only an unquoted `~` at the start is a tilde expansion.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct QuotedWord {
+    /// The word itself, the same as iterating over [`Shlex`] would return.
+    pub bytes: Vec<u8>,
+    /// Whether each byte of `bytes` was quoted (by any kind of quotes) or escaped.
+    pub quoted: Vec<bool>,
+    /// Whether the word contained any quoting at all, even if it didn't quote anything.  This
+    /// distinguishes `''`, which a shell passes on as an empty argument, from an empty word that
+    /// would vanish (such as a lone backslash-newline).
+    pub was_quoted: bool,
 }
 
 impl<'a> Shlex<'a> {
@@ -54,6 +83,55 @@ impl<'a> Shlex<'a> {
             in_iter: in_bytes.iter(),
             line_no: 1,
             had_error: false,
+            dialect: Dialect::Posix,
+            unquoted_syntax: None,
+            unquoted: Vec::new(),
+            was_quoted: false,
+        }
+    }
+
+    /// Like [`Iterator::next`], but also returns which bytes of the word were quoted.
+    ///
+    /// ```rust
+    /// use shlex::bytes::Shlex;
+    ///
+    /// let mut shl = Shlex::new(br#"a"*"* '' b"#);
+    /// let word = shl.next_with_quoting().unwrap();
+    /// assert_eq!(word.bytes, b"a**");
+    /// assert_eq!(word.quoted, [false, true, false]);
+    /// assert!(shl.next_with_quoting().unwrap().was_quoted);
+    /// assert!(!shl.next_with_quoting().unwrap().was_quoted);
+    /// ```
+    pub fn next_with_quoting(&mut self) -> Option<QuotedWord> {
+        let bytes = self.next()?;
+        let mut quoted = vec![true; bytes.len()];
+        for &i in &self.unquoted {
+            quoted[i] = false;
+        }
+        let was_quoted = self.was_quoted || quoted.contains(&true);
+        Some(QuotedWord { bytes, quoted, was_quoted })
+    }
+
+    /// Set which shell [`Dialect`]'s quoting rules to split with.  The default is
+    /// [`Dialect::Posix`].
+    ///
+    /// In every dialect, words are separated by spaces, tabs, and newlines, and `#` at the start of
+    /// a word begins a comment.  Other syntax that isn't quoting (such as `$` or `;`) is left
+    /// as-is, as it is for [`Dialect::Posix`].
+    #[inline]
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    fn parse_word_dialect(&mut self, ch: u8) -> Option<Vec<u8>> {
+        match self.dialect {
+            Dialect::Posix | Dialect::Bash | Dialect::Python => self.parse_word(ch),
+            Dialect::Csh => self.parse_word_csh(ch),
+            Dialect::Rc => self.parse_word_rc(ch),
+            Dialect::Nushell => self.parse_word_nushell(ch),
+            Dialect::Fish => self.parse_word_fish(ch),
+            Dialect::PowerShell => self.parse_word_powershell(ch),
         }
     }
 
@@ -69,14 +147,31 @@ impl<'a> Shlex<'a> {
                     self.had_error = true;
                     return None;
                 },
+                // Bash's ANSI-C quoting, `$'...'`, and locale-specific translation, `$"..."`
+                // (which we treat as regular double quotes).
+                '$' if self.dialect == Dialect::Bash && matches!(self.peek_char(), Some(b'\'') | Some(b'"')) => {
+                    let res = if self.next_char() == Some(b'"') {
+                        self.parse_double(&mut result)
+                    } else {
+                        self.parse_ansi_c(&mut result)
+                    };
+                    if let Err(()) = res {
+                        self.had_error = true;
+                        return None;
+                    }
+                },
                 '\\' => if let Some(ch2) = self.next_char() {
-                    if ch2 != b'\n' { result.push(ch2); }
+                    // Python keeps escaped newlines rather than removing them.
+                    if ch2 != b'\n' || self.dialect == Dialect::Python { result.push(ch2); }
                 } else {
                     self.had_error = true;
                     return None;
                 },
                 ' ' | '\t' | '\n' => { break; },
-                _ => { result.push(ch); },
+                '\r' if self.dialect == Dialect::Python => { break; },
+                _ => {
+                    self.push_unquoted(ch, &mut result);
+                },
             }
             if let Some(ch2) = self.next_char() { ch = ch2; } else { break; }
         }
@@ -84,12 +179,18 @@ impl<'a> Shlex<'a> {
     }
 
     fn parse_double(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        self.was_quoted = true;
         loop {
             if let Some(ch2) = self.next_char() {
                 match ch2 as char {
                     '\\' => {
                         if let Some(ch3) = self.next_char() {
                             match ch3 as char {
+                                // Python only recognizes \" and \\.
+                                '$' | '`' | '\n' if self.dialect == Dialect::Python => {
+                                    result.push(b'\\');
+                                    result.push(ch3);
+                                },
                                 // \$ => $
                                 '$' | '`' | '"' | '\\' => { result.push(ch3); },
                                 // \<newline> => nothing
@@ -102,7 +203,10 @@ impl<'a> Shlex<'a> {
                         }
                     },
                     '"' => { return Ok(()); },
-                    _ => { result.push(ch2); },
+                    _ => {
+                        self.note_double_quoted(ch2);
+                        result.push(ch2);
+                    },
                 }
             } else {
                 return Err(());
@@ -111,6 +215,7 @@ impl<'a> Shlex<'a> {
     }
 
     fn parse_single(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        self.was_quoted = true;
         loop {
             if let Some(ch2) = self.next_char() {
                 match ch2 as char {
@@ -123,31 +228,537 @@ impl<'a> Shlex<'a> {
         }
     }
 
+    /// Parse the inside of Bash's `$'...'`, in which backslash escapes are interpreted as in C.
+    fn parse_ansi_c(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        self.was_quoted = true;
+        let (decoded, len) = decode_ansi_c(self.in_iter.as_slice()).map_err(|_| ())?;
+        for _ in 0..len {
+            self.next_char();
+        }
+        result.extend_from_slice(&decoded);
+        Ok(())
+    }
+
+    /// csh: Backslashes escape anything outside of quotes, but inside quotes they only escape `!`
+    /// and newline.  An unescaped newline inside quotes is an error.
+    fn parse_word_csh(&mut self, mut ch: u8) -> Option<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        loop {
+            match ch {
+                b'"' | b'\'' => if let Err(()) = self.parse_quoted_csh(ch, &mut result) {
+                    self.had_error = true;
+                    return None;
+                },
+                b'\\' => match self.next_char() {
+                    // An escaped newline outside of quotes separates words.
+                    Some(b'\n') => { break; },
+                    Some(ch2) => { result.push(ch2); },
+                    None => {
+                        self.had_error = true;
+                        return None;
+                    }
+                },
+                b' ' | b'\t' | b'\n' => { break; },
+                _ => {
+                    self.push_unquoted(ch, &mut result);
+                },
+            }
+            if let Some(ch2) = self.next_char() { ch = ch2; } else { break; }
+        }
+        Some(result)
+    }
+
+    fn parse_quoted_csh(&mut self, quote: u8, result: &mut Vec<u8>) -> Result<(), ()> {
+        self.was_quoted = true;
+        loop {
+            match self.next_char() {
+                Some(b'\\') => match self.peek_char() {
+                    Some(ch3 @ b'!') | Some(ch3 @ b'\n') => {
+                        self.next_char();
+                        result.push(ch3);
+                    },
+                    _ => { result.push(b'\\'); },
+                },
+                Some(b'\n') | None => { return Err(()); },
+                Some(ch2) if ch2 == quote => { return Ok(()); },
+                Some(ch2) => {
+                    if quote == b'"' {
+                        self.note_double_quoted(ch2);
+                    }
+                    result.push(ch2);
+                },
+            }
+        }
+    }
+
+    /// rc: Only single quotes, in which `''` stands for one quote.  Backslashes are literal,
+    /// except that backslash-newline separates words.  And `#` starts a comment even in the middle
+    /// of a word.
+    fn parse_word_rc(&mut self, mut ch: u8) -> Option<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        loop {
+            match ch {
+                b'\'' => {
+                    self.was_quoted = true;
+                    loop {
+                        match self.next_char() {
+                            Some(b'\'') => if self.peek_char() == Some(b'\'') {
+                                self.next_char();
+                                result.push(b'\'');
+                            } else {
+                                break;
+                            },
+                            Some(ch2) => { result.push(ch2); },
+                            None => {
+                                self.had_error = true;
+                                return None;
+                            }
+                        }
+                    }
+                },
+                b'\\' if self.peek_char() == Some(b'\n') => {
+                    self.next_char();
+                    break;
+                },
+                b'#' => {
+                    self.skip_comment();
+                    break;
+                },
+                b' ' | b'\t' | b'\n' => { break; },
+                _ => {
+                    self.push_unquoted(ch, &mut result);
+                },
+            }
+            if let Some(ch2) = self.next_char() { ch = ch2; } else { break; }
+        }
+        Some(result)
+    }
+
+    /// Nushell: Quotes are only recognized at the start of a word, and the quoted string must make
+    /// up the whole word.  Backslashes are only special in double quotes.
+    fn parse_word_nushell(&mut self, ch: u8) -> Option<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        let quoted = match ch {
+            b'\'' | b'`' => self.parse_until_nushell(ch, &mut result),
+            b'"' => self.parse_double_nushell(&mut result),
+            b'r' if self.peek_char() == Some(b'#') => self.parse_raw_nushell(&mut result),
+            _ => {
+                let mut ch = ch;
+                loop {
+                    match ch {
+                        b' ' | b'\t' | b'\n' => { break; },
+                        _ => {
+                            self.push_unquoted(ch, &mut result);
+                        },
+                    }
+                    if let Some(ch2) = self.next_char() { ch = ch2; } else { break; }
+                }
+                return Some(result);
+            },
+        };
+        self.was_quoted = true;
+        // The quoted string must be followed by whitespace or the end of input.
+        let quoted = quoted.and_then(|()| match self.next_char() {
+            None | Some(b' ') | Some(b'\t') | Some(b'\n') => Ok(()),
+            Some(_) => Err(()),
+        });
+        if let Err(()) = quoted {
+            self.had_error = true;
+            return None;
+        }
+        Some(result)
+    }
+
+    fn parse_until_nushell(&mut self, quote: u8, result: &mut Vec<u8>) -> Result<(), ()> {
+        loop {
+            match self.next_char() {
+                Some(ch2) if ch2 == quote => { return Ok(()); },
+                Some(ch2) => { result.push(ch2); },
+                None => { return Err(()); },
+            }
+        }
+    }
+
+    fn parse_double_nushell(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        loop {
+            match self.next_char().ok_or(())? {
+                b'"' => { return Ok(()); },
+                b'\\' => match self.next_char().ok_or(())? {
+                    c @ b'"' | c @ b'\'' | c @ b'\\' | c @ b'/' => { result.push(c); },
+                    b'b' => { result.push(b'\x08'); },
+                    b'f' => { result.push(b'\x0c'); },
+                    b'n' => { result.push(b'\n'); },
+                    b'r' => { result.push(b'\r'); },
+                    b't' => { result.push(b'\t'); },
+                    b'u' => {
+                        if self.next_char() != Some(b'{') { return Err(()); }
+                        let mut code: u32 = 0;
+                        let mut digits = 0;
+                        loop {
+                            match self.next_char().ok_or(())? {
+                                b'}' if digits > 0 => { break; },
+                                c if c.is_ascii_hexdigit() && digits < 6 => {
+                                    code = code * 16 + (c as char).to_digit(16).unwrap();
+                                    digits += 1;
+                                },
+                                _ => { return Err(()); },
+                            }
+                        }
+                        let c = core::char::from_u32(code).ok_or(())?;
+                        result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    },
+                    _ => { return Err(()); },
+                },
+                ch2 => { result.push(ch2); },
+            }
+        }
+    }
+
+    fn parse_raw_nushell(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        let mut hashes = 0;
+        loop {
+            match self.next_char() {
+                Some(b'#') => { hashes += 1; },
+                Some(b'\'') => { break; },
+                _ => { return Err(()); },
+            }
+        }
+        loop {
+            match self.next_char().ok_or(())? {
+                b'\'' if self.in_iter.as_slice().iter().take(hashes).filter(|&&c| c == b'#').count() == hashes => {
+                    for _ in 0..hashes { self.next_char(); }
+                    return Ok(());
+                },
+                ch2 => { result.push(ch2); },
+            }
+        }
+    }
+
+    /// fish: Backslash escapes work outside of quotes, including C-style escapes like `\n` and
+    /// `\x41`.  In single quotes, only `\'` and `\\` are escapes; in double quotes, only `\"`,
+    /// `\$`, `\\`, and backslash-newline.
+    ///
+    /// Numeric escapes that would produce bytes outside of ASCII (which fish would encode
+    /// specially) are treated as errors.
+    fn parse_word_fish(&mut self, mut ch: u8) -> Option<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        loop {
+            let res = match ch {
+                b'\'' => self.parse_single_fish(&mut result),
+                b'"' => self.parse_double_fish(&mut result),
+                b'\\' => match self.next_char() {
+                    Some(b'\n') => Ok(()),
+                    Some(ch2) => self.parse_escape_fish(ch2, &mut result),
+                    None => Err(()),
+                },
+                b' ' | b'\t' | b'\n' => { break; },
+                _ => {
+                    self.push_unquoted(ch, &mut result);
+                    Ok(())
+                },
+            };
+            if let Err(()) = res {
+                self.had_error = true;
+                return None;
+            }
+            if let Some(ch2) = self.next_char() { ch = ch2; } else { break; }
+        }
+        Some(result)
+    }
+
+    fn parse_single_fish(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        self.was_quoted = true;
+        loop {
+            match self.next_char().ok_or(())? {
+                b'\'' => { return Ok(()); },
+                b'\\' => match self.peek_char() {
+                    Some(ch3 @ b'\'') | Some(ch3 @ b'\\') => {
+                        self.next_char();
+                        result.push(ch3);
+                    },
+                    _ => { result.push(b'\\'); },
+                },
+                ch2 => { result.push(ch2); },
+            }
+        }
+    }
+
+    fn parse_double_fish(&mut self, result: &mut Vec<u8>) -> Result<(), ()> {
+        self.was_quoted = true;
+        loop {
+            match self.next_char().ok_or(())? {
+                b'"' => { return Ok(()); },
+                b'\\' => match self.peek_char() {
+                    Some(ch3 @ b'"') | Some(ch3 @ b'$') | Some(ch3 @ b'\\') => {
+                        self.next_char();
+                        result.push(ch3);
+                    },
+                    Some(b'\n') => { self.next_char(); },
+                    _ => { result.push(b'\\'); },
+                },
+                ch2 => {
+                    self.note_double_quoted(ch2);
+                    result.push(ch2);
+                },
+            }
+        }
+    }
+
+    /// Parse the rest of a fish escape sequence outside of quotes, given the character after the
+    /// backslash.
+    fn parse_escape_fish(&mut self, ch: u8, result: &mut Vec<u8>) -> Result<(), ()> {
+        let (radix, max_digits) = match ch {
+            b'a' => { result.push(b'\x07'); return Ok(()); },
+            b'b' => { result.push(b'\x08'); return Ok(()); },
+            b'e' => { result.push(b'\x1b'); return Ok(()); },
+            b'f' => { result.push(b'\x0c'); return Ok(()); },
+            b'n' => { result.push(b'\n'); return Ok(()); },
+            b'r' => { result.push(b'\r'); return Ok(()); },
+            b't' => { result.push(b'\t'); return Ok(()); },
+            b'v' => { result.push(b'\x0b'); return Ok(()); },
+            b'c' => {
+                // Masking a byte of a multibyte character would produce invalid UTF-8.
+                let ch2 = self.next_char().filter(u8::is_ascii).ok_or(())?;
+                result.push(ch2 & 0x1f);
+                return Ok(());
+            },
+            b'x' | b'X' => (16, 2),
+            b'u' => (16, 4),
+            b'U' => (16, 8),
+            b'0'..=b'7' => (8, 3),
+            _ => { result.push(ch); return Ok(()); },
+        };
+        let mut code: u32 = 0;
+        let mut digits = 0;
+        if radix == 8 {
+            // The first digit is part of the number.
+            code = u32::from(ch - b'0');
+            digits = 1;
+        }
+        while digits < max_digits {
+            match self.peek_char().and_then(|c| (c as char).to_digit(radix)) {
+                Some(digit) => {
+                    self.next_char();
+                    code = code.checked_mul(radix).ok_or(())? + digit;
+                    digits += 1;
+                },
+                None => { break; },
+            }
+        }
+        if digits == 0 || (matches!(ch, b'x' | b'X' | b'0'..=b'7') && code >= 0x80) {
+            return Err(());
+        }
+        let c = core::char::from_u32(code).ok_or(())?;
+        result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        Ok(())
+    }
+
+    /// PowerShell: Backtick is the escape character, both outside of quotes and in double quotes.
+    /// Inside quotes, a doubled quote stands for one quote.  PowerShell also treats the Unicode
+    /// 'smart quotes' as equivalent to the ASCII ones, so this does too.
+    fn parse_word_powershell(&mut self, mut ch: u8) -> Option<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        loop {
+            let res = match (ch, self.powershell_quote_kind(ch)) {
+                (_, Some(_)) => self.parse_quoted_powershell(ch, &mut result),
+                (b'`', _) => match self.next_char() {
+                    Some(b'\n') => { break; },
+                    Some(ch2) => self.parse_escape_powershell(ch2, &mut result),
+                    None => Err(()),
+                },
+                (b' ', _) | (b'\t', _) | (b'\n', _) => { break; },
+                _ => {
+                    self.push_unquoted(ch, &mut result);
+                    Ok(())
+                },
+            };
+            if let Err(()) = res {
+                self.had_error = true;
+                return None;
+            }
+            if let Some(ch2) = self.next_char() { ch = ch2; } else { break; }
+        }
+        Some(result)
+    }
+
+    /// If `ch` (which was just read) is the start of a quote character, return `'` or `"` as
+    /// appropriate.
+    fn powershell_quote_kind(&self, ch: u8) -> Option<u8> {
+        powershell_quote_kind(ch, self.in_iter.as_slice())
+    }
+
+    /// Like `powershell_quote_kind`, but for the next character, which hasn't been read yet.
+    fn peek_powershell_quote_kind(&self) -> Option<u8> {
+        match self.in_iter.as_slice() {
+            [ch, rest @ ..] => powershell_quote_kind(*ch, rest),
+            [] => None,
+        }
+    }
+
+    /// Having read the first byte of a quote character, read the rest of it.
+    fn skip_powershell_quote(&mut self, ch: u8) {
+        if ch == 0xe2 {
+            self.next_char();
+            self.next_char();
+        }
+    }
+
+    /// Having read the first byte of a quote character, read the rest of it and append the whole
+    /// character to `result`.
+    fn push_powershell_quote(&mut self, ch: u8, result: &mut Vec<u8>) {
+        result.push(ch);
+        if ch == 0xe2 {
+            result.push(self.next_char().unwrap());
+            result.push(self.next_char().unwrap());
+        }
+    }
+
+    /// Parse a quoted string, given its opening quote character `open`, up to and including the
+    /// closing quote.
+    fn parse_quoted_powershell(&mut self, open: u8, result: &mut Vec<u8>) -> Result<(), ()> {
+        let kind = self.powershell_quote_kind(open);
+        let double = kind == Some(b'"');
+        self.was_quoted = true;
+        self.skip_powershell_quote(open);
+        loop {
+            let ch2 = self.next_char().ok_or(())?;
+            if self.powershell_quote_kind(ch2) == kind {
+                self.skip_powershell_quote(ch2);
+                if self.peek_powershell_quote_kind() == kind {
+                    let ch3 = self.next_char().unwrap();
+                    self.push_powershell_quote(ch3, result);
+                } else {
+                    return Ok(());
+                }
+            } else if double && ch2 == b'`' {
+                let ch3 = self.next_char().ok_or(())?;
+                self.parse_escape_powershell(ch3, result)?;
+            } else {
+                if double {
+                    self.note_double_quoted(ch2);
+                }
+                result.push(ch2);
+            }
+        }
+    }
+
+    /// Parse the rest of a PowerShell escape sequence, given the character after the backtick.
+    fn parse_escape_powershell(&mut self, ch: u8, result: &mut Vec<u8>) -> Result<(), ()> {
+        match ch {
+            b'0' => result.push(b'\0'),
+            b'a' => result.push(b'\x07'),
+            b'b' => result.push(b'\x08'),
+            b'e' => result.push(b'\x1b'),
+            b'f' => result.push(b'\x0c'),
+            b'n' => result.push(b'\n'),
+            b'r' => result.push(b'\r'),
+            b't' => result.push(b'\t'),
+            b'v' => result.push(b'\x0b'),
+            b'u' => {
+                if self.next_char() != Some(b'{') { return Err(()); }
+                let mut code: u32 = 0;
+                let mut digits = 0;
+                loop {
+                    match self.next_char().ok_or(())? {
+                        b'}' if digits > 0 => { break; },
+                        c if c.is_ascii_hexdigit() && digits < 6 => {
+                            code = code * 16 + (c as char).to_digit(16).unwrap();
+                            digits += 1;
+                        },
+                        _ => { return Err(()); },
+                    }
+                }
+                let c = core::char::from_u32(code).ok_or(())?;
+                result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            },
+            _ => result.push(ch),
+        }
+        Ok(())
+    }
+
+    /// Append `ch`, which is unquoted, to the word `result`.
+    fn push_unquoted(&mut self, ch: u8, result: &mut Vec<u8>) {
+        self.note_unquoted(ch, result.is_empty());
+        self.unquoted.push(result.len());
+        result.push(ch);
+    }
+
+    /// Record `ch` if it's shell syntax when it appears unquoted.  `at_start` is whether it's at
+    /// the start of a word (as far as we know).
+    fn note_unquoted(&mut self, ch: u8, at_start: bool) {
+        use Dialect::*;
+        let is_syntax = match ch {
+            b'$' | b'|' | b'&' | b';' | b'<' | b'>' | b'(' | b')' => true,
+            b'*' | b'?' | b'[' => self.dialect != PowerShell,
+            b'`' => self.dialect != Nushell,
+            b'~' => at_start && self.dialect != Rc && self.dialect != PowerShell,
+            // Brace expansion in Bash, zsh, csh and fish, and syntax elsewhere.
+            b'{' | b'}' => true,
+            b'^' | b'=' => self.dialect == Rc,
+            b',' => self.dialect == PowerShell,
+            b'@' => at_start && self.dialect == PowerShell,
+            _ => false,
+        };
+        if is_syntax && self.unquoted_syntax.is_none() {
+            self.unquoted_syntax = Some(ch);
+        }
+    }
+
+    /// Record `ch` if it's shell syntax when it appears in double quotes.
+    fn note_double_quoted(&mut self, ch: u8) {
+        let is_syntax = match ch {
+            b'$' => true,
+            b'`' => self.dialect != Dialect::PowerShell,
+            _ => false,
+        };
+        if is_syntax && self.unquoted_syntax.is_none() {
+            self.unquoted_syntax = Some(ch);
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(ch2) = self.next_char() {
+            if ch2 as char == '\n' { break; }
+        }
+    }
+
     fn next_char(&mut self) -> Option<u8> {
         let res = self.in_iter.next().copied();
         if res == Some(b'\n') { self.line_no += 1; }
         res
     }
+
+    fn peek_char(&self) -> Option<u8> {
+        self.in_iter.as_slice().first().copied()
+    }
 }
 
 impl Iterator for Shlex<'_> {
     type Item = Vec<u8>;
     fn next(&mut self) -> Option<Self::Item> {
+        self.unquoted.clear();
+        self.was_quoted = false;
         if let Some(mut ch) = self.next_char() {
             // skip initial whitespace
             loop {
                 match ch as char {
                     ' ' | '\t' | '\n' => {},
-                    '#' => {
-                        while let Some(ch2) = self.next_char() {
-                            if ch2 as char == '\n' { break; }
-                        }
+                    '\r' if self.dialect == Dialect::Python => {},
+                    '#' if self.dialect != Dialect::Python => { self.skip_comment(); },
+                    // In most dialects other than POSIX, escaped newlines outside quotes are
+                    // whitespace.  (In POSIX, they're removed, which makes no difference here.)
+                    '\\' if self.peek_char() == Some(b'\n') &&
+                        matches!(self.dialect, Dialect::Csh | Dialect::Rc | Dialect::Fish) => {
+                        self.next_char();
+                    },
+                    '`' if self.peek_char() == Some(b'\n') && self.dialect == Dialect::PowerShell => {
+                        self.next_char();
                     },
                     _ => { break; }
                 }
                 if let Some(ch2) = self.next_char() { ch = ch2; } else { return None; }
             }
-            self.parse_word(ch)
+            self.parse_word_dialect(ch)
         } else { // no initial character
             None
         }
@@ -155,6 +766,113 @@ impl Iterator for Shlex<'_> {
 
 }
 
+/// If `ch`, followed by `rest`, starts with a PowerShell quote character, return `'` or `"` as
+/// appropriate.
+fn powershell_quote_kind(ch: u8, rest: &[u8]) -> Option<u8> {
+    match (ch, rest) {
+        (b'\'', _) => Some(b'\''),
+        (b'"', _) => Some(b'"'),
+        // U+2018 through U+201B
+        (0xe2, [0x80, 0x98..=0x9b, ..]) => Some(b'\''),
+        // U+201C through U+201E
+        (0xe2, [0x80, 0x9c..=0x9e, ..]) => Some(b'"'),
+        _ => None,
+    }
+}
+
+/// Decode the inside of Bash's `$'...'`, starting after the opening quote, in which backslash
+/// escapes are interpreted as in C.  Returns the decoded bytes and how much of `input` was used,
+/// including the closing quote.
+///
+/// As in Bash, a nul byte (from an escape like `\0`) ends the string; everything after it
+/// until the closing quote is ignored.
+pub(crate) fn decode_ansi_c(input: &[u8]) -> Result<(Vec<u8>, usize), ParseError> {
+    let mut result = Vec::new();
+    let mut truncated = false;
+    let mut i = 0;
+    loop {
+        let mut ch = *input.get(i).ok_or(ParseError::Unterminated)?;
+        i += 1;
+        if ch == b'\'' {
+            return Ok((result, i));
+        }
+        if ch == b'\\' {
+            let ch2 = *input.get(i).ok_or(ParseError::Unterminated)?;
+            i += 1;
+            let (radix, max_digits) = match ch2 {
+                b'0'..=b'7' => (8, 3),
+                b'x' => (16, 2),
+                b'u' => (16, 4),
+                b'U' => (16, 8),
+                _ => (0, 0),
+            };
+            if radix != 0 {
+                let mut code: u32 = 0;
+                let mut digits = 0;
+                if radix == 8 {
+                    code = u32::from(ch2 - b'0');
+                    digits = 1;
+                }
+                while digits < max_digits {
+                    match input.get(i).and_then(|&c| (c as char).to_digit(radix)) {
+                        Some(digit) => {
+                            i += 1;
+                            code = code * radix + digit;
+                            digits += 1;
+                        },
+                        None => { break; },
+                    }
+                }
+                if digits == 0 {
+                    // No digits; the escape is taken literally.
+                    if !truncated {
+                        result.push(b'\\');
+                        result.push(ch2);
+                    }
+                    continue;
+                }
+                if ch2 == b'u' || ch2 == b'U' {
+                    let c = core::char::from_u32(code).ok_or(ParseError::InvalidEscape)?;
+                    if !truncated {
+                        result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                    continue;
+                }
+                ch = code as u8;
+            } else {
+                ch = match ch2 {
+                    b'a' => b'\x07',
+                    b'b' => b'\x08',
+                    b'e' | b'E' => b'\x1b',
+                    b'f' => b'\x0c',
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'v' => b'\x0b',
+                    b'\\' | b'\'' | b'"' | b'?' => ch2,
+                    b'c' if input.get(i).map_or(false, |&c| c != b'\'') => {
+                        i += 1;
+                        input[i - 1] & 0x1f
+                    },
+                    _ => {
+                        if !truncated {
+                            result.push(b'\\');
+                            result.push(ch2);
+                        }
+                        continue;
+                    },
+                };
+            }
+        }
+        if ch == b'\0' {
+            truncated = true;
+        }
+        if !truncated {
+            result.push(ch);
+        }
+    }
+}
+
 /// Convenience function that consumes the whole byte string at once.  Returns None if the input was
 /// erroneous.
 pub fn split(in_bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
@@ -163,6 +881,14 @@ pub fn split(in_bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
     if shl.had_error { None } else { Some(res) }
 }
 
+/// Like [`split`], but also returns which bytes of each word were quoted.  See
+/// [`Shlex::next_with_quoting`].
+pub fn split_with_quoting(in_bytes: &[u8]) -> Option<Vec<QuotedWord>> {
+    let mut shl = Shlex::new(in_bytes);
+    let res = core::iter::from_fn(|| shl.next_with_quoting()).collect();
+    if shl.had_error { None } else { Some(res) }
+}
+
 /// A more configurable interface to quote strings.  If you only want the default settings you can
 /// use the convenience functions [`try_quote`] and [`try_join`].
 ///
@@ -170,6 +896,8 @@ pub fn split(in_bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
 #[derive(Default, Debug, Clone)]
 pub struct Quoter {
     allow_nul: bool,
+    forbid_raw_control: bool,
+    dialect: Dialect,
     // TODO: more options
 }
 
@@ -188,6 +916,25 @@ impl Quoter {
         self
     }
 
+    /// Set whether to return [`QuoteError::Control`] rather than include [control
+    /// characters](quoting_warning#control-characters-interactive-contexts-only) as-is in the
+    /// output.  By default they are included as-is.
+    ///
+    /// This only matters for dialects where control characters can't be represented using escape
+    /// sequences; see [`QuoteError::Control`].
+    #[inline]
+    pub fn forbid_raw_control(mut self, forbid: bool) -> Self {
+        self.forbid_raw_control = forbid;
+        self
+    }
+
+    /// Set which shell [`Dialect`] to quote for.  The default is [`Dialect::Posix`].
+    #[inline]
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
     /// Convenience function that consumes an iterable of words and turns it into a single byte string,
     /// quoting words when necessary. Consecutive words will be separated by a single space.
     pub fn join<'a, I: IntoIterator<Item = &'a [u8]>>(&self, words: I) -> Result<Vec<u8>, QuoteError> {
@@ -197,13 +944,20 @@ impl Quoter {
             .join(&b' '))
     }
 
+    /// Like [`Quoter::join`], but first applies `policy` to the untrusted arguments, as
+    /// [`guard_args`] does.
+    pub fn join_args<'a, I: IntoIterator<Item = Arg<&'a [u8]>>>(&self, args: I, policy: OptionPolicy) -> Result<Vec<u8>, ArgError> {
+        let args = guard_args(args, policy)?;
+        Ok(self.join(args.iter().map(|arg| &arg[..]))?)
+    }
+
     /// Given a single word, return a byte string suitable to encode it as a shell argument.
     ///
     /// If given valid UTF-8, this will never produce invalid UTF-8. This is because it only
     /// ever inserts valid ASCII characters before or after existing ASCII characters (or
     /// returns two single quotes if the input was an empty string). It will never modify a
     /// multibyte UTF-8 character.
-    pub fn quote<'a>(&self, mut in_bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, QuoteError> {
+    pub fn quote<'a>(&self, in_bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, QuoteError> {
         if in_bytes.is_empty() {
             // Empty string.  Special case that isn't meaningful as only part of a word.
             return Ok(b"''"[..].into());
@@ -211,26 +965,100 @@ impl Quoter {
         if !self.allow_nul && in_bytes.contains(&b'\0') {
             return Err(QuoteError::Nul);
         }
-        let mut out: Vec<u8> = Vec::new();
-        while !in_bytes.is_empty() {
-            // Pick a quoting strategy for some prefix of the input.  Normally this will cover the
-            // entire input, but in some case we might need to divide the input into multiple chunks
-            // that are quoted differently.
-            let (cur_len, strategy) = quoting_strategy(in_bytes);
-            if cur_len == in_bytes.len() && strategy == QuotingStrategy::Unquoted && out.is_empty() {
-                // Entire string can be represented unquoted.  Reuse the allocation.
-                return Ok(in_bytes.into());
-            }
-            let (cur_chunk, rest) = in_bytes.split_at(cur_len);
-            assert!(rest.len() < in_bytes.len()); // no infinite loop
-            in_bytes = rest;
-            append_quoted_chunk(&mut out, cur_chunk, strategy);
+        if self.forbid_raw_control && !has_control_escapes(self.dialect) &&
+            in_bytes.iter().any(|&c| is_raw_control(c)) {
+            return Err(QuoteError::Control);
+        }
+        if self.dialect == Dialect::Nushell && core::str::from_utf8(in_bytes).is_err() {
+            return Err(QuoteError::InvalidUtf8);
         }
-        Ok(out.into())
+        Ok(match self.dialect {
+            Dialect::Posix => quote_posix(in_bytes),
+            Dialect::Csh => quote_csh(in_bytes),
+            Dialect::Rc => quote_rc(in_bytes),
+            Dialect::Nushell => quote_nushell(in_bytes),
+            Dialect::Fish => quote_fish(in_bytes),
+            Dialect::PowerShell => quote_powershell(in_bytes),
+            Dialect::Bash => quote_bash(in_bytes),
+            Dialect::Python => quote_python(in_bytes),
+        })
     }
 
 }
 
+/// Can this dialect's quoting represent control characters without including them as-is?
+fn has_control_escapes(dialect: Dialect) -> bool {
+    match dialect {
+        Dialect::Bash | Dialect::Nushell | Dialect::Fish | Dialect::PowerShell => true,
+        Dialect::Posix | Dialect::Csh | Dialect::Rc | Dialect::Python => false,
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn is_control(c: u8) -> bool {
+    c < 0x20 || c == 0x7f
+}
+
+/// Is this a control character affected by [`Quoter::forbid_raw_control`]?
+///
+/// Tab and newline are excluded, since they're usually harmless in quotes.  Nul is excluded since
+/// it's handled separately.
+fn is_raw_control(c: u8) -> bool {
+    is_control(c) && !matches!(c, b'\0' | b'\t' | b'\n')
+}
+
+/// Quote a nonempty string for POSIX shells.
+fn quote_posix(mut in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    let mut out: Vec<u8> = Vec::new();
+    while !in_bytes.is_empty() {
+        // Pick a quoting strategy for some prefix of the input.  Normally this will cover the
+        // entire input, but in some case we might need to divide the input into multiple chunks
+        // that are quoted differently.
+        let (cur_len, strategy) = quoting_strategy(in_bytes);
+        if cur_len == in_bytes.len() && strategy == QuotingStrategy::Unquoted && out.is_empty() {
+            // Entire string can be represented unquoted.  Reuse the allocation.
+            return in_bytes.into();
+        }
+        let (cur_chunk, rest) = in_bytes.split_at(cur_len);
+        assert!(rest.len() < in_bytes.len()); // no infinite loop
+        in_bytes = rest;
+        append_quoted_chunk(&mut out, cur_chunk, strategy);
+    }
+    out.into()
+}
+
+/// Quote a nonempty string for csh and tcsh.
+///
+/// Everything goes in single quotes, which in csh are literal except for two things.  `!` still
+/// triggers history expansion, but can be escaped as `\!`.  And a newline ends the command even in
+/// quotes, unless it's preceded by a backslash.  A backslash before anything else is literal.
+///
+/// Single quotes themselves are written as `\'` outside of the quotes.  Double quotes are no use
+/// here, since in csh they don't suppress `$`, `` ` ``, or `!`, and backslashes can't help with
+/// the first two.
+fn quote_csh(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if in_bytes.iter().all(|&c| c < 0x80 && unquoted_ok_fast(c)) {
+        return in_bytes.into();
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 2);
+    out.push(b'\'');
+    let mut prev = 0u8;
+    for &c in in_bytes {
+        match c {
+            b'\'' => out.extend_from_slice(b"'\\''"),
+            b'!' | b'\n' => { out.push(b'\\'); out.push(c); },
+            // `^` at the start of a line is quick substitution in interactive tcsh.  Only a quoted
+            // newline can put it there, so move it outside the quotes in that case.
+            b'^' if prev == b'\n' => out.extend_from_slice(b"'\\^'"),
+            _ => out.push(c),
+        }
+        prev = c;
+    }
+    out.push(b'\'');
+    out.into()
+}
+
 #[derive(PartialEq)]
 enum QuotingStrategy {
     /// No quotes and no backslash escapes.  (If backslash escapes would be necessary, we use a
@@ -243,6 +1071,292 @@ enum QuotingStrategy {
     // TODO: add $'xxx' and "$(printf 'xxx')" styles
 }
 
+/// Quote a nonempty string for Bash, exactly like Bash's `printf %q` in the C locale.
+///
+/// If the string contains any bytes that aren't printable ASCII, the whole string uses ANSI-C
+/// quoting (`$'...'`), with escapes for those bytes and for `\` and `'`.  Otherwise, special
+/// characters are backslash-escaped.
+fn quote_bash(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if in_bytes.iter().any(|&c| !(0x20..0x7f).contains(&c)) {
+        let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 3);
+        out.extend_from_slice(b"$'");
+        for &c in in_bytes {
+            let escape = match c {
+                b'\x1b' => b'E',
+                b'\x07' => b'a',
+                b'\x0b' => b'v',
+                b'\x08' => b'b',
+                b'\x0c' => b'f',
+                b'\n' => b'n',
+                b'\r' => b'r',
+                b'\t' => b't',
+                b'\\' | b'\'' => c,
+                0x20..=0x7e => {
+                    out.push(c);
+                    continue;
+                },
+                _ => {
+                    out.push(b'\\');
+                    out.push(b'0' + (c >> 6));
+                    out.push(b'0' + ((c >> 3) & 7));
+                    out.push(b'0' + (c & 7));
+                    continue;
+                },
+            };
+            out.push(b'\\');
+            out.push(escape);
+        }
+        out.push(b'\'');
+        return out.into();
+    }
+    let needs_escape = |i: usize, c: u8| match c {
+        b' ' | b'!' | b'"' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b',' | b';' | b'<' | b'>' |
+        b'?' | b'[' | b'\\' | b']' | b'^' | b'`' | b'{' | b'|' | b'}' => true,
+        b'#' => i == 0,
+        b'~' => i == 0 || in_bytes[i - 1] == b':' || in_bytes[i - 1] == b'=',
+        _ => false,
+    };
+    if !in_bytes.iter().enumerate().any(|(i, &c)| needs_escape(i, c)) {
+        return in_bytes.into();
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() * 2);
+    for (i, &c) in in_bytes.iter().enumerate() {
+        if needs_escape(i, c) {
+            out.push(b'\\');
+        }
+        out.push(c);
+    }
+    out.into()
+}
+
+/// Quote a nonempty string exactly like Python's `shlex.quote`.
+///
+/// That is: leave it alone if it only contains ASCII alphanumerics and `%+,-./:=@_`, and otherwise
+/// put it in single quotes, with each single quote replaced by `'"'"'`.
+fn quote_python(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if in_bytes.iter().all(|&c| c.is_ascii_alphanumeric() || b"%+,-./:=@_".contains(&c)) {
+        return in_bytes.into();
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 2);
+    out.push(b'\'');
+    for &c in in_bytes {
+        if c == b'\'' {
+            out.extend_from_slice(b"'\"'\"'");
+        } else {
+            out.push(c);
+        }
+    }
+    out.push(b'\'');
+    out.into()
+}
+
+/// Quote a nonempty string for rc.
+///
+/// rc has no escapes, only single quotes, inside of which everything is literal except that `''`
+/// stands for one single quote.
+fn quote_rc(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    // `@` is excluded because it's the subshell operator.
+    if in_bytes.iter().all(|&c| c < 0x80 && c != b'@' && unquoted_ok_fast(c)) {
+        return in_bytes.into();
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 2);
+    out.push(b'\'');
+    for &c in in_bytes {
+        if c == b'\'' {
+            out.push(b'\'');
+        }
+        out.push(c);
+    }
+    out.push(b'\'');
+    out.into()
+}
+
+/// Quote a nonempty string for Nushell, picking whichever form is shortest.
+///
+/// The forms are: bare words; backtick-quoted and single-quoted strings, which have no escapes but
+/// can't contain their own delimiter; double-quoted strings, which can represent anything using
+/// backslash escapes; and raw strings like `r#'...'#`, which have no escapes but can use as many
+/// `#` as necessary to avoid matching the contents.
+///
+/// Strings containing control characters always use double quotes, so that the control
+/// characters can be written as escapes.
+fn quote_nushell(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if bare_ok_nushell(in_bytes) {
+        return in_bytes.into();
+    }
+    let has_control = in_bytes.iter().any(|&c| is_control(c));
+    let double_escapes = in_bytes.iter().filter(|&&c| c == b'"' || c == b'\\').count();
+    if !has_control {
+        // Without escapes, single quotes and backticks can't beat any other form.
+        for &quote in b"'`" {
+            if !in_bytes.contains(&quote) {
+                let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 2);
+                out.push(quote);
+                out.extend_from_slice(in_bytes);
+                out.push(quote);
+                return out.into();
+            }
+        }
+        // Find the smallest number of `#` such that the contents don't contain the terminator.
+        let mut hashes = 1;
+        while in_bytes.windows(hashes + 1).any(|w| w[0] == b'\'' && w[1..].iter().all(|&c| c == b'#')) {
+            hashes += 1;
+        }
+        if 2 * hashes + 1 < double_escapes {
+            let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 2 * hashes + 3);
+            out.push(b'r');
+            out.extend(core::iter::repeat(b'#').take(hashes));
+            out.push(b'\'');
+            out.extend_from_slice(in_bytes);
+            out.push(b'\'');
+            out.extend(core::iter::repeat(b'#').take(hashes));
+            return out.into();
+        }
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + double_escapes + 2);
+    out.push(b'"');
+    for &c in in_bytes {
+        match c {
+            b'"' | b'\\' => { out.push(b'\\'); out.push(c); },
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            0..=0x1f | 0x7f => {
+                out.extend_from_slice(b"\\u{");
+                if c >= 0x10 {
+                    out.push(HEX_DIGITS[(c >> 4) as usize]);
+                }
+                out.push(HEX_DIGITS[(c & 0xf) as usize]);
+                out.push(b'}');
+            },
+            _ => out.push(c),
+        }
+    }
+    out.push(b'"');
+    out.into()
+}
+
+/// Quote a nonempty string for fish.
+///
+/// fish understands the POSIX quoting, so use that, except that control characters are written
+/// as escapes (outside of quotes).
+fn quote_fish(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !in_bytes.iter().any(|&c| is_control(c)) {
+        return quote_posix(in_bytes);
+    }
+    let mut out: Vec<u8> = Vec::new();
+    let mut rest = in_bytes;
+    while let Some(&c) = rest.first() {
+        let len = rest.iter().position(|&c| is_control(c)).unwrap_or(rest.len());
+        if len > 0 {
+            out.extend_from_slice(&quote_posix(&rest[..len]));
+            rest = &rest[len..];
+            continue;
+        }
+        match c {
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            _ => {
+                out.extend_from_slice(b"\\x");
+                out.push(HEX_DIGITS[(c >> 4) as usize]);
+                out.push(HEX_DIGITS[(c & 0xf) as usize]);
+            },
+        }
+        rest = &rest[1..];
+    }
+    out.into()
+}
+
+/// Quote a nonempty string for PowerShell.
+///
+/// Single quotes are used unless the string contains control characters; they have no escapes,
+/// but a doubled quote stands for one quote.  Strings with control characters use double quotes,
+/// in which backtick is the escape character.  Either way, PowerShell's Unicode 'smart quotes'
+/// must be treated like the ASCII quotes.
+fn quote_powershell(in_bytes: &[u8]) -> Cow<'_, [u8]> {
+    if bare_ok_powershell(in_bytes) {
+        return in_bytes.into();
+    }
+    let smart_quote = |i: usize, range: core::ops::RangeInclusive<u8>| {
+        matches!(in_bytes.get(i..i + 3), Some(&[0xe2, 0x80, c]) if range.contains(&c))
+    };
+    let mut out: Vec<u8> = Vec::with_capacity(in_bytes.len() + 2);
+    if !in_bytes.iter().any(|&c| is_control(c)) {
+        out.push(b'\'');
+        for (i, &c) in in_bytes.iter().enumerate() {
+            // Double the quote.
+            if c == b'\'' {
+                out.push(b'\'');
+            } else if smart_quote(i, 0x98..=0x9b) {
+                out.extend_from_slice(&in_bytes[i..i + 3]);
+            }
+            out.push(c);
+        }
+        out.push(b'\'');
+    } else {
+        out.push(b'"');
+        for (i, &c) in in_bytes.iter().enumerate() {
+            match c {
+                b'\0' => out.extend_from_slice(b"`0"),
+                b'\n' => out.extend_from_slice(b"`n"),
+                b'\r' => out.extend_from_slice(b"`r"),
+                b'\t' => out.extend_from_slice(b"`t"),
+                0..=0x1f | 0x7f => {
+                    out.extend_from_slice(b"`u{");
+                    if c >= 0x10 {
+                        out.push(HEX_DIGITS[(c >> 4) as usize]);
+                    }
+                    out.push(HEX_DIGITS[(c & 0xf) as usize]);
+                    out.push(b'}');
+                },
+                b'"' | b'`' | b'$' => { out.push(b'`'); out.push(c); },
+                _ => {
+                    if smart_quote(i, 0x9c..=0x9e) {
+                        out.push(b'`');
+                    }
+                    out.push(c);
+                },
+            }
+        }
+        out.push(b'"');
+    }
+    out.into()
+}
+
+/// Can this string be a Nushell bare word that evaluates to itself as a string?
+fn bare_ok_nushell(in_bytes: &[u8]) -> bool {
+    // Bare words can contain many more characters than this, but other than these, nearly every
+    // ASCII punctuation character has a special meaning somewhere.
+    if !in_bytes.iter().all(|&c| c.is_ascii_alphanumeric() || b"+-./:@_".contains(&c)) {
+        return false;
+    }
+    // Avoid anything that could be parsed as a number, range, or other literal, like `1`, `-1`,
+    // `.5`, `1kb`, `0x10`, `1..2`, or `true`.  To be safe, require words to start with a letter,
+    // `_`, or `/`, or with `-` or `.` followed by one of those.
+    starts_like_word(in_bytes) &&
+        !in_bytes.windows(2).any(|w| w == b"..") &&
+        !matches!(in_bytes, b"true" | b"false" | b"null")
+}
+
+/// Can this string be a PowerShell bare word that evaluates to itself as a string?
+fn bare_ok_powershell(in_bytes: &[u8]) -> bool {
+    // As with Nushell, avoid things that look like numbers, like `1` or `1kb`.
+    in_bytes.iter().all(|&c| c.is_ascii_alphanumeric() || b"-./:_".contains(&c)) &&
+        starts_like_word(in_bytes)
+}
+
+/// Does this string start with a letter, `_`, or `/`, or with `-`, `--`, or `.` followed by one of
+/// those?
+fn starts_like_word(in_bytes: &[u8]) -> bool {
+    let start_ok = |c: u8| c.is_ascii_alphabetic() || c == b'_' || c == b'/';
+    match in_bytes {
+        [b'-', b'-', c, ..] | [b'-', c, ..] | [b'.', c, ..] => start_ok(*c),
+        [c, ..] => start_ok(*c),
+        [] => false,
+    }
+}
+
 /// Is this ASCII byte okay to emit unquoted?
 const fn unquoted_ok(c: u8) -> bool {
     match c as char {
@@ -498,6 +1612,89 @@ pub fn try_quote(in_bytes: &[u8]) -> Result<Cow<'_, [u8]>, QuoteError> {
     Quoter::new().quote(in_bytes)
 }
 
+/// Apply `policy` to `args`, so that the program they are passed to can't mistake an untrusted
+/// argument for an option.  See [`OptionPolicy`].
+///
+/// The string equivalent is [shlex::guard_args].
+pub fn guard_args<'a, I: IntoIterator<Item = Arg<&'a [u8]>>>(args: I, policy: OptionPolicy) -> Result<Vec<Cow<'a, [u8]>>, ArgError> {
+    let args: Vec<_> = args.into_iter().collect();
+    let is_option = |arg: &Arg<&[u8]>| matches!(arg, Arg::Untrusted(arg) if arg.starts_with(b"-"));
+    let insert_end = policy == OptionPolicy::EndOfOptions && args.iter().any(is_option);
+    let mut end_of_options = false;
+    let mut inserted = false;
+    let mut out = Vec::with_capacity(args.len() + 1);
+    for (index, arg) in args.into_iter().enumerate() {
+        match arg {
+            // A second `--` would be taken as an operand.
+            Arg::Trusted(arg) if inserted && arg == b"--" => {},
+            Arg::Trusted(arg) => {
+                end_of_options |= arg == b"--";
+                out.push(Cow::Borrowed(arg));
+            },
+            Arg::Untrusted(arg) => {
+                if insert_end && !end_of_options {
+                    out.push(Cow::Borrowed(&b"--"[..]));
+                    end_of_options = true;
+                    inserted = true;
+                }
+                if end_of_options || !arg.starts_with(b"-") {
+                    out.push(Cow::Borrowed(arg));
+                } else if policy == OptionPolicy::PathPrefix {
+                    out.push(Cow::Owned([&b"./"[..], arg].concat()));
+                } else {
+                    return Err(ArgError::Option(index));
+                }
+            },
+        }
+    }
+    Ok(out)
+}
+
+/// Split a command line using one shell [`Dialect`]'s rules, then quote each word for another.
+///
+/// This only works for command lines made of literal strings.  If the input contains shell
+/// syntax other than quoting, such as a variable, a glob, a brace, a pipe, or a leading
+/// `NAME=value` assignment, this returns [`TranslateError::Syntax`].  Control characters, other than tab and newline, are an error
+/// unless the target dialect has escape sequences for them (see [`QuoteError::Control`]).
+///
+/// The string equivalent is [shlex::translate].
+pub fn translate(in_bytes: &[u8], from: Dialect, to: Dialect) -> Result<Vec<u8>, TranslateError> {
+    let mut shl = Shlex::new(in_bytes).dialect(from);
+    let mut words: Vec<Vec<u8>> = Vec::new();
+    let mut assignment = false;
+    while let Some(word) = shl.next_with_quoting() {
+        if words.is_empty() {
+            assignment = is_assignment(&word, from);
+        }
+        words.push(word.bytes);
+    }
+    if shl.had_error {
+        return Err(TranslateError::Split);
+    }
+    if let Some(c) = shl.unquoted_syntax {
+        return Err(TranslateError::Syntax(c));
+    }
+    if assignment {
+        return Err(TranslateError::Syntax(b'='));
+    }
+    let quoter = Quoter::new().dialect(to).forbid_raw_control(true);
+    Ok(quoter.join(words.iter().map(|word| &word[..]))?)
+}
+
+/// Is `word`, at the start of a command, a `NAME=value` assignment in `dialect` rather than an
+/// argument?
+fn is_assignment(word: &QuotedWord, dialect: Dialect) -> bool {
+    if !matches!(dialect, Dialect::Posix | Dialect::Bash | Dialect::Fish | Dialect::Nushell) {
+        return false;
+    }
+    let eq = match word.bytes.iter().position(|&c| c == b'=') {
+        Some(eq) => eq,
+        None => { return false; },
+    };
+    eq > 0 && is_name_start(word.bytes[0]) && word.bytes[..eq].iter().all(|&c| is_name_char(c)) &&
+        !word.quoted[..=eq].contains(&true)
+}
+
 #[cfg(test)]
 const INVALID_UTF8: &[u8] = b"\xa1";
 #[cfg(test)]
@@ -511,6 +1708,7 @@ fn test_invalid_utf8() {
 }
 
 #[cfg(test)]
+#[allow(clippy::type_complexity)]
 static SPLIT_TEST_ITEMS: &[(&[u8], Option<&[&[u8]]>)] = &[
     (b"foo$baz", Some(&[b"foo$baz"])),
     (b"foo baz", Some(&[b"foo", b"baz"])),
@@ -542,6 +1740,49 @@ fn test_split() {
     }
 }
 
+#[test]
+fn test_split_fish_control() {
+    let split_fish = |input| {
+        let mut shl = Shlex::new(input).dialect(Dialect::Fish);
+        let words: Vec<_> = shl.by_ref().collect();
+        if shl.had_error { None } else { Some(words) }
+    };
+    assert_eq!(split_fish(b"\\ca\\c["), Some(vec![b"\x01\x1b".to_vec()]));
+    assert_eq!(split_fish(b"\\c\xc3\xa9"), None);
+}
+
+#[test]
+fn test_split_with_quoting() {
+    // Each word is given as its bytes, a mask with `q` for quoted bytes, and `was_quoted`.
+    type Words = &'static [(&'static [u8], &'static [u8], bool)];
+    let cases: &[(&[u8], Dialect, Words)] = &[
+        (b"a\"*\"\\? '' \"\" x$'\\n' \\\n", Dialect::Bash, &[
+            (b"a*?", b"-qq", true),
+            (b"", b"", true),
+            (b"", b"", true),
+            (b"x\n", b"-q", true),
+            (b"", b"", false),
+        ]),
+        (b"'a''b' c\\\nd", Dialect::Rc, &[(b"a'b", b"qqq", true), (b"c", b"-", false), (b"d", b"-", false)]),
+        (b"'' a\\*\"b\"", Dialect::Csh, &[(b"", b"", true), (b"a*b", b"-qq", true)]),
+        (b"'a b' c\\n", Dialect::Fish, &[(b"a b", b"qqq", true), (b"c\n", b"-q", true)]),
+        (b"\"\" a`*", Dialect::PowerShell, &[(b"", b"", true), (b"a*", b"-q", true)]),
+        (b"'' a*", Dialect::Nushell, &[(b"", b"", true), (b"a*", b"--", false)]),
+    ];
+    for &(input, dialect, expected) in cases {
+        let mut shl = Shlex::new(input).dialect(dialect);
+        let words: Vec<QuotedWord> = core::iter::from_fn(|| shl.next_with_quoting()).collect();
+        assert!(!shl.had_error, "{:?}", input);
+        let expected: Vec<QuotedWord> = expected.iter().map(|&(bytes, mask, was_quoted)| QuotedWord {
+            bytes: bytes.to_owned(),
+            quoted: mask.iter().map(|&c| c == b'q').collect(),
+            was_quoted,
+        }).collect();
+        assert_eq!(words, expected, "{:?}", input);
+    }
+    assert_eq!(split_with_quoting(b"'a"), None);
+}
+
 #[test]
 fn test_lineno() {
     let mut sh = Shlex::new(b"\nfoo\nbar");
@@ -563,6 +1804,62 @@ fn test_quote() {
     assert_eq!(quote(b"foo bar"), &b"'foo bar'"[..]);
     assert_eq!(quote(b"'\""), &b"\"'\\\"\""[..]);
     assert_eq!(quote(b""), &b"''"[..]);
+    // Nushell strings must be UTF-8.
+    let nushell = Quoter::new().dialect(Dialect::Nushell);
+    assert_eq!(nushell.quote(INVALID_UTF8), Err(QuoteError::InvalidUtf8));
+    assert_eq!(nushell.quote(b"a b\xc3\xa9").unwrap(), &b"'a b\xc3\xa9'"[..]);
+}
+
+#[test]
+#[cfg(all(unix, feature = "std"))]
+fn test_quote_bash_matches_printf_q() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::process::Command;
+
+    // Every byte on its own and between other characters, plus some other edge cases, plus
+    // pseudorandom strings.
+    let mut inputs: Vec<Vec<u8>> = Vec::new();
+    for c in 1..=255u8 {
+        inputs.push(vec![c]);
+        inputs.push(vec![b'a', c, b'b']);
+    }
+    for &s in &[&b"~"[..], b"a:~", b"a=~", b"a~", b"#a", b"a#", b"~a:~b=~"] {
+        inputs.push(s.to_owned());
+    }
+    let mut seed: u32 = 1;
+    for _ in 0..200 {
+        let len = seed % 16;
+        let mut s = Vec::new();
+        for _ in 0..len {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            s.push(((seed >> 16) % 255) as u8 + 1);
+        }
+        inputs.push(s);
+    }
+
+    let output = Command::new("bash")
+        .env("LC_ALL", "C")
+        .arg("-c")
+        .arg(r#"for a; do printf '%q\n' "$a"; done"#)
+        .arg("bash")
+        .args(inputs.iter().map(|s| OsStr::from_bytes(s)))
+        .output();
+    let output = match output {
+        Ok(output) => output.stdout,
+        // bash isn't installed.
+        Err(_) => { return; },
+    };
+    let quoter = Quoter::new().dialect(Dialect::Bash);
+    let expected: Vec<&[u8]> = output.split(|&c| c == b'\n').collect();
+    assert_eq!(expected.len(), inputs.len() + 1);
+    for (input, expected) in inputs.iter().zip(expected) {
+        let quoted = quoter.quote(input).unwrap();
+        assert_eq!(&quoted[..], expected, "{:?}", input);
+        let mut shl = Shlex::new(&quoted).dialect(Dialect::Bash);
+        assert_eq!(shl.next().as_ref(), Some(input));
+        assert_eq!(shl.next(), None);
+    }
 }
 
 #[test]
@@ -0,0 +1,1413 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Parsing of shell commands into their parts: variable assignments, arguments and redirections.
+//!
+//! Where [`split`](crate::split) would turn `FOO=1 ./run --x >out.log 2>&1 <in` into a flat list
+//! of words, [`parse_simple_command`] separates the assignment `FOO=1`, the arguments `./run` and
+//! `--x`, and the three redirections, so that the command can be run with
+//! `std::process::Command` and explicitly opened files rather than by a shell.
+//!
+//! [`parse_list`] parses a whole command line, made of simple commands joined into pipelines
+//! (`a | b`, optionally negated with `!`), and-or chains (`a && b || c`) and lists (`a; b & c`).
+//! Compound commands, such as subshells, `{ ... }` groups, `if`, loops and function definitions,
+//! are rejected with [`SyntaxError::Unsupported`] rather than misparsed.  [`parse_script`]
+//! accepts them too, parsing the whole POSIX shell grammar into [`CompoundCommand`]s and
+//! [`FunctionDefinition`]s.  Bash's `[[ ... ]]`, `(( ... ))` and `function` keyword are still
+//! unsupported.
+//!
+//! Here-documents (`<<EOF` and `<<-EOF`) are supported: their bodies, from the lines after the
+//! command, are attached to their [`Redirection`]s as [`HereDoc`]s.
+//!
+//! The arguments, assignment values and redirection targets are [`Word`]s, which keep their
+//! quoting and expansions; [`Word::cooked`] gives their text after quote removal.
+//!
+//! ```
+//! use shlex::command::{parse_simple_command, RedirectOp};
+//! let command = parse_simple_command(b"FOO=1 ./run --x >out.log 2>&1 <in").unwrap();
+//! assert_eq!(command.assignments[0].name, "FOO");
+//! assert_eq!(command.assignments[0].value.cooked(), b"1");
+//! let args: Vec<_> = command.words.iter().map(|word| word.cooked()).collect();
+//! assert_eq!(args, [&b"./run"[..], b"--x"]);
+//! let redirections: Vec<_> = command.redirections.iter()
+//!     .map(|redir| (redir.fd, redir.op, redir.target.cooked()))
+//!     .collect();
+//! assert_eq!(redirections, [
+//!     (None, RedirectOp::Output, b"out.log".to_vec()),
+//!     (Some(2), RedirectOp::DupOutput, b"1".to_vec()),
+//!     (None, RedirectOp::Input, b"in".to_vec()),
+//! ]);
+//! ```
+
+extern crate alloc;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::word::{is_name_char, is_name_start, Inner, ParseError, Parser, Segment, Spanned, Word, MAX_NESTING};
+
+/// A list of and-or chains, separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<ListItem>,
+    pub span: Range<usize>,
+}
+
+impl List {
+    fn collect_redirections<'a>(&'a mut self, out: &mut Vec<&'a mut Redirection>) {
+        for item in &mut self.items {
+            let and_or = &mut item.and_or;
+            for pipeline in core::iter::once(&mut and_or.first).chain(and_or.rest.iter_mut().map(|(_, pipeline)| pipeline)) {
+                for command in &mut pipeline.commands {
+                    command.collect_redirections(out);
+                }
+            }
+        }
+    }
+}
+
+/// An and-or chain in a [`List`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    /// Whether the chain was followed by `&`, which runs it in the background.
+    pub background: bool,
+}
+
+/// A chain of pipelines joined by `&&` and `||`, such as `a && b || c`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    /// The rest of the pipelines, each with the operator before it.
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+    pub span: Range<usize>,
+}
+
+/// An operator in an [`AndOr`] chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndOrOp {
+    /// `&&`, which runs the next pipeline if the previous one succeeded.
+    And,
+    /// `||`, which runs the next pipeline if the previous one failed.
+    Or,
+}
+
+/// A sequence of commands joined by `|`, such as `a | b`, or just one command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    /// Whether the pipeline started with `!`, which negates its exit status.
+    pub negated: bool,
+    pub commands: Vec<Command>,
+    pub span: Range<usize>,
+}
+
+/// A command in a [`Pipeline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand),
+    FunctionDefinition(FunctionDefinition),
+}
+
+impl Command {
+    /// The simple command, if this is one.  Lists from [`parse_list`] only contain simple
+    /// commands.
+    pub fn as_simple(&self) -> Option<&SimpleCommand> {
+        match self {
+            Command::Simple(command) => Some(command),
+            _ => None,
+        }
+    }
+
+    /// The range of the input the command was parsed from.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Command::Simple(command) => command.span.clone(),
+            Command::Compound(command) => command.span.clone(),
+            Command::FunctionDefinition(definition) => definition.span.clone(),
+        }
+    }
+
+    fn collect_redirections<'a>(&'a mut self, out: &mut Vec<&'a mut Redirection>) {
+        match self {
+            Command::Simple(command) => out.extend(command.redirections.iter_mut()),
+            Command::Compound(command) => command.collect_redirections(out),
+            Command::FunctionDefinition(definition) => definition.body.collect_redirections(out),
+        }
+    }
+}
+
+/// A compound command, such as a subshell, an `if` statement or a loop, along with any
+/// redirections after it, as in `{ a; b; } >log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompoundCommand {
+    pub kind: CompoundKind,
+    pub redirections: Vec<Redirection>,
+    pub span: Range<usize>,
+}
+
+impl CompoundCommand {
+    fn collect_redirections<'a>(&'a mut self, out: &mut Vec<&'a mut Redirection>) {
+        match &mut self.kind {
+            CompoundKind::Subshell(list) | CompoundKind::Group(list) => list.collect_redirections(out),
+            CompoundKind::If { branches, else_body } => {
+                for branch in branches {
+                    branch.condition.collect_redirections(out);
+                    branch.body.collect_redirections(out);
+                }
+                if let Some(else_body) = else_body {
+                    else_body.collect_redirections(out);
+                }
+            },
+            CompoundKind::While { condition, body } | CompoundKind::Until { condition, body } => {
+                condition.collect_redirections(out);
+                body.collect_redirections(out);
+            },
+            CompoundKind::For { body, .. } => body.collect_redirections(out),
+            CompoundKind::Case { items, .. } => {
+                for item in items {
+                    item.body.collect_redirections(out);
+                }
+            },
+        }
+        out.extend(self.redirections.iter_mut());
+    }
+}
+
+/// The kinds of [`CompoundCommand`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompoundKind {
+    /// `( list )`
+    Subshell(List),
+    /// `{ list; }`
+    Group(List),
+    /// `if condition; then body; elif condition; then body; else else_body; fi`
+    If { branches: Vec<IfBranch>, else_body: Option<List> },
+    /// `while condition; do body; done`
+    While { condition: List, body: List },
+    /// `until condition; do body; done`
+    Until { condition: List, body: List },
+    /// `for name in words; do body; done`.  `words` is `None` if there was no `in`, which loops
+    /// over the positional parameters.
+    For { name: String, words: Option<Vec<Word>>, body: List },
+    /// `case word in pattern | pattern) body;; ... esac`
+    Case { word: Word, items: Vec<CaseItem> },
+}
+
+/// The `if` or an `elif` part of an `if` statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfBranch {
+    pub condition: List,
+    pub body: List,
+}
+
+/// An item of a `case` statement: `pattern | pattern) body;;`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    /// The patterns, which can be matched with [`Pattern`](crate::pattern::Pattern) once
+    /// they're expanded.
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub span: Range<usize>,
+}
+
+/// A function definition, `name() compound-command`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub body: CompoundCommand,
+    pub span: Range<usize>,
+}
+
+/// A simple command: a list of variable assignments, then a list of words, with redirections
+/// anywhere among them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// The `NAME=value` words before the command name.
+    pub assignments: Vec<Assignment>,
+    /// The command name and its arguments.
+    pub words: Vec<Word>,
+    pub redirections: Vec<Redirection>,
+    pub span: Range<usize>,
+}
+
+/// A variable assignment, `NAME=value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    /// The value, which is the rest of the word after the `=`.
+    pub value: Word,
+    pub span: Range<usize>,
+}
+
+/// A redirection, such as `>out.log`, `2>&1` or `<<EOF`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirection {
+    /// The file descriptor being redirected, if one was written, as in `2>`.  Otherwise it's
+    /// [`RedirectOp::default_fd`].
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    /// The file name; for [`RedirectOp::DupInput`] and [`RedirectOp::DupOutput`], the file
+    /// descriptor to duplicate, or `-` to close it; or for here-documents, the delimiter.
+    pub target: Word,
+    /// The here-document, for [`RedirectOp::HereDoc`] and [`RedirectOp::HereDocStripTabs`].
+    pub here_doc: Option<HereDoc>,
+    /// The range of the input the redirection was parsed from.  This doesn't include the body of
+    /// a here-document, which is on the following lines.
+    pub span: Range<usize>,
+}
+
+/// The body of a here-document, the lines after a `<<` redirection up to its delimiter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HereDoc {
+    /// The body.  If the delimiter was unquoted, this contains expansions, which work as in
+    /// double quotes; otherwise, it's all literal.  Leading tabs have already been removed for
+    /// [`RedirectOp::HereDocStripTabs`].
+    pub body: Vec<Spanned<Inner>>,
+    /// Whether any part of the delimiter was quoted, which turns off expansion in the body.
+    pub quoted: bool,
+    /// The range of the input the body was parsed from, not including the delimiter line.
+    pub span: Range<usize>,
+}
+
+/// The operator of a [`Redirection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RedirectOp {
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+    /// `<&`
+    DupInput,
+    /// `>&`
+    DupOutput,
+    /// `<>`
+    ReadWrite,
+    /// `>|`, which overwrites the file even if the shell's `noclobber` option is set.
+    Clobber,
+    /// `<<`
+    HereDoc,
+    /// `<<-`, which removes tabs from the start of each line of the here-document.
+    HereDocStripTabs,
+}
+
+impl RedirectOp {
+    /// The file descriptor redirected when none is given: 0 (standard input) for `<`, `<&`, `<>`
+    /// and here-documents, and 1 (standard output) otherwise.
+    pub fn default_fd(self) -> u32 {
+        match self {
+            RedirectOp::Input | RedirectOp::DupInput | RedirectOp::ReadWrite | RedirectOp::HereDoc | RedirectOp::HereDocStripTabs => 0,
+            _ => 1,
+        }
+    }
+
+    fn from_operator(op: &str) -> Option<RedirectOp> {
+        Some(match op {
+            "<" => RedirectOp::Input,
+            ">" => RedirectOp::Output,
+            ">>" => RedirectOp::Append,
+            "<&" => RedirectOp::DupInput,
+            ">&" => RedirectOp::DupOutput,
+            "<>" => RedirectOp::ReadWrite,
+            ">|" => RedirectOp::Clobber,
+            "<<" => RedirectOp::HereDoc,
+            "<<-" => RedirectOp::HereDocStripTabs,
+            _ => { return None; },
+        })
+    }
+}
+
+/// An error from parsing a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SyntaxError {
+    /// A word was malformed.
+    Word(ParseError),
+    /// A token appeared where it isn't allowed, such as an operator other than a redirection in a
+    /// simple command, or anything but a word after a redirection operator.  `offset` is where
+    /// it starts, or the length of the input if the input ended too soon.
+    Unexpected { offset: usize },
+    /// The input used a construct that isn't supported, starting at `offset`.
+    Unsupported { construct: Construct, offset: usize },
+    /// Compound commands and expansions were nested more than [`MAX_NESTING`] levels deep.
+    /// `offset` is where the compound command that was one too many starts.
+    TooDeep { offset: usize },
+}
+
+/// A shell construct that can't be parsed, for [`SyntaxError::Unsupported`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Construct {
+    /// `( ... )`
+    Subshell,
+    /// `{ ...; }`
+    Group,
+    /// `if ...; then ...; fi`
+    If,
+    /// `while`, `until` and `for` loops.
+    Loop,
+    /// `case ... esac`
+    Case,
+    /// `name() ...` or bash's `function name ...`
+    FunctionDefinition,
+    /// Bash's `[[ ... ]]`, `(( ... ))` and `select` loops.
+    BashCommand,
+}
+
+impl core::fmt::Display for Construct {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Construct::Subshell => "subshells",
+            Construct::Group => "command groups",
+            Construct::If => "if statements",
+            Construct::Loop => "loops",
+            Construct::Case => "case statements",
+            Construct::FunctionDefinition => "function definitions",
+            Construct::BashCommand => "[[, (( and select commands",
+        })
+    }
+}
+
+impl core::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SyntaxError::Word(err) => err.fmt(f),
+            SyntaxError::Unexpected { offset } => write!(f, "syntax error at byte {}", offset),
+            SyntaxError::Unsupported { construct, offset } => write!(f, "{} are not supported (at byte {})", construct, offset),
+            SyntaxError::TooDeep { offset } => write!(f, "commands nested too deeply (at byte {})", offset),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SyntaxError {}
+
+impl From<ParseError> for SyntaxError {
+    fn from(err: ParseError) -> Self {
+        SyntaxError::Word(err)
+    }
+}
+
+/// Parse `input` as a single simple command, optionally followed by newlines.  Any other
+/// operator, such as `;` or `|`, is a [`SyntaxError::Unexpected`].
+pub fn parse_simple_command(input: &[u8]) -> Result<SimpleCommand, SyntaxError> {
+    let mut lexer = Lexer::new(input);
+    let mut command = lexer.simple_command()?;
+    while let Some(token) = lexer.next()? {
+        if token.kind != TokenKind::Operator("\n") {
+            return Err(SyntaxError::Unexpected { offset: token.span.start });
+        }
+    }
+    lexer.attach_here_docs(command.redirections.iter_mut().collect());
+    Ok(command)
+}
+
+/// Parse `input` as a list of pipelines and and-or chains of simple commands.  Compound
+/// commands and function definitions are a [`SyntaxError::Unsupported`]; use [`parse_script`]
+/// to allow them.
+pub fn parse_list(input: &[u8]) -> Result<List, SyntaxError> {
+    parse(input, false)
+}
+
+/// Parse `input` as a shell script, using the full POSIX shell grammar.
+pub fn parse_script(input: &[u8]) -> Result<List, SyntaxError> {
+    parse(input, true)
+}
+
+fn parse(input: &[u8], compound: bool) -> Result<List, SyntaxError> {
+    let mut lexer = Lexer::new(input);
+    lexer.compound = compound;
+    let mut list = lexer.list(&[])?;
+    if let Some(token) = lexer.peek()? {
+        return Err(unexpected(input, Some(token)));
+    }
+    if list.items.is_empty() {
+        list.span = 0..0;
+    }
+    let mut redirections = Vec::new();
+    list.collect_redirections(&mut redirections);
+    lexer.attach_here_docs(redirections);
+    Ok(list)
+}
+
+/// Find the end of the command in a `$(...)` command substitution in `input`, which starts at
+/// `start`, and return the position just after its closing `)`.  `nesting` is how many
+/// expansions the command substitution is inside, including itself, and `not_arithmetic` is the
+/// outer parser's [`Parser::not_arithmetic`], which is shared with the one parsing the command.
+pub(crate) fn command_substitution_end(input: &[u8], start: usize, nesting: usize, not_arithmetic: &mut BTreeSet<usize>) -> Result<usize, ParseError> {
+    let mut lexer = Lexer::new(input);
+    lexer.compound = true;
+    lexer.parser.pos = start;
+    lexer.parser.nesting = nesting;
+    lexer.parser.not_arithmetic = core::mem::take(not_arithmetic);
+    let res = lexer.list(&[]).and_then(|_| lexer.next());
+    *not_arithmetic = core::mem::take(&mut lexer.parser.not_arithmetic);
+    match res {
+        Ok(Some(Token { kind: TokenKind::Operator(")"), span })) => Ok(span.end),
+        Ok(None) => Err(ParseError::Unterminated),
+        Err(SyntaxError::Word(err)) => Err(err),
+        Err(SyntaxError::TooDeep { .. }) => Err(ParseError::TooDeep),
+        Err(SyntaxError::Unexpected { offset }) if offset == input.len() => Err(ParseError::Unterminated),
+        Ok(Some(_)) | Err(_) => Err(ParseError::BadSubstitution),
+    }
+}
+
+/// The error for an unexpected `token` in `input`, or for the input ending.
+fn unexpected(input: &[u8], token: Option<&Token>) -> SyntaxError {
+    SyntaxError::Unexpected { offset: token.map_or(input.len(), |token| token.span.start) }
+}
+
+/// The 1-based line number of `offset` in `input`, for reporting the position of a span or a
+/// [`SyntaxError`] the way [`Shlex::line_no`](crate::bytes::Shlex::line_no) does.
+pub fn line_no(input: &[u8], offset: usize) -> usize {
+    1 + input[..offset].iter().filter(|&&c| c == b'\n').count()
+}
+
+/// Every operator, longest first.
+const OPERATORS: &[&str] = &[
+    "<<-",
+    "&&", "||", ";;", "<<", ">>", "<&", ">&", "<>", ">|",
+    ";", "&", "|", "<", ">", "(", ")", "\n",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word(Word),
+    /// A file descriptor number right before a redirection operator, as in `2>`.
+    IoNumber(u32),
+    Operator(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    span: Range<usize>,
+}
+
+/// A here-document whose body hasn't been read yet, because it starts after the next newline.
+struct PendingHereDoc {
+    delimiter: Vec<u8>,
+    quoted: bool,
+    strip_tabs: bool,
+}
+
+/// Splits a command into words and operators.
+struct Lexer<'a> {
+    input: &'a [u8],
+    parser: Parser<'a>,
+    peeked: Option<Token>,
+    /// Whether compound commands and function definitions are allowed.
+    compound: bool,
+    pending: Vec<PendingHereDoc>,
+    /// The bodies of here-documents that have been read, in order, to be attached to their
+    /// redirections with [`Lexer::attach_here_docs`].
+    here_docs: Vec<HereDoc>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Lexer { input, parser: Parser::with_operators(input), peeked: None, compound: false, pending: Vec::new(), here_docs: Vec::new() }
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token>, SyntaxError> {
+        if self.peeked.is_none() {
+            self.peeked = self.lex()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, SyntaxError> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.lex(),
+        }
+    }
+
+    /// If the next token is an operator, return it.
+    fn peek_operator(&mut self) -> Result<Option<&'static str>, SyntaxError> {
+        Ok(match self.peek()? {
+            Some(Token { kind: TokenKind::Operator(op), .. }) => Some(op),
+            _ => None,
+        })
+    }
+
+    /// If the next token is a reserved word, return it.
+    fn peek_reserved(&mut self) -> Result<Option<&'static str>, SyntaxError> {
+        Ok(match self.peek()? {
+            Some(Token { kind: TokenKind::Word(word), .. }) => reserved_word(word),
+            _ => None,
+        })
+    }
+
+    /// Where the next token starts, or the length of the input if there are no more.
+    fn next_start(&mut self) -> Result<usize, SyntaxError> {
+        let len = self.input.len();
+        Ok(self.peek()?.map_or(len, |token| token.span.start))
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), SyntaxError> {
+        while self.peek_operator()? == Some("\n") {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn lex(&mut self) -> Result<Option<Token>, SyntaxError> {
+        loop {
+            self.parser.skip_blanks();
+            let start = self.parser.pos;
+            let rest = &self.input[start..];
+            if rest.is_empty() {
+                self.read_here_docs()?;
+                return Ok(None);
+            }
+            if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(op.as_bytes())) {
+                self.parser.pos += op.len();
+                let span = start..self.parser.pos;
+                if *op == "\n" {
+                    self.read_here_docs()?;
+                }
+                return Ok(Some(Token { kind: TokenKind::Operator(op), span }));
+            }
+            let word = self.parser.parse_word()?;
+            // A word can be empty if it was just a line continuation.
+            if word.segments.is_empty() {
+                continue;
+            }
+            let kind = match (io_number(&word), self.parser.peek()) {
+                (Some(fd), Some(b'<')) | (Some(fd), Some(b'>')) => TokenKind::IoNumber(fd),
+                _ => TokenKind::Word(word),
+            };
+            return Ok(Some(Token { kind, span: start..self.parser.pos }));
+        }
+    }
+
+    /// Read the bodies of any pending here-documents, which start at the current position, and
+    /// skip past them.
+    fn read_here_docs(&mut self) -> Result<(), SyntaxError> {
+        for here_doc in core::mem::take(&mut self.pending) {
+            let start = self.parser.pos;
+            // The body ends at the delimiter line, or, as in shells, at the end of the input.
+            let mut end = self.input.len();
+            let mut line_start = start;
+            while line_start < self.input.len() {
+                let line_end = self.input[line_start..].iter().position(|&c| c == b'\n').map_or(self.input.len(), |i| line_start + i);
+                let mut line = &self.input[line_start..line_end];
+                if here_doc.strip_tabs {
+                    while line.first() == Some(&b'\t') {
+                        line = &line[1..];
+                    }
+                }
+                if line == &here_doc.delimiter[..] {
+                    end = line_start;
+                    break;
+                }
+                line_start = line_end + 1;
+            }
+            // Skip the delimiter line, including its newline.
+            self.parser.pos = match self.input[end..].iter().position(|&c| c == b'\n') {
+                Some(i) => end + i + 1,
+                None => self.input.len(),
+            };
+            let body = if here_doc.quoted {
+                let mut text = Vec::with_capacity(end - start);
+                let mut line_start = true;
+                for &c in &self.input[start..end] {
+                    if !(here_doc.strip_tabs && line_start && c == b'\t') {
+                        text.push(c);
+                        line_start = c == b'\n';
+                    }
+                }
+                let mut body = Vec::new();
+                if !text.is_empty() {
+                    body.push(Spanned { node: Inner::Literal(text), span: start..end });
+                }
+                body
+            } else {
+                let mut parser = Parser::new(self.input);
+                parser.pos = start;
+                parser.parse_here_doc(end, here_doc.strip_tabs)?
+            };
+            self.here_docs.push(HereDoc { body, quoted: here_doc.quoted, span: start..end });
+        }
+        Ok(())
+    }
+
+    /// Give the here-documents that have been read to `redirections`, which must be all the
+    /// redirections parsed.
+    fn attach_here_docs(&mut self, mut redirections: Vec<&mut Redirection>) {
+        redirections.sort_by_key(|redirection| redirection.span.start);
+        let mut here_docs = core::mem::take(&mut self.here_docs).into_iter();
+        for redirection in redirections {
+            if redirection.op == RedirectOp::HereDoc || redirection.op == RedirectOp::HereDocStripTabs {
+                redirection.here_doc = here_docs.next();
+            }
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, SyntaxError> {
+        let first = self.pipeline()?;
+        let mut span = first.span.clone();
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek_operator()? {
+                Some("&&") => AndOrOp::And,
+                Some("||") => AndOrOp::Or,
+                _ => { break; },
+            };
+            self.next()?;
+            self.skip_newlines()?;
+            let pipeline = self.pipeline()?;
+            span.end = pipeline.span.end;
+            rest.push((op, pipeline));
+        }
+        Ok(AndOr { first, rest, span })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, SyntaxError> {
+        let bang = match self.peek()? {
+            Some(Token { kind: TokenKind::Word(word), span }) if reserved_word(word) == Some("!") => Some(span.start),
+            _ => None,
+        };
+        if bang.is_some() {
+            self.next()?;
+        }
+        let mut commands = alloc::vec![self.command()?];
+        while self.peek_operator()? == Some("|") {
+            self.next()?;
+            self.skip_newlines()?;
+            commands.push(self.command()?);
+        }
+        let start = bang.unwrap_or(commands[0].span().start);
+        let span = start..commands[commands.len() - 1].span().end;
+        Ok(Pipeline { negated: bang.is_some(), commands, span })
+    }
+
+    /// Parse a list, which ends at the end of the input, at `)` or `;;`, or at a reserved word
+    /// in `terminators` where a command would start.
+    fn list(&mut self, terminators: &[&str]) -> Result<List, SyntaxError> {
+        let mut list = List::default();
+        loop {
+            self.skip_newlines()?;
+            match self.peek()? {
+                None | Some(Token { kind: TokenKind::Operator(")"), .. }) | Some(Token { kind: TokenKind::Operator(";;"), .. }) => { break; },
+                Some(Token { kind: TokenKind::Word(word), .. }) if reserved_word(word).map_or(false, |word| terminators.contains(&word)) => { break; },
+                _ => {},
+            }
+            let and_or = self.and_or()?;
+            let background = match self.peek_operator()? {
+                Some(";") | Some("\n") => false,
+                Some("&") => true,
+                _ => {
+                    list.items.push(ListItem { and_or, background: false });
+                    break;
+                },
+            };
+            self.next()?;
+            list.items.push(ListItem { and_or, background });
+        }
+        list.span = match (list.items.first(), list.items.last()) {
+            (Some(first), Some(last)) => first.and_or.span.start..last.and_or.span.end,
+            _ => {
+                let pos = self.next_start()?;
+                pos..pos
+            },
+        };
+        Ok(list)
+    }
+
+    /// Like [`Lexer::list`], but the list can't be empty.
+    fn nonempty_list(&mut self, terminators: &[&str]) -> Result<List, SyntaxError> {
+        let list = self.list(terminators)?;
+        if list.items.is_empty() {
+            return Err(SyntaxError::Unexpected { offset: list.span.start });
+        }
+        Ok(list)
+    }
+
+    fn command(&mut self) -> Result<Command, SyntaxError> {
+        if let Some(construct) = self.compound_start()? {
+            if !self.compound {
+                return Err(SyntaxError::Unsupported { construct, offset: self.next_start()? });
+            }
+            return self.compound_command().map(Command::Compound);
+        }
+        let input = self.input;
+        match self.peek()? {
+            Some(Token { kind: TokenKind::Word(_), .. }) | Some(Token { kind: TokenKind::IoNumber(_), .. }) => {},
+            Some(Token { kind: TokenKind::Operator(op), .. }) if is_redirection(op) => {},
+            token => { return Err(unexpected(input, token)); },
+        }
+        let command = self.simple_command()?;
+        if self.peek_operator()? == Some("(") && command.words.len() == 1 && command.assignments.is_empty() && command.redirections.is_empty() {
+            if !self.compound {
+                return Err(SyntaxError::Unsupported { construct: Construct::FunctionDefinition, offset: command.span.start });
+            }
+            return self.function_definition(command).map(Command::FunctionDefinition);
+        }
+        Ok(Command::Simple(command))
+    }
+
+    /// If the next token starts a compound command, return which kind.  Reserved words that
+    /// can't start a command, and unsupported bash syntax, are errors.
+    fn compound_start(&mut self) -> Result<Option<Construct>, SyntaxError> {
+        let input = self.input;
+        let token = self.peek()?;
+        let unsupported = |construct, span: &Range<usize>| Err(SyntaxError::Unsupported { construct, offset: span.start });
+        Ok(match token {
+            Some(Token { kind: TokenKind::Word(word), span }) => match reserved_word(word) {
+                Some("{") => Some(Construct::Group),
+                Some("if") => Some(Construct::If),
+                Some("while") | Some("until") | Some("for") => Some(Construct::Loop),
+                Some("case") => Some(Construct::Case),
+                Some("function") => { return unsupported(Construct::FunctionDefinition, span); },
+                Some("[[") | Some("select") => { return unsupported(Construct::BashCommand, span); },
+                // These are only reserved after `for` or `case`, and after `[[`.
+                Some("in") | Some("]]") => None,
+                Some(_) => { return Err(unexpected(input, token)); },
+                None => None,
+            },
+            Some(Token { kind: TokenKind::Operator("("), span }) => {
+                if input.get(span.end) == Some(&b'(') {
+                    return unsupported(Construct::BashCommand, span);
+                }
+                Some(Construct::Subshell)
+            },
+            _ => None,
+        })
+    }
+
+    /// Parse a compound command, which [`Lexer::compound_start`] has found, and any
+    /// redirections after it.  Nesting them counts toward the same limit as nesting expansions.
+    fn compound_command(&mut self) -> Result<CompoundCommand, SyntaxError> {
+        if self.parser.nesting == MAX_NESTING {
+            return Err(SyntaxError::TooDeep { offset: self.next_start()? });
+        }
+        self.parser.nesting += 1;
+        let res = self.compound_command_inner();
+        self.parser.nesting -= 1;
+        res
+    }
+
+    fn compound_command_inner(&mut self) -> Result<CompoundCommand, SyntaxError> {
+        let token = self.next()?.unwrap();
+        let (kind, end) = match &token.kind {
+            TokenKind::Operator("(") => {
+                let body = self.nonempty_list(&[])?;
+                (CompoundKind::Subshell(body), self.expect_operator(")")?)
+            },
+            TokenKind::Word(word) => match reserved_word(word) {
+                Some("{") => {
+                    let body = self.nonempty_list(&["}"])?;
+                    (CompoundKind::Group(body), self.expect_reserved("}")?)
+                },
+                Some("if") => self.if_rest()?,
+                Some(keyword @ "while") | Some(keyword @ "until") => {
+                    let condition = self.nonempty_list(&["do"])?;
+                    let (body, end) = self.do_group()?;
+                    if keyword == "while" {
+                        (CompoundKind::While { condition, body }, end)
+                    } else {
+                        (CompoundKind::Until { condition, body }, end)
+                    }
+                },
+                Some("for") => self.for_rest()?,
+                Some("case") => self.case_rest()?,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let mut command = CompoundCommand { kind, redirections: Vec::new(), span: token.span.start..end };
+        loop {
+            match self.peek()? {
+                Some(Token { kind: TokenKind::IoNumber(_), .. }) => {},
+                Some(Token { kind: TokenKind::Operator(op), .. }) if is_redirection(op) => {},
+                _ => { break; },
+            }
+            let token = self.next()?.unwrap();
+            let redirection = self.redirection(token)?;
+            command.span.end = redirection.span.end;
+            command.redirections.push(redirection);
+        }
+        Ok(command)
+    }
+
+    /// Parse the rest of an `if` statement, after the `if`.
+    fn if_rest(&mut self) -> Result<(CompoundKind, usize), SyntaxError> {
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.nonempty_list(&["then"])?;
+            self.expect_reserved("then")?;
+            let body = self.nonempty_list(&["elif", "else", "fi"])?;
+            branches.push(IfBranch { condition, body });
+            if self.peek_reserved()? != Some("elif") {
+                break;
+            }
+            self.next()?;
+        }
+        let else_body = if self.peek_reserved()? == Some("else") {
+            self.next()?;
+            Some(self.nonempty_list(&["fi"])?)
+        } else {
+            None
+        };
+        Ok((CompoundKind::If { branches, else_body }, self.expect_reserved("fi")?))
+    }
+
+    /// Parse `do body; done`.
+    fn do_group(&mut self) -> Result<(List, usize), SyntaxError> {
+        self.expect_reserved("do")?;
+        let body = self.nonempty_list(&["done"])?;
+        Ok((body, self.expect_reserved("done")?))
+    }
+
+    /// Parse the rest of a `for` loop, after the `for`.
+    fn for_rest(&mut self) -> Result<(CompoundKind, usize), SyntaxError> {
+        let name = match self.next()? {
+            Some(Token { kind: TokenKind::Word(word), span }) => name(&word).ok_or(SyntaxError::Unexpected { offset: span.start })?,
+            token => { return Err(unexpected(self.input, token.as_ref())); },
+        };
+        self.skip_newlines()?;
+        let words = if self.peek_reserved()? == Some("in") {
+            self.next()?;
+            let mut words = Vec::new();
+            loop {
+                match self.next()? {
+                    Some(Token { kind: TokenKind::Word(word), .. }) => words.push(word),
+                    Some(Token { kind: TokenKind::Operator(";"), .. }) | Some(Token { kind: TokenKind::Operator("\n"), .. }) => { break; },
+                    token => { return Err(unexpected(self.input, token.as_ref())); },
+                }
+            }
+            Some(words)
+        } else {
+            // `for name; do`, which POSIX doesn't allow, but shells do.
+            if self.peek_operator()? == Some(";") {
+                self.next()?;
+            }
+            None
+        };
+        self.skip_newlines()?;
+        let (body, end) = self.do_group()?;
+        Ok((CompoundKind::For { name, words, body }, end))
+    }
+
+    /// Parse the rest of a `case` statement, after the `case`.
+    fn case_rest(&mut self) -> Result<(CompoundKind, usize), SyntaxError> {
+        let word = match self.next()? {
+            Some(Token { kind: TokenKind::Word(word), .. }) => word,
+            token => { return Err(unexpected(self.input, token.as_ref())); },
+        };
+        self.skip_newlines()?;
+        self.expect_reserved("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            if self.peek_reserved()? == Some("esac") {
+                break;
+            }
+            let start = self.next_start()?;
+            if self.peek_operator()? == Some("(") {
+                self.next()?;
+            }
+            let mut patterns = Vec::new();
+            let mut end;
+            loop {
+                match self.next()? {
+                    Some(Token { kind: TokenKind::Word(word), .. }) => patterns.push(word),
+                    token => { return Err(unexpected(self.input, token.as_ref())); },
+                }
+                match self.next()? {
+                    Some(Token { kind: TokenKind::Operator("|"), .. }) => {},
+                    Some(Token { kind: TokenKind::Operator(")"), span }) => {
+                        end = span.end;
+                        break;
+                    },
+                    token => { return Err(unexpected(self.input, token.as_ref())); },
+                }
+            }
+            let body = self.list(&["esac"])?;
+            if !body.items.is_empty() {
+                end = body.span.end;
+            }
+            let terminated = self.peek_operator()? == Some(";;");
+            if terminated {
+                end = self.next()?.unwrap().span.end;
+            }
+            items.push(CaseItem { patterns, body, span: start..end });
+            if !terminated {
+                break;
+            }
+        }
+        Ok((CompoundKind::Case { word, items }, self.expect_reserved("esac")?))
+    }
+
+    /// Parse the rest of a function definition, whose name is the only word of `command`.
+    fn function_definition(&mut self, command: SimpleCommand) -> Result<FunctionDefinition, SyntaxError> {
+        let start = command.span.start;
+        let name = name(&command.words[0]).ok_or(SyntaxError::Unexpected { offset: start })?;
+        self.next()?;
+        self.expect_operator(")")?;
+        self.skip_newlines()?;
+        if self.compound_start()?.is_none() {
+            let input = self.input;
+            return Err(unexpected(input, self.peek()?));
+        }
+        let body = self.compound_command()?;
+        let span = start..body.span.end;
+        Ok(FunctionDefinition { name, body, span })
+    }
+
+    /// Consume the operator `op`, returning where it ends.
+    fn expect_operator(&mut self, op: &str) -> Result<usize, SyntaxError> {
+        match self.next()? {
+            Some(Token { kind: TokenKind::Operator(found), span }) if found == op => Ok(span.end),
+            token => Err(unexpected(self.input, token.as_ref())),
+        }
+    }
+
+    /// Consume the reserved word `word`, returning where it ends.
+    fn expect_reserved(&mut self, word: &str) -> Result<usize, SyntaxError> {
+        match self.next()? {
+            Some(Token { kind: TokenKind::Word(found), span }) if reserved_word(&found) == Some(word) => Ok(span.end),
+            token => Err(unexpected(self.input, token.as_ref())),
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, SyntaxError> {
+        let mut command = SimpleCommand::default();
+        let mut span: Option<Range<usize>> = None;
+        loop {
+            let is_redirection = match self.peek()? {
+                Some(Token { kind: TokenKind::Word(_), .. }) => false,
+                Some(Token { kind: TokenKind::IoNumber(_), .. }) => true,
+                Some(Token { kind: TokenKind::Operator(op), .. }) if is_redirection(op) => true,
+                _ => { break; },
+            };
+            let token = self.next()?.unwrap();
+            let start = span.as_ref().map_or(token.span.start, |span| span.start);
+            if is_redirection {
+                let redirection = self.redirection(token)?;
+                span = Some(start..redirection.span.end);
+                command.redirections.push(redirection);
+                continue;
+            }
+            span = Some(start..token.span.end);
+            let word = match token.kind {
+                TokenKind::Word(word) => word,
+                _ => unreachable!(),
+            };
+            match assignment(&word) {
+                Some(assignment) if command.words.is_empty() => command.assignments.push(assignment),
+                _ => command.words.push(word),
+            }
+        }
+        let pos = self.parser.pos;
+        command.span = span.unwrap_or(pos..pos);
+        Ok(command)
+    }
+
+    /// Parse the rest of a redirection, after `token`, which is its operator or file descriptor.
+    fn redirection(&mut self, token: Token) -> Result<Redirection, SyntaxError> {
+        let start = token.span.start;
+        let (fd, op_token) = match token.kind {
+            TokenKind::IoNumber(fd) => (Some(fd), self.next()?.unwrap()),
+            _ => (None, token),
+        };
+        let op = match op_token.kind {
+            TokenKind::Operator(op) => RedirectOp::from_operator(op),
+            _ => None,
+        };
+        let op = op.ok_or(SyntaxError::Unexpected { offset: op_token.span.start })?;
+        match self.next()? {
+            Some(Token { kind: TokenKind::Word(target), span }) => {
+                if op == RedirectOp::HereDoc || op == RedirectOp::HereDocStripTabs {
+                    self.pending.push(PendingHereDoc {
+                        delimiter: target.cooked(),
+                        // Only quoting turns off expansion in the body; `<<$EOF` doesn't.
+                        quoted: target.segments.iter().any(|segment| matches!(segment.node,
+                            Segment::Escaped(_) | Segment::SingleQuoted(_) | Segment::DoubleQuoted(_) | Segment::AnsiC(_))),
+                        strip_tabs: op == RedirectOp::HereDocStripTabs,
+                    });
+                }
+                Ok(Redirection { fd, op, target, here_doc: None, span: start..span.end })
+            },
+            token => Err(unexpected(self.input, token.as_ref())),
+        }
+    }
+}
+
+fn is_redirection(op: &str) -> bool {
+    RedirectOp::from_operator(op).is_some()
+}
+
+/// Words that are special at the start of a command.
+pub(crate) const RESERVED_WORDS: &[&str] = &[
+    "!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for", "if", "in", "then",
+    "until", "while",
+    // bash
+    "[[", "]]", "function", "select",
+];
+
+/// If `word` is an unquoted reserved word, return it.
+fn reserved_word(word: &Word) -> Option<&'static str> {
+    match &word.segments[..] {
+        [Spanned { node: Segment::Literal(text), .. }] => {
+            RESERVED_WORDS.iter().copied().find(|reserved| reserved.as_bytes() == &text[..])
+        },
+        _ => None,
+    }
+}
+
+/// If `word` is an unquoted name, return it.
+fn name(word: &Word) -> Option<String> {
+    match &word.segments[..] {
+        [Spanned { node: Segment::Literal(text), .. }] if is_name_start(text[0]) && text.iter().all(|&c| is_name_char(c)) => {
+            Some(String::from_utf8_lossy(text).into_owned())
+        },
+        _ => None,
+    }
+}
+
+/// If `word` is all digits, unquoted, the file descriptor they make.
+fn io_number(word: &Word) -> Option<u32> {
+    match &word.segments[..] {
+        [Spanned { node: Segment::Literal(text), .. }] if text.iter().all(u8::is_ascii_digit) => {
+            core::str::from_utf8(text).ok()?.parse().ok()
+        },
+        _ => None,
+    }
+}
+
+/// If `word` is an assignment, which starts with an unquoted `NAME=`, split it up.
+pub(crate) fn assignment(word: &Word) -> Option<Assignment> {
+    let first = word.segments.first()?;
+    let text = match &first.node {
+        Segment::Literal(text) => text,
+        _ => { return None; },
+    };
+    let eq = text.iter().position(|&c| c == b'=')?;
+    if eq == 0 || !is_name_start(text[0]) || !text[..eq].iter().all(|&c| is_name_char(c)) {
+        return None;
+    }
+    let name = String::from_utf8_lossy(&text[..eq]).into_owned();
+    // The value starts after the `=`, skipping any line continuations in the source.
+    let source = &word.source()[first.span.start - word.span.start..];
+    let mut offset = 0;
+    let mut skipped = 0;
+    while skipped <= eq {
+        if source[offset..].starts_with(b"\\\n") {
+            offset += 2;
+        } else {
+            offset += 1;
+            skipped += 1;
+        }
+    }
+    let value_start = first.span.start + offset;
+    let mut segments = Vec::with_capacity(word.segments.len());
+    if eq + 1 < text.len() {
+        segments.push(Spanned { node: Segment::Literal(text[eq + 1..].to_vec()), span: value_start..first.span.end });
+    }
+    segments.extend_from_slice(&word.segments[1..]);
+    let value = Word {
+        segments,
+        span: value_start..word.span.end,
+        source: word.source()[value_start - word.span.start..].to_vec(),
+    };
+    Some(Assignment { name, value, span: word.span.clone() })
+}
+
+#[test]
+fn test_simple_command() {
+    let command = parse_simple_command(b"A=1 B='x y' cmd C=2 <in 2>>log 3<>rw 4<&- >|out arg 12 3>&1\n\n").unwrap();
+    let assignments: Vec<_> = command.assignments.iter()
+        .map(|assignment| (&assignment.name[..], assignment.value.cooked(), assignment.span.clone()))
+        .collect();
+    assert_eq!(assignments, [("A", b"1".to_vec(), 0..3), ("B", b"x y".to_vec(), 4..11)]);
+    assert_eq!(command.assignments[1].value.span, 6..11);
+    let words: Vec<_> = command.words.iter().map(Word::cooked).collect();
+    assert_eq!(words, [&b"cmd"[..], b"C=2", b"arg", b"12"]);
+    let redirections: Vec<_> = command.redirections.iter()
+        .map(|redir| (redir.fd, redir.op, redir.target.cooked()))
+        .collect();
+    assert_eq!(redirections, [
+        (None, RedirectOp::Input, b"in".to_vec()),
+        (Some(2), RedirectOp::Append, b"log".to_vec()),
+        (Some(3), RedirectOp::ReadWrite, b"rw".to_vec()),
+        (Some(4), RedirectOp::DupInput, b"-".to_vec()),
+        (None, RedirectOp::Clobber, b"out".to_vec()),
+        (Some(3), RedirectOp::DupOutput, b"1".to_vec()),
+    ]);
+    assert_eq!(command.redirections[1].span, 24..30);
+    assert_eq!(command.span, 0..59);
+
+    // Quoting stops words from being assignments, file descriptors and operators.
+    let command = parse_simple_command(b"'A'=1 \\B=2 A\\\n=3 \"2\">x '>' a\\;b").unwrap();
+    let words: Vec<_> = command.words.iter().map(Word::cooked).collect();
+    assert_eq!(words, [&b"A=1"[..], b"B=2", b"A=3", b"2", b">", b"a;b"]);
+    assert_eq!(command.redirections[0].fd, None);
+
+    let command = parse_simple_command(b"A\\\n=1 B=\\\n2").unwrap();
+    assert_eq!(command.assignments[0].value.span, 4..5);
+    assert_eq!(command.assignments[1].value.span, 8..11);
+    assert_eq!(command.assignments[1].value.cooked(), b"2");
+
+    assert_eq!(parse_simple_command(b""), Ok(SimpleCommand::default()));
+}
+
+#[test]
+fn test_simple_command_errors() {
+    assert_eq!(parse_simple_command(b"a; b"), Err(SyntaxError::Unexpected { offset: 1 }));
+    assert_eq!(parse_simple_command(b"a | b"), Err(SyntaxError::Unexpected { offset: 2 }));
+    assert_eq!(parse_simple_command(b"a >"), Err(SyntaxError::Unexpected { offset: 3 }));
+    assert_eq!(parse_simple_command(b"a > >b"), Err(SyntaxError::Unexpected { offset: 4 }));
+    assert_eq!(parse_simple_command(b"a 2>"), Err(SyntaxError::Unexpected { offset: 4 }));
+    assert_eq!(parse_simple_command(b"a 'b"), Err(SyntaxError::Word(ParseError::Unterminated)));
+}
+
+#[test]
+fn test_parse_list() {
+    let list = parse_list(b"\na | b &&\n ! c || d; e & f\n\n").unwrap();
+    let summary: Vec<_> = list.items.iter().map(|item| {
+        let pipelines: Vec<_> = core::iter::once(&item.and_or.first).chain(item.and_or.rest.iter().map(|(_, p)| p))
+            .map(|pipeline| (pipeline.negated, pipeline.commands.iter().map(|command| command.as_simple().unwrap().words[0].cooked()).collect::<Vec<_>>()))
+            .collect();
+        let ops: Vec<_> = item.and_or.rest.iter().map(|&(op, _)| op).collect();
+        (pipelines, ops, item.background)
+    }).collect();
+    assert_eq!(summary, [
+        (alloc::vec![(false, alloc::vec![b"a".to_vec(), b"b".to_vec()]), (true, alloc::vec![b"c".to_vec()]), (false, alloc::vec![b"d".to_vec()])],
+         alloc::vec![AndOrOp::And, AndOrOp::Or], false),
+        (alloc::vec![(false, alloc::vec![b"e".to_vec()])], alloc::vec![], true),
+        (alloc::vec![(false, alloc::vec![b"f".to_vec()])], alloc::vec![], false),
+    ]);
+    assert_eq!(list.span, 1..26);
+    assert_eq!(list.items[0].and_or.span, 1..19);
+    assert_eq!(list.items[0].and_or.rest[0].1.span, 11..14);
+
+    // Redirections can come first, and reserved words are only special at the start of commands.
+    let list = parse_list(b">log if x; '{' y; \\! z; c=1 fi").unwrap();
+    assert_eq!(list.items.len(), 4);
+    assert_eq!(list.items[0].and_or.first.commands[0].as_simple().unwrap().redirections.len(), 1);
+    assert_eq!(parse_list(b" \n# comment\n"), Ok(List::default()));
+}
+
+#[test]
+fn test_parse_list_errors() {
+    let unsupported = |construct, offset| Err(SyntaxError::Unsupported { construct, offset });
+    assert_eq!(parse_list(b"a; (b)"), unsupported(Construct::Subshell, 3));
+    assert_eq!(parse_list(b"((x = 1))"), unsupported(Construct::BashCommand, 0));
+    assert_eq!(parse_list(b"[[ a < b ]]"), unsupported(Construct::BashCommand, 0));
+    assert_eq!(parse_list(b"a && { b; }"), unsupported(Construct::Group, 5));
+    assert_eq!(parse_list(b"if a; then b; fi"), unsupported(Construct::If, 0));
+    assert_eq!(parse_list(b"x | while read a; do b; done"), unsupported(Construct::Loop, 4));
+    assert_eq!(parse_list(b"for a in b; do c; done"), unsupported(Construct::Loop, 0));
+    assert_eq!(parse_list(b"case a in b) c;; esac"), unsupported(Construct::Case, 0));
+    assert_eq!(parse_list(b"f() { a; }"), unsupported(Construct::FunctionDefinition, 0));
+    assert_eq!(parse_list(b"function f { a; }"), unsupported(Construct::FunctionDefinition, 0));
+
+    let unexpected = |offset| Err(SyntaxError::Unexpected { offset });
+    assert_eq!(parse_list(b"; a"), unexpected(0));
+    assert_eq!(parse_list(b"a;; b"), unexpected(1));
+    assert_eq!(parse_list(b"a | | b"), unexpected(4));
+    assert_eq!(parse_list(b"a &&"), unexpected(4));
+    assert_eq!(parse_list(b"!"), unexpected(1));
+    assert_eq!(parse_list(b"fi"), unexpected(0));
+    assert_eq!(parse_list(b"a )"), unexpected(2));
+}
+
+#[test]
+fn test_parse_script() {
+    let input = b"f() { echo \"$1\"; } >&2
+if a; then b; elif c
+then d; else e; fi
+for x in 1 \"2 3\"; do echo $x; done
+for y; do :; done
+while ! a; do b & done
+case $x in (a|b) one;; c) ;; *) two
+esac
+(cd /; ls) | wc -l
+cat <<A; { cat <<B; }
+a
+A
+b
+B
+";
+    let list = parse_script(input).unwrap();
+    assert_eq!(list.items.len(), 9);
+    let command = |item: usize| &list.items[item].and_or.first.commands[0];
+    let compound = |item: usize| match command(item) {
+        Command::Compound(command) => command,
+        other => panic!("{:?}", other),
+    };
+    let words = |list: &List| -> Vec<Vec<u8>> {
+        list.items.iter().map(|item| item.and_or.first.commands[0].as_simple().unwrap().words[0].cooked()).collect()
+    };
+
+    let definition = match command(0) {
+        Command::FunctionDefinition(definition) => definition,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(definition.name, "f");
+    assert_eq!(definition.span, 0..22);
+    assert!(matches!(&definition.body.kind, CompoundKind::Group(body) if words(body) == [b"echo"]));
+    assert_eq!(definition.body.redirections[0].op, RedirectOp::DupOutput);
+
+    assert_eq!(compound(1).span, 23..62);
+    match &compound(1).kind {
+        CompoundKind::If { branches, else_body } => {
+            assert_eq!(branches.len(), 2);
+            assert_eq!(words(&branches[1].condition), [b"c"]);
+            assert_eq!(words(&branches[1].body), [b"d"]);
+            assert_eq!(words(else_body.as_ref().unwrap()), [b"e"]);
+        },
+        other => panic!("{:?}", other),
+    }
+
+    match &compound(2).kind {
+        CompoundKind::For { name, words: Some(words), body } => {
+            assert_eq!(name, "x");
+            assert_eq!(words.iter().map(Word::cooked).collect::<Vec<_>>(), [&b"1"[..], b"2 3"]);
+            assert_eq!(body.items.len(), 1);
+        },
+        other => panic!("{:?}", other),
+    }
+    assert!(matches!(&compound(3).kind, CompoundKind::For { name, words: None, .. } if name == "y"));
+    match &compound(4).kind {
+        CompoundKind::While { condition, body } => {
+            assert!(condition.items[0].and_or.first.negated);
+            assert!(body.items[0].background);
+        },
+        other => panic!("{:?}", other),
+    }
+
+    assert_eq!(compound(5).span, 139..179);
+    match &compound(5).kind {
+        CompoundKind::Case { word, items } => {
+            assert_eq!(word.source(), b"$x");
+            let patterns: Vec<Vec<_>> = items.iter().map(|item| item.patterns.iter().map(Word::cooked).collect()).collect();
+            assert_eq!(patterns, [alloc::vec![b"a".to_vec(), b"b".to_vec()], alloc::vec![b"c".to_vec()], alloc::vec![b"*".to_vec()]]);
+            assert_eq!(words(&items[0].body), [b"one"]);
+            assert!(items[1].body.items.is_empty());
+            assert_eq!(items[0].span, 150..161);
+            assert_eq!(items[2].span, 168..174);
+        },
+        other => panic!("{:?}", other),
+    }
+
+    let pipeline = &list.items[6].and_or.first;
+    assert_eq!(pipeline.commands.len(), 2);
+    assert!(matches!(&pipeline.commands[0], Command::Compound(CompoundCommand { kind: CompoundKind::Subshell(body), .. }) if body.items.len() == 2));
+
+    // Here-documents inside compound commands are attached in order.
+    let here_doc = |command: &SimpleCommand| command.redirections[0].here_doc.as_ref().unwrap().span.clone();
+    assert_eq!(here_doc(command(7).as_simple().unwrap()), 221..223);
+    match &compound(8).kind {
+        CompoundKind::Group(body) => assert_eq!(here_doc(body.items[0].and_or.first.commands[0].as_simple().unwrap()), 225..227),
+        other => panic!("{:?}", other),
+    }
+
+    // `in` and `]]` are only reserved after `for` or `case`, and after `[[`.
+    assert_eq!(words(&parse_script(b"in foo; ]] x").unwrap()), [b"in", b"]]"]);
+    match &parse_script(b"case in in in) in;; esac").unwrap().items[0].and_or.first.commands[0] {
+        Command::Compound(CompoundCommand { kind: CompoundKind::Case { items, .. }, .. }) => assert_eq!(words(&items[0].body), [b"in"]),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(words(&parse_list(b"in a").unwrap()), [b"in"]);
+}
+
+#[test]
+fn test_parse_script_errors() {
+    let unexpected = |offset| Err(SyntaxError::Unexpected { offset });
+    assert_eq!(parse_script(b"if a; then fi"), unexpected(11));
+    assert_eq!(parse_script(b"if a; b; fi"), unexpected(9));
+    assert_eq!(parse_script(b"{ a }"), unexpected(5));
+    assert_eq!(parse_script(b"( a"), unexpected(3));
+    assert_eq!(parse_script(b"()"), unexpected(1));
+    assert_eq!(parse_script(b"for 1 in a; do b; done"), unexpected(4));
+    assert_eq!(parse_script(b"while a; do b; fi"), unexpected(15));
+    assert_eq!(parse_script(b"case a in b c) d;; esac"), unexpected(12));
+    assert_eq!(parse_script(b"case a in b) d; e) f;; esac"), unexpected(17));
+    assert_eq!(parse_script(b"f() a"), unexpected(4));
+    assert_eq!(parse_script(b"a=1 f() { a; }"), unexpected(5));
+    assert_eq!(parse_script(b"{ a; } b"), unexpected(7));
+
+    let unsupported = |construct, offset| Err(SyntaxError::Unsupported { construct, offset });
+    assert_eq!(parse_script(b"{ [[ a ]]; }"), unsupported(Construct::BashCommand, 2));
+    assert_eq!(parse_script(b"function f { a; }"), unsupported(Construct::FunctionDefinition, 0));
+    assert_eq!(parse_script(b"a; select x in y; do b; done"), unsupported(Construct::BashCommand, 3));
+
+    let nested = |open: &[u8], close: &[u8], depth: usize| {
+        let mut input = open.repeat(depth);
+        input.push(b'a');
+        input.extend(close.repeat(depth));
+        parse_script(&input)
+    };
+    assert!(nested(b"( ", b" )", MAX_NESTING).is_ok());
+    assert_eq!(nested(b"( ", b" )", MAX_NESTING + 1), Err(SyntaxError::TooDeep { offset: 2 * MAX_NESTING }));
+    assert_eq!(nested(b"( ", b" )", 3000), Err(SyntaxError::TooDeep { offset: 2 * MAX_NESTING }));
+    assert_eq!(nested(b"if a; then ", b"; fi", 3000), Err(SyntaxError::TooDeep { offset: 11 * MAX_NESTING }));
+    assert!(nested(b"( echo $(", b") )", MAX_NESTING / 2).is_ok());
+    assert_eq!(nested(b"( echo $(", b") )", 3000), Err(SyntaxError::Word(ParseError::TooDeep)));
+}
+
+#[test]
+fn test_here_docs() {
+    let input = b"cat <<EOF; cat <<-'END' >out\nhello $USER\n\\$x \\y `a\\\"b` \"q\" \\\nEOF\n\tliteral $x\n\t\tEND\necho after <<E\\\nOF\nbody\nEOF\n";
+    let list = parse_list(input).unwrap();
+    assert_eq!(list.items.len(), 3);
+    let here_doc = |item: usize| {
+        let command = list.items[item].and_or.first.commands[0].as_simple().unwrap();
+        command.redirections[0].here_doc.as_ref().unwrap()
+    };
+
+    let first = here_doc(0);
+    assert!(!first.quoted);
+    assert_eq!(&input[first.span.clone()], &b"hello $USER\n\\$x \\y `a\\\"b` \"q\" \\\n"[..]);
+    let body: Vec<_> = first.body.iter().map(|inner| inner.node.clone()).collect();
+    assert!(matches!(&body[1], Inner::Parameter(param) if param.name == "USER"));
+    assert_eq!(body[2], Inner::Literal(b"\n$x \\y ".to_vec()));
+    assert_eq!(body[3], Inner::CommandSubst(crate::word::CommandSubstitution { command: b"a\\\"b".to_vec(), backquoted: true }));
+    assert_eq!(body[4], Inner::Literal(b" \"q\" ".to_vec()));
+    assert_eq!(first.body[1].span, 35..40);
+
+    let second = here_doc(1);
+    assert!(second.quoted);
+    assert_eq!(list.items[1].and_or.first.commands[0].as_simple().unwrap().redirections[0].op, RedirectOp::HereDocStripTabs);
+    assert_eq!(second.body, [Spanned { node: Inner::Literal(b"literal $x\n".to_vec()), span: 65..77 }]);
+    assert_eq!(list.items[1].and_or.first.commands[0].as_simple().unwrap().redirections[1].op, RedirectOp::Output);
+
+    // The delimiter is the quote-removed text of the word, but a line continuation doesn't quote.
+    let third = here_doc(2);
+    assert!(!third.quoted);
+    assert_eq!(third.body, [Spanned { node: Inner::Literal(b"body\n".to_vec()), span: 102..107 }]);
+    let echo = list.items[2].and_or.first.commands[0].as_simple().unwrap();
+    assert_eq!(echo.words[0].cooked(), b"echo");
+    assert_eq!(line_no(input, echo.span.start), 7);
+
+    // A here-document without a delimiter line ends at the end of the input.
+    let command = parse_simple_command(b"cat <<A <<B\nfoo\nA\nbar").unwrap();
+    let bodies: Vec<_> = command.redirections.iter()
+        .map(|redir| redir.here_doc.as_ref().unwrap().body[0].node.clone())
+        .collect();
+    assert_eq!(bodies, [Inner::Literal(b"foo\n".to_vec()), Inner::Literal(b"bar".to_vec())]);
+    let command = parse_simple_command(b"cat <<A").unwrap();
+    assert_eq!(command.redirections[0].here_doc, Some(HereDoc { body: Vec::new(), quoted: false, span: 7..7 }));
+
+    // An unquoted expansion in the delimiter is taken literally, and doesn't make it quoted.
+    let command = parse_simple_command(b"cat <<$EOF\n$x\n$EOF\n").unwrap();
+    let here_doc = command.redirections[0].here_doc.as_ref().unwrap();
+    assert!(!here_doc.quoted);
+    assert_eq!(here_doc.body[0].node, Inner::Parameter(crate::word::Parameter { name: String::from("x"), op: crate::word::ParamOp::Plain }));
+    let command = parse_simple_command(b"cat <<${E}\"\"\n$x\n${E}\n").unwrap();
+    assert!(command.redirections[0].here_doc.as_ref().unwrap().quoted);
+
+    assert_eq!(parse_simple_command(b"cat <<A\n$(\nA\n)"), Err(SyntaxError::Word(ParseError::Unterminated)));
+    assert_eq!(parse_simple_command(b"cat <<"), Err(SyntaxError::Unexpected { offset: 6 }));
+}
@@ -0,0 +1,1338 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Split a string into words like [`split`](crate::split), but also perform parameter expansion
+//! (`$FOO`, `${FOO:-default}`, and so on) the way a POSIX shell would, looking up variables in a
+//! caller-supplied environment.
+//!
+//! Expansion is opt-in and never runs anything: command substitutions (`$(...)` and backquotes)
+//! are rejected with an error rather than executed, unless a handler passed to
+//! [`Expander::command_substitution`] decides what they produce.
+//!
+//! Besides the POSIX forms (`${FOO-default}`, `${FOO=default}`, `${FOO?message}`,
+//! `${FOO+alternative}`, their `:` variants, `${#FOO}`, and `${FOO%suffix}`, `${FOO#prefix}` and
+//! their doubled variants), the bash forms `${FOO/pattern/replacement}` (including `//`, `/#` and
+//! `/%`) and `${FOO:offset:length}` are supported.  Patterns, lengths and offsets work on bytes,
+//! like bash running in the C locale.
+//!
+//! Arithmetic expansion (`$((JOBS * 2))`) supports the POSIX operators, including assignments
+//! and `?:`, on 64-bit integers.  Variables can be referred to with or without a `$`; unset ones
+//! are 0.  Overflow is an error, rather than wrapping around as in most shells.
+//!
+//! Tilde expansion (`~/dir` and `~user/dir`), brace expansion (`file.{c,h}`) and pathname
+//! expansion (`*.rs`) can also be enabled, with [`Expander::tilde`], [`Expander::braces`] and
+//! [`Expander::glob`].
+//!
+//! Quoting is respected the same way the shell does.  Nothing is expanded inside single quotes.
+//! The result of an expansion outside of double quotes is split into multiple words at
+//! whitespace (or the characters set with [`Expander::ifs`]), and disappears entirely if it is
+//! empty; inside double quotes, it is kept as a single word.
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//! let mut vars = BTreeMap::new();
+//! vars.insert("NAME".to_string(), "two words".to_string());
+//! assert_eq!(
+//!     shlex::expand::expand(r#"echo $NAME "$NAME" '$NAME' ${UNSET:-default}"#, &vars),
+//!     Ok(vec!["echo".into(), "two".into(), "words".into(), "two words".into(),
+//!             "$NAME".into(), "default".into()]));
+//! ```
+
+extern crate alloc;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(test)]
+use alloc::vec;
+
+use crate::arith;
+use crate::brace::expand_braces;
+use crate::glob::{glob, FileSystem, NoMatch};
+use crate::pattern::Pattern;
+use crate::word::{is_name_char, is_name_start, Inner, ParamOp, Parameter, ParseError, Parser, ReplaceKind, Segment, Spanned, Word};
+pub use crate::word::CommandSubstitution;
+
+/// The most words brace expansion can make from one call to [`Expander::expand`].
+pub const MAX_BRACE_WORDS: usize = 100_000;
+
+/// A source of variable values for an [`Expander`].
+///
+/// This is implemented for maps from `String` to `String`, and for closures taking a variable
+/// name and returning its value.  For example, to expand using the process's environment:
+///
+/// ```
+/// let vars = |name: &str| std::env::var(name).ok();
+/// assert_eq!(shlex::expand::expand("${SHLEX_SURELY_UNSET-x}", &vars), Ok(vec!["x".into()]));
+/// ```
+pub trait Variables {
+    /// Returns the value of the variable called `name`, or `None` if it's unset.
+    ///
+    /// Besides variable names, `name` may be a positional parameter (like `1`) or a special
+    /// parameter (like `@` or `?`), which are looked up the same way.
+    fn get(&self, name: &str) -> Option<Cow<'_, [u8]>>;
+}
+
+impl<F: Fn(&str) -> Option<String>> Variables for F {
+    fn get(&self, name: &str) -> Option<Cow<'_, [u8]>> {
+        self(name).map(|value| Cow::Owned(value.into_bytes()))
+    }
+}
+
+impl Variables for BTreeMap<String, String> {
+    fn get(&self, name: &str) -> Option<Cow<'_, [u8]>> {
+        BTreeMap::get(self, name).map(|value| Cow::Borrowed(value.as_bytes()))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::hash::BuildHasher> Variables for std::collections::HashMap<String, String, S> {
+    fn get(&self, name: &str) -> Option<Cow<'_, [u8]>> {
+        std::collections::HashMap::get(self, name).map(|value| Cow::Borrowed(value.as_bytes()))
+    }
+}
+
+/// A handler for command substitutions (`$(...)` and `` `...` ``), which decides what they
+/// expand to in place of running them; see [`Expander::command_substitution`].
+///
+/// This is implemented for closures taking a [`CommandSubstitution`] and returning either the
+/// command's output or a reason to reject it.  For example, to record each command and replace it
+/// with `output`:
+///
+/// ```
+/// use shlex::expand::{CommandSubstitution, Expander};
+/// let vars = |_: &str| None;
+/// let mut commands = Vec::new();
+/// let mut handler = |subst: &CommandSubstitution| -> Result<String, String> {
+///     commands.push(String::from_utf8_lossy(&subst.command).into_owned());
+///     Ok("output\n".into())
+/// };
+/// assert_eq!(
+///     Expander::new(&vars).command_substitution(&mut handler).expand("echo $(date +%s) `id -u`"),
+///     Ok(vec!["echo".into(), "output".into(), "output".into()]));
+/// assert_eq!(commands, ["date +%s", "id -u"]);
+/// ```
+pub trait Substitutions {
+    /// Returns the output of `subst`'s command, or a reason for rejecting it.
+    fn substitute(&mut self, subst: &CommandSubstitution) -> Result<Vec<u8>, String>;
+}
+
+impl<F: FnMut(&CommandSubstitution) -> Result<String, String>> Substitutions for F {
+    fn substitute(&mut self, subst: &CommandSubstitution) -> Result<Vec<u8>, String> {
+        self(subst).map(String::into_bytes)
+    }
+}
+
+/// Looks up home directories for tilde expansion; see [`Expander::tilde`].
+pub trait TildeResolver {
+    /// Returns the current user's home directory, for `~`, or `None` to leave the `~` unexpanded.
+    fn home(&self) -> Option<Cow<'_, [u8]>>;
+
+    /// Returns the home directory of `user`, for `~user`, or `None` to leave the `~user`
+    /// unexpanded.
+    fn user_home(&self, user: &str) -> Option<Cow<'_, [u8]>>;
+}
+
+/// The default [`TildeResolver`], which uses the `HOME` environment variable for `~`, and the
+/// passwd database for `~user`.
+///
+/// The passwd database is read from `/etc/passwd`, so users that only exist in other sources
+/// (such as LDAP) are not found.  On non-Unix platforms, `~user` is never expanded.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemTildeResolver;
+
+#[cfg(feature = "std")]
+impl TildeResolver for SystemTildeResolver {
+    fn home(&self) -> Option<Cow<'_, [u8]>> {
+        let home = std::env::var_os("HOME")?;
+        #[cfg(unix)]
+        let home = std::os::unix::ffi::OsStringExt::into_vec(home);
+        #[cfg(not(unix))]
+        let home = home.into_string().ok()?.into_bytes();
+        Some(Cow::Owned(home))
+    }
+
+    fn user_home(&self, user: &str) -> Option<Cow<'_, [u8]>> {
+        if cfg!(unix) {
+            let passwd = std::fs::read("/etc/passwd").ok()?;
+            passwd_home(&passwd, user).map(|home| Cow::Owned(home.to_vec()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Find the home directory of `user` in the contents of an `/etc/passwd` file.
+#[cfg(feature = "std")]
+fn passwd_home<'a>(passwd: &'a [u8], user: &str) -> Option<&'a [u8]> {
+    passwd.split(|&c| c == b'\n').find_map(|line| {
+        let mut fields = line.split(|&c| c == b':');
+        if fields.next()? != user.as_bytes() {
+            return None;
+        }
+        fields.nth(4)
+    })
+}
+
+/// Errors from [`Expander`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExpandError {
+    /// The input ended inside a quotation or a `${...}`, or right after a backslash.
+    Unterminated,
+    /// A `${...}` expression was malformed, or used `=` to assign to a positional or special
+    /// parameter.
+    BadSubstitution,
+    /// A `\u` or `\U` escape in a `$'...'` string was not a valid code point.
+    InvalidEscape,
+    /// Expansions or brace expressions were nested more than
+    /// [`MAX_NESTING`](crate::word::MAX_NESTING) levels deep, or operators and parentheses in an
+    /// arithmetic expression were nested too deeply.
+    TooDeep,
+    /// The input contained a command substitution, which is never executed, and no handler was
+    /// set with [`Expander::command_substitution`].
+    CommandSubstitution,
+    /// The handler set with [`Expander::command_substitution`] rejected a command substitution
+    /// running `command`, for the given `reason`.
+    CommandRejected { command: String, reason: String },
+    /// A `${NAME:?message}` or `${NAME?message}` expression found its parameter unset (or, with the
+    /// colon, empty).  `message` is the expanded message, or a default one if it was omitted.
+    Unset { name: String, message: String },
+    /// A glob pattern didn't match any files, and [`NoMatch::Fail`] was set.
+    NoMatch { pattern: String },
+    /// A `${NAME:offset:length}` expression had a negative length that would end the substring
+    /// before it starts.
+    NegativeSubstring,
+    /// Brace expansion would have made more than [`MAX_BRACE_WORDS`] words.
+    TooManyWords,
+    /// An arithmetic expression, in `$((...))` or a substring offset or length, was malformed.
+    /// `expression` is the expression, after expanding any parameters in it, or the value of
+    /// the variable that was malformed.
+    InvalidArithmetic { expression: String },
+    /// An arithmetic expression divided by zero.
+    DivisionByZero,
+    /// An arithmetic expression overflowed a 64-bit signed integer (including by shifting bits
+    /// out to the left), or shifted by a negative amount or by 64 or more.
+    ArithmeticOverflow,
+    /// The result of expanding was not valid UTF-8, in [`Expander::expand`].  This can only happen
+    /// if a [`Variables`] implementation returns a value that is not valid UTF-8.
+    InvalidUtf8,
+}
+
+impl core::fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExpandError::Unterminated => f.write_str("unterminated quote, escape or parameter expansion"),
+            ExpandError::BadSubstitution => f.write_str("bad substitution"),
+            ExpandError::InvalidEscape => f.write_str("invalid code point in $'...' escape"),
+            ExpandError::TooDeep => f.write_str("expansions nested too deeply"),
+            ExpandError::CommandSubstitution => f.write_str("command substitution is not supported"),
+            ExpandError::CommandRejected { command, reason } => write!(f, "command substitution `{}` rejected: {}", command, reason),
+            ExpandError::Unset { name, message } => write!(f, "{}: {}", name, message),
+            ExpandError::NoMatch { pattern } => write!(f, "no match: {}", pattern),
+            ExpandError::NegativeSubstring => f.write_str("substring expression < 0"),
+            ExpandError::TooManyWords => f.write_str("brace expansion made too many words"),
+            ExpandError::InvalidArithmetic { expression } => write!(f, "syntax error in arithmetic expression: {}", expression),
+            ExpandError::DivisionByZero => f.write_str("division by 0"),
+            ExpandError::ArithmeticOverflow => f.write_str("arithmetic overflow"),
+            ExpandError::InvalidUtf8 => f.write_str("expansion produced invalid UTF-8"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ExpandError {}
+
+impl From<ParseError> for ExpandError {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::Unterminated => ExpandError::Unterminated,
+            ParseError::BadSubstitution => ExpandError::BadSubstitution,
+            ParseError::InvalidEscape => ExpandError::InvalidEscape,
+            ParseError::TooDeep => ExpandError::TooDeep,
+        }
+    }
+}
+
+/// Splits and expands shell words, looking up parameters in a [`Variables`].
+///
+/// Tilde expansion is off by default, and can be enabled with [`Expander::tilde`].
+///
+/// Values assigned with `${NAME:=word}` or in arithmetic expressions like `$((NAME += 1))` are
+/// remembered by the `Expander` (the [`Variables`] is never modified), so they are seen by later
+/// expansions, and can be retrieved with [`Expander::assignments`].
+pub struct Expander<'a> {
+    vars: &'a dyn Variables,
+    tilde: Option<&'a dyn TildeResolver>,
+    braces: bool,
+    fs: Option<&'a dyn FileSystem>,
+    substitutions: Option<&'a mut dyn Substitutions>,
+    no_match: NoMatch,
+    globstar: bool,
+    ifs: Vec<u8>,
+    assigned: BTreeMap<String, Vec<u8>>,
+}
+
+impl<'a> Expander<'a> {
+    /// Create an `Expander` that looks up variables in `vars`.
+    pub fn new<V: Variables>(vars: &'a V) -> Self {
+        Self {
+            vars,
+            tilde: None,
+            braces: false,
+            fs: None,
+            substitutions: None,
+            no_match: NoMatch::default(),
+            globstar: false,
+            ifs: b" \t\n".to_vec(),
+            assigned: BTreeMap::new(),
+        }
+    }
+
+    /// Enable tilde expansion, using `resolver` to look up home directories.  On `std`,
+    /// [`SystemTildeResolver`] is the usual choice.
+    ///
+    /// Like in the shell, a tilde is only expanded if it is unquoted, and either at the start of a
+    /// word or (in a word that looks like an assignment, such as `PATH=~/bin:~alice/bin`) right
+    /// after the `=` or a `:`.  The tilde and the following characters up to the next `/` (or `:`
+    /// in assignments) are replaced with the home directory; if any of those characters are
+    /// quoted, or the resolver doesn't know the user, nothing is expanded.
+    #[inline]
+    pub fn tilde<T: TildeResolver>(mut self, resolver: &'a T) -> Self {
+        self.tilde = Some(resolver);
+        self
+    }
+
+    /// Enable bash-style brace expansion, which turns a word like `file.{c,h}` into `file.c
+    /// file.h`.  The default is `false`.
+    ///
+    /// Supported are lists of alternatives (`{a,b,c}`, which can be nested), numeric sequences
+    /// (`{1..10}`, with an optional increment as in `{1..10..2}`, and zero-padded if either end
+    /// is, as in `{01..10}`), and character sequences (`{a..z}`).  Braces and commas only count
+    /// if they're unquoted.  Like in bash, brace expansion happens before any other expansion,
+    /// so `{$A,$B}` expands to the values of `A` and `B`, but `$A` can't expand to a brace
+    /// expression.  Making more than [`MAX_BRACE_WORDS`] words in all is an error.
+    #[inline]
+    pub fn braces(mut self, braces: bool) -> Self {
+        self.braces = braces;
+        self
+    }
+
+    /// Enable pathname expansion ("globbing"), matching words with unquoted `*`, `?` or `[...]`
+    /// against the files in `fs`.  On `std`, [`StdFileSystem`](crate::glob::StdFileSystem) is
+    /// the usual choice.
+    ///
+    /// Like in the shell, a word that matches is replaced by the matching paths, in sorted
+    /// order, and a word that doesn't is handled according to [`Expander::no_match`].  Files
+    /// whose names start with `.` only match if the pattern for that path component starts with
+    /// `.` too.  Pattern characters only count if they're unquoted, so `'*.rs'` and `\*.rs` are
+    /// left alone, but they can come from unquoted expansions: if `P` is `*.rs`, `$P` is expanded.
+    #[inline]
+    pub fn glob<F: FileSystem>(mut self, fs: &'a F) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Set what a glob pattern that doesn't match anything expands to.  The default is
+    /// [`NoMatch::Literal`].
+    #[inline]
+    pub fn no_match(mut self, no_match: NoMatch) -> Self {
+        self.no_match = no_match;
+        self
+    }
+
+    /// Set whether a path component of exactly `**` in a glob pattern matches any number of
+    /// directories, like bash's `globstar` option.  The default is `false`, in which case it's
+    /// equivalent to `*`.
+    ///
+    /// `**` doesn't descend into directories whose names start with `.`, or into symbolic links.
+    #[inline]
+    pub fn globstar(mut self, globstar: bool) -> Self {
+        self.globstar = globstar;
+        self
+    }
+
+    /// Handle command substitutions with `handler`, rather than rejecting them with
+    /// [`ExpandError::CommandSubstitution`].
+    ///
+    /// Like in the shell, trailing newlines are removed from the output that `handler` returns,
+    /// and unless the substitution is in double quotes, the output is split into words.  If
+    /// `handler` rejects a substitution, expansion fails with [`ExpandError::CommandRejected`].
+    #[inline]
+    pub fn command_substitution<S: Substitutions>(mut self, handler: &'a mut S) -> Self {
+        self.substitutions = Some(handler);
+        self
+    }
+
+    /// Set the characters that the results of unquoted expansions are split at, like the shell's
+    /// `IFS` variable.  The default is space, tab and newline.  If `ifs` is empty, nothing is
+    /// split.
+    ///
+    /// As in POSIX, spaces, tabs and newlines in `ifs` are treated differently from other
+    /// characters: a run of them counts as one separator, and they're ignored at the start and
+    /// end of the result, whereas each other character separates two words, which may be empty.
+    /// With an `ifs` of `:`, if `P` is `a::b:`, `$P` expands to the three words `a`, `` and `b`.
+    ///
+    /// Only the results of expansions are split, never literal text in the input, which is
+    /// always split into words at whitespace.
+    #[inline]
+    pub fn ifs<S: AsRef<[u8]>>(mut self, ifs: S) -> Self {
+        self.ifs = ifs.as_ref().to_vec();
+        self
+    }
+
+    /// Split `in_str` into words and expand them.
+    pub fn expand(&mut self, in_str: &str) -> Result<Vec<String>, ExpandError> {
+        self.expand_bytes(in_str.as_bytes())?
+            .into_iter()
+            .map(|word| String::from_utf8(word).map_err(|_| ExpandError::InvalidUtf8))
+            .collect()
+    }
+
+    /// Split `in_bytes` into words and expand them.
+    pub fn expand_bytes(&mut self, in_bytes: &[u8]) -> Result<Vec<Vec<u8>>, ExpandError> {
+        let mut parser = Parser::new(in_bytes);
+        let mut fields = Fields::new(Some(self.ifs.clone()));
+        let mut brace_words = 0;
+        while let Some(word) = parser.next_word()? {
+            if self.braces {
+                let words = expand_braces(&word, MAX_BRACE_WORDS - brace_words)?;
+                brace_words += words.len();
+                for word in words {
+                    self.expand_segments(&word.segments, false, false, Tilde::Word, &mut fields)?;
+                    fields.end_field();
+                }
+            } else {
+                self.expand_segments(&word.segments, false, false, Tilde::Word, &mut fields)?;
+                fields.end_field();
+            }
+        }
+        let fs = match self.fs {
+            Some(fs) => fs,
+            None => { return Ok(fields.fields.into_iter().map(|(text, _)| text).collect()); },
+        };
+        let mut words = Vec::new();
+        for (text, quoted) in fields.fields {
+            match glob(fs, &text, &quoted, self.globstar) {
+                Some(paths) if !paths.is_empty() => words.extend(paths),
+                Some(_) if self.no_match == NoMatch::Null => {},
+                Some(_) if self.no_match == NoMatch::Fail => {
+                    return Err(ExpandError::NoMatch { pattern: String::from_utf8_lossy(&text).into_owned() });
+                },
+                _ => words.push(text),
+            }
+        }
+        Ok(words)
+    }
+
+    /// Returns the variables assigned by `${NAME:=word}` or `${NAME=word}` expressions, or by
+    /// arithmetic expressions, so far.
+    pub fn assignments(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.assigned
+    }
+
+    fn lookup(&self, name: &str) -> Option<Vec<u8>> {
+        match self.assigned.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.vars.get(name).map(Cow::into_owned),
+        }
+    }
+
+    /// Expand `segments`, appending the results to `out`.  `dquoted` is whether the segments are
+    /// inside double quotes; `split_literals` is whether unquoted literal text should be field
+    /// split, which is the case inside `${NAME:-word}`, since it's part of the expansion.
+    fn expand_segments(&mut self, segments: &[Spanned<Segment>], dquoted: bool, split_literals: bool, tilde: Tilde, out: &mut Fields) -> Result<(), ExpandError> {
+        // Whether we're at a position where a tilde prefix can start.
+        let mut at_tilde_start = tilde != Tilde::Off;
+        let mut assignment = false;
+        for (i, segment) in segments.iter().enumerate() {
+            match &segment.node {
+                Segment::Literal(text) => {
+                    let split = split_literals && !dquoted;
+                    match self.tilde {
+                        Some(resolver) if (at_tilde_start || assignment) && !dquoted => {
+                            let mut text = &text[..];
+                            if i == 0 && tilde == Tilde::Word {
+                                if let Some(len) = assignment_prefix_len(text) {
+                                    out.push(&text[..len], false);
+                                    text = &text[len..];
+                                    assignment = true;
+                                }
+                            }
+                            let last = i + 1 == segments.len();
+                            push_tilde_literal(resolver, text, split, assignment, last, &mut at_tilde_start, out);
+                            continue;
+                        },
+                        _ if split => out.push_split(text),
+                        _ => out.push(text, dquoted),
+                    }
+                },
+                Segment::Escaped(c) => out.push(&[*c], true),
+                Segment::SingleQuoted(text) | Segment::AnsiC(text) => out.push(text, true),
+                Segment::DoubleQuoted(inners) => {
+                    // Even `""` produces a word.
+                    out.push(b"", true);
+                    for inner in inners {
+                        match &inner.node {
+                            Inner::Literal(text) => out.push(text, true),
+                            Inner::Parameter(param) => self.expand_parameter(param, true, out)?,
+                            Inner::Arithmetic(word) => {
+                                let value = self.expand_arithmetic(word)?;
+                                out.push(value.to_string().as_bytes(), true);
+                            },
+                            Inner::CommandSubst(subst) => {
+                                let output = self.substitute(subst)?;
+                                out.push(&output, true);
+                            },
+                        }
+                    }
+                },
+                Segment::Parameter(param) => self.expand_parameter(param, dquoted, out)?,
+                Segment::Arithmetic(word) => {
+                    let value = self.expand_arithmetic(word)?;
+                    out.push_value(value.to_string().as_bytes(), dquoted);
+                },
+                Segment::CommandSubst(subst) => {
+                    let output = self.substitute(subst)?;
+                    out.push_value(&output, dquoted);
+                },
+            }
+            at_tilde_start = false;
+        }
+        Ok(())
+    }
+
+    fn expand_parameter(&mut self, param: &Parameter, dquoted: bool, out: &mut Fields) -> Result<(), ExpandError> {
+        let value = self.lookup(&param.name);
+        let (colon, word) = match &param.op {
+            ParamOp::Default { colon, word } |
+            ParamOp::Assign { colon, word } |
+            ParamOp::Error { colon, word } |
+            ParamOp::Alternative { colon, word } => (*colon, word),
+            op => {
+                let result = match value {
+                    Some(value) => self.transform(&value, op)?,
+                    None if *op == ParamOp::Length => b"0".to_vec(),
+                    // bash doesn't even try to match patterns against unset parameters; `${FOO/*/x}`
+                    // is empty rather than `x`.
+                    None => Vec::new(),
+                };
+                out.push_value(&result, dquoted);
+                return Ok(());
+            },
+        };
+        let value = value.filter(|value| !(colon && value.is_empty()));
+        let tilde = if dquoted { Tilde::Off } else { Tilde::Start };
+        match (&param.op, value) {
+            (ParamOp::Alternative { .. }, Some(_)) => {
+                self.expand_segments(&word.segments, dquoted, true, tilde, out)?;
+            },
+            (ParamOp::Alternative { .. }, None) => {},
+            (_, Some(value)) => out.push_value(&value, dquoted),
+            (ParamOp::Default { .. }, None) => {
+                self.expand_segments(&word.segments, dquoted, true, tilde, out)?;
+            },
+            (ParamOp::Assign { .. }, None) => {
+                if !is_name_start(param.name.as_bytes()[0]) {
+                    return Err(ExpandError::BadSubstitution);
+                }
+                let (value, _) = self.expand_flat(word, tilde)?;
+                out.push_value(&value, dquoted);
+                self.assigned.insert(param.name.clone(), value);
+            },
+            (ParamOp::Error { .. }, None) => {
+                let message = if word.segments.is_empty() {
+                    String::from("parameter null or not set")
+                } else {
+                    // bash expands tildes here even in double quotes.
+                    String::from_utf8_lossy(&self.expand_flat(word, Tilde::Start)?.0).into_owned()
+                };
+                return Err(ExpandError::Unset { name: param.name.clone(), message });
+            },
+            (_, None) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Apply one of the operators that transform a parameter's value, rather than choosing
+    /// between it and a word.
+    fn transform(&mut self, value: &[u8], op: &ParamOp) -> Result<Vec<u8>, ExpandError> {
+        Ok(match op {
+            ParamOp::Plain => value.to_vec(),
+            ParamOp::Length => value.len().to_string().into_bytes(),
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let start = pattern.match_suffix(value, *longest).unwrap_or(value.len());
+                value[..start].to_vec()
+            },
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let len = pattern.match_prefix(value, *longest).unwrap_or(0);
+                value[len..].to_vec()
+            },
+            ParamOp::Replace { kind, pattern, replacement } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let (replacement, quoted) = match replacement {
+                    Some(replacement) => self.expand_flat(replacement, Tilde::Start)?,
+                    None => (Vec::new(), Vec::new()),
+                };
+                replace(value, &pattern, *kind, &replacement, &quoted)
+            },
+            ParamOp::Substring { offset, length } => {
+                let offset = self.expand_arithmetic(offset)?;
+                let length = match length {
+                    Some(length) => Some(self.expand_arithmetic(length)?),
+                    None => None,
+                };
+                substring(value, offset, length)?.to_vec()
+            },
+            _ => unreachable!(),
+        })
+    }
+
+    /// Expand `word` into a single string, without splitting it, as for the value of an
+    /// assignment.  Also returns whether each byte was quoted.
+    fn expand_flat(&mut self, word: &Word, tilde: Tilde) -> Result<(Vec<u8>, Vec<bool>), ExpandError> {
+        let mut fields = Fields::new(None);
+        self.expand_segments(&word.segments, false, false, tilde, &mut fields)?;
+        Ok((fields.cur, fields.cur_quoted))
+    }
+
+    fn expand_pattern(&mut self, word: &Word) -> Result<Pattern, ExpandError> {
+        // Patterns are not affected by surrounding double quotes, including for tilde expansion.
+        let (text, quoted) = self.expand_flat(word, Tilde::Start)?;
+        Ok(Pattern::new(&text, &quoted))
+    }
+
+    /// Get the output of a command substitution from the handler.
+    fn substitute(&mut self, subst: &CommandSubstitution) -> Result<Vec<u8>, ExpandError> {
+        let handler = self.substitutions.as_mut().ok_or(ExpandError::CommandSubstitution)?;
+        let mut output = handler.substitute(subst).map_err(|reason| ExpandError::CommandRejected {
+            command: String::from_utf8_lossy(&subst.command).into_owned(),
+            reason,
+        })?;
+        while output.last() == Some(&b'\n') {
+            output.pop();
+        }
+        Ok(output)
+    }
+
+    /// Expand `word`, and evaluate the result as an arithmetic expression.
+    fn expand_arithmetic(&mut self, word: &Word) -> Result<i64, ExpandError> {
+        let (text, _) = self.expand_flat(word, Tilde::Off)?;
+        arith::evaluate(&text, self)
+    }
+}
+
+impl arith::Env for Expander<'_> {
+    fn get(&mut self, name: &str) -> Option<Vec<u8>> {
+        self.lookup(name)
+    }
+
+    fn set(&mut self, name: &str, value: i64) {
+        self.assigned.insert(String::from(name), value.to_string().into_bytes());
+    }
+}
+
+/// Where tilde expansion applies in a sequence of segments, if it's enabled.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tilde {
+    Off,
+    /// At the start.
+    Start,
+    /// At the start, or, if the segments look like an assignment, after the `=` and each `:`.
+    Word,
+}
+
+/// If `text` starts with `NAME=`, returns its length.
+fn assignment_prefix_len(text: &[u8]) -> Option<usize> {
+    if !is_name_start(*text.first()?) {
+        return None;
+    }
+    let len = text.iter().position(|&c| !is_name_char(c))?;
+    if text[len] == b'=' { Some(len + 1) } else { None }
+}
+
+/// Append an unquoted literal segment, expanding any tilde prefixes in it.  `at_start` is whether
+/// `text` starts where a tilde prefix can; it's updated to whether the end of `text` is such a
+/// place.  `last` is whether this is the last segment of its word.
+fn push_tilde_literal(resolver: &dyn TildeResolver, text: &[u8], split: bool, assignment: bool, last: bool, at_start: &mut bool, out: &mut Fields) {
+    let mut i = 0;
+    while i < text.len() {
+        if *at_start && text[i] == b'~' {
+            let end = text[i..]
+                .iter()
+                .position(|&c| c == b'/' || (assignment && c == b':'))
+                .map_or(text.len(), |len| i + len);
+            // If the prefix continues into the next segment, part of it is quoted or expanded,
+            // so it doesn't count.
+            if end < text.len() || last {
+                let user = &text[i + 1..end];
+                let home = if user.is_empty() {
+                    resolver.home()
+                } else {
+                    core::str::from_utf8(user).ok().and_then(|user| resolver.user_home(user))
+                };
+                if let Some(home) = home {
+                    // The result is not split or used as a pattern.
+                    out.push(&home, true);
+                    i = end;
+                }
+            }
+        }
+        // In assignments, copy up to and including the next `:`, after which another tilde
+        // prefix can start.
+        let end = match text[i..].iter().position(|&c| assignment && c == b':') {
+            Some(len) => i + len + 1,
+            None => text.len(),
+        };
+        *at_start = end > i && assignment && text[end - 1] == b':';
+        if split {
+            out.push_split(&text[i..end]);
+        } else {
+            out.push(&text[i..end], false);
+        }
+        i = end;
+    }
+}
+
+/// Replace matches of `pattern` in `text`, as `${FOO/pattern/replacement}` does.  Each unquoted
+/// `&` in the replacement stands for the matched text, as in bash 5.2.
+fn replace(text: &[u8], pattern: &Pattern, kind: ReplaceKind, replacement: &[u8], quoted: &[bool]) -> Vec<u8> {
+    let substitute = |out: &mut Vec<u8>, matched: &[u8]| {
+        for (&c, &quoted) in replacement.iter().zip(quoted) {
+            if c == b'&' && !quoted {
+                out.extend_from_slice(matched);
+            } else {
+                out.push(c);
+            }
+        }
+    };
+    let mut out = Vec::new();
+    match kind {
+        ReplaceKind::Prefix => match pattern.match_prefix(text, true) {
+            Some(len) => {
+                substitute(&mut out, &text[..len]);
+                out.extend_from_slice(&text[len..]);
+            },
+            None => { return text.to_vec(); },
+        },
+        ReplaceKind::Suffix => match pattern.match_suffix(text, true) {
+            Some(start) => {
+                out.extend_from_slice(&text[..start]);
+                substitute(&mut out, &text[start..]);
+            },
+            None => { return text.to_vec(); },
+        },
+        ReplaceKind::First | ReplaceKind::All => {
+            if pattern.is_empty() {
+                return text.to_vec();
+            }
+            let mut pos = 0;
+            while pos <= text.len() {
+                match pattern.match_prefix(&text[pos..], true) {
+                    // An empty match only counts if the whole text is empty; otherwise it could
+                    // only happen at the end.
+                    Some(len) if len > 0 || text.is_empty() => {
+                        substitute(&mut out, &text[pos..pos + len]);
+                        pos += len;
+                        if kind == ReplaceKind::First || len == 0 {
+                            break;
+                        }
+                    },
+                    _ if pos < text.len() => {
+                        out.push(text[pos]);
+                        pos += 1;
+                    },
+                    _ => { break; },
+                }
+            }
+            out.extend_from_slice(&text[pos..]);
+        },
+    }
+    out
+}
+
+/// Take a substring of `text`, as `${FOO:offset:length}` does.  Negative offsets and lengths count
+/// from the end.
+fn substring(text: &[u8], offset: i64, length: Option<i64>) -> Result<&[u8], ExpandError> {
+    let len = text.len() as i64;
+    let start = if offset < 0 { offset + len } else { offset };
+    if start < 0 || start > len {
+        return Ok(&[]);
+    }
+    let end = match length {
+        None => len,
+        Some(length) if length < 0 => {
+            let end = len + length;
+            if end < start {
+                return Err(ExpandError::NegativeSubstring);
+            }
+            end
+        },
+        Some(length) => len.min(start.saturating_add(length)),
+    };
+    Ok(&text[start as usize..end as usize])
+}
+
+/// Split `in_str` into words and expand them, looking up parameters in `vars`.
+///
+/// This is a shorthand for [`Expander::new`] followed by [`Expander::expand`].
+pub fn expand<V: Variables>(in_str: &str, vars: &V) -> Result<Vec<String>, ExpandError> {
+    Expander::new(vars).expand(in_str)
+}
+
+/// The words produced so far by expansion, plus the one in progress.
+struct Fields {
+    /// Each word, and whether each of its bytes was quoted.
+    fields: Vec<(Vec<u8>, Vec<bool>)>,
+    cur: Vec<u8>,
+    /// Whether each byte of `cur` was quoted.
+    cur_quoted: Vec<bool>,
+    /// Whether the word in progress exists, even if empty.  A word exists once it contains any
+    /// text, or any quotes.
+    cur_exists: bool,
+    /// The characters unquoted expansions are split at, or `None` if they aren't split, as when
+    /// expanding something that always produces one string, like a pattern.
+    ifs: Option<Vec<u8>>,
+    /// The kind of separator that was last split at, if nothing has been appended since.
+    delimiter: Option<Delimiter>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delimiter {
+    /// Space, tab or newline.
+    Whitespace,
+    /// Any other character in `IFS`.
+    Other,
+}
+
+impl Fields {
+    fn new(ifs: Option<Vec<u8>>) -> Self {
+        Fields { fields: Vec::new(), cur: Vec::new(), cur_quoted: Vec::new(), cur_exists: false, ifs, delimiter: None }
+    }
+
+    /// Append text that is not subject to splitting.
+    fn push(&mut self, text: &[u8], quoted: bool) {
+        self.cur.extend_from_slice(text);
+        self.cur_quoted.resize(self.cur.len(), quoted);
+        self.cur_exists = true;
+        self.delimiter = None;
+    }
+
+    /// Append unquoted text that is split into separate words at `IFS` characters.
+    fn push_split(&mut self, text: &[u8]) {
+        if self.ifs.is_none() {
+            self.push(text, false);
+            return;
+        }
+        for &c in text {
+            match self.delimiter_kind(c) {
+                None => self.push(&[c], false),
+                Some(Delimiter::Whitespace) => {
+                    // A run of whitespace ends the word in progress, if there is one.  After
+                    // another separator, or at the start of a word, it's ignored.
+                    if self.delimiter.is_none() && self.cur_exists {
+                        self.end_field();
+                        self.delimiter = Some(Delimiter::Whitespace);
+                    }
+                },
+                Some(Delimiter::Other) => {
+                    // Other separators always end a word, even an empty one, unless they're part
+                    // of the same separator as the preceding whitespace.
+                    if self.delimiter != Some(Delimiter::Whitespace) {
+                        self.cur_exists = true;
+                        self.end_field();
+                    }
+                    self.delimiter = Some(Delimiter::Other);
+                },
+            }
+        }
+    }
+
+    fn delimiter_kind(&self, c: u8) -> Option<Delimiter> {
+        match &self.ifs {
+            Some(ifs) if ifs.contains(&c) => {
+                Some(if matches!(c, b' ' | b'\t' | b'\n') { Delimiter::Whitespace } else { Delimiter::Other })
+            },
+            _ => None,
+        }
+    }
+
+    /// Append the value of a parameter, which is split unless it's in double quotes.
+    fn push_value(&mut self, value: &[u8], dquoted: bool) {
+        if dquoted {
+            self.push(value, true);
+        } else {
+            self.push_split(value);
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.cur_exists {
+            self.fields.push((core::mem::take(&mut self.cur), core::mem::take(&mut self.cur_quoted)));
+            self.cur_exists = false;
+        }
+        self.delimiter = None;
+    }
+}
+
+#[cfg(test)]
+static EXPAND_TEST_ITEMS: &[(&str, Result<&[&str], ExpandError>)] = &[
+    ("$FOO", Ok(&["foo"])),
+    ("${FOO}bar", Ok(&["foobar"])),
+    ("$FOO-bar", Ok(&["foo-bar"])),
+    ("$SPACED", Ok(&["a", "b"])),
+    ("x${SPACED}y", Ok(&["x", "a", "b", "y"])),
+    ("\"$SPACED\"", Ok(&["  a  b  "])),
+    ("'$FOO' \\$FOO \"\\$FOO\"", Ok(&["$FOO", "$FOO", "$FOO"])),
+    ("$UNSET $EMPTY", Ok(&[])),
+    ("\"$UNSET\" ''$EMPTY", Ok(&["", ""])),
+    ("a$ $ \"$\" $/", Ok(&["a$", "$", "$", "$/"])),
+    ("$1 ${10} $# $@", Ok(&["one", "ten"])),
+    ("${UNSET-a b} \"${UNSET-a b}\" ${UNSET-\"a b\"}", Ok(&["a", "b", "a b", "a b"])),
+    ("${EMPTY-x} ${EMPTY:-x} ${FOO:-x}", Ok(&["x", "foo"])),
+    ("${EMPTY:-''}", Ok(&[""])),
+    ("${UNSET:-$FOO}", Ok(&["foo"])),
+    ("${UNSET:-\"$SPACED\"}", Ok(&["  a  b  "])),
+    ("\"${UNSET:-'x'}\"", Ok(&["'x'"])),
+    ("${FOO+alt} ${EMPTY+alt} ${EMPTY:+alt} ${UNSET+alt}", Ok(&["alt", "alt"])),
+    ("${FOO:?} ${FOO?oops}", Ok(&["foo", "foo"])),
+    ("${UNSET?}", Err(ExpandError::Unset { name: String::new(), message: String::new() })),
+    ("${FOO", Err(ExpandError::Unterminated)),
+    ("${FOO:-x", Err(ExpandError::Unterminated)),
+    ("'", Err(ExpandError::Unterminated)),
+    ("${}", Err(ExpandError::BadSubstitution)),
+    ("${FOO BAR}", Err(ExpandError::BadSubstitution)),
+    ("${2:=x}", Err(ExpandError::BadSubstitution)),
+    ("${FOO%o} ${FOO%%o*} ${FOO#f} ${FOO/o/0} ${FOO//o/0} ${#FOO} ${FOO:1:1}", Ok(&["fo", "f", "oo", "f0o", "f00", "3", "o"])),
+    ("${FOO:1:-3}", Err(ExpandError::NegativeSubstring)),
+    ("${FOO:x} ${FOO:1+1}", Ok(&["foo", "o"])),
+    ("${FOO:}", Err(ExpandError::BadSubstitution)),
+    ("$((1 + 2 * 3)) \"$(( (1 + 2) * 3 ))\" $(($1 + 1)) x$((-1))", Ok(&["7", "9", "1", "x-1"])),
+    ("$((9223372036854775807 + 1))", Err(ExpandError::ArithmeticOverflow)),
+    ("$((9223372036854775808))", Err(ExpandError::ArithmeticOverflow)),
+    ("$((1 << 64))", Err(ExpandError::ArithmeticOverflow)),
+    ("$((1 << 62)) $((-1 << 63)) $((-3 << 61))", Ok(&["4611686018427387904", "-9223372036854775808", "-6917529027641081856"])),
+    ("$((3 << 62))", Err(ExpandError::ArithmeticOverflow)),
+    ("$((1 << 63))", Err(ExpandError::ArithmeticOverflow)),
+    ("$((1 / (FOO - FOO)))", Err(ExpandError::DivisionByZero)),
+    ("$((1 +))", Err(ExpandError::InvalidArithmetic { expression: String::new() })),
+    ("$((LOOP))", Err(ExpandError::InvalidArithmetic { expression: String::new() })),
+    ("$((1 2))", Err(ExpandError::InvalidArithmetic { expression: String::new() })),
+    ("$((1) )", Err(ExpandError::CommandSubstitution)),
+    ("$((1", Err(ExpandError::Unterminated)),
+    ("${#FOO:-x}", Err(ExpandError::BadSubstitution)),
+    ("$(true)", Err(ExpandError::CommandSubstitution)),
+    ("\"`true`\"", Err(ExpandError::CommandSubstitution)),
+];
+
+#[cfg(test)]
+fn test_vars() -> BTreeMap<String, String> {
+    let mut vars = BTreeMap::new();
+    for &(name, value) in &[("FOO", "foo"), ("SPACED", "  a  b  "), ("EMPTY", ""), ("1", "one"), ("10", "ten"), ("LOOP", "LOOP + 1")] {
+        vars.insert(String::from(name), String::from(value));
+    }
+    vars
+}
+
+#[test]
+fn test_expand() {
+    let vars = test_vars();
+    for &(input, ref expected) in EXPAND_TEST_ITEMS {
+        let result = expand(input, &vars);
+        match (&result, expected) {
+            // Don't bother spelling out messages in the table.
+            (Err(ExpandError::Unset { .. }), Err(ExpandError::Unset { .. })) |
+            (Err(ExpandError::InvalidArithmetic { .. }), Err(ExpandError::InvalidArithmetic { .. })) => {},
+            (_, _) => {
+                let expected = expected.clone().map(|words| words.iter().map(|&w| String::from(w)).collect::<Vec<_>>());
+                assert_eq!(result, expected, "expanding {:?}", input);
+            },
+        }
+    }
+}
+
+#[test]
+fn test_expand_assign_and_error() {
+    let vars = test_vars();
+    let mut expander = Expander::new(&vars);
+    assert_eq!(expander.expand("${NEW:=a  b} $NEW \"${FOO:=x}\""),
+               Ok(vec![String::from("a"), String::from("b"), String::from("a"),
+                       String::from("b"), String::from("foo")]));
+    assert_eq!(expander.assignments().get("NEW").map(|v| &v[..]), Some(&b"a  b"[..]));
+    assert_eq!(expander.assignments().get("FOO"), None);
+    assert_eq!(expander.expand("${EMPTY:?is $FOO}"),
+               Err(ExpandError::Unset { name: String::from("EMPTY"), message: String::from("is foo") }));
+    assert_eq!(expander.expand("${UNSET?}"),
+               Err(ExpandError::Unset { name: String::from("UNSET"), message: String::from("parameter null or not set") }));
+
+    assert_eq!(expander.expand("$((A = 2, 1)) $((A *= 3)) $A $((B += A)) $((0 && (C = 1))) ${C-unset}"),
+               Err(ExpandError::InvalidArithmetic { expression: String::from("A = 2, 1") }));
+    assert_eq!(expander.expand("$((A = 2)) $((A *= 3)) $A $((B += A)) $((0 && (C = 1))) ${C-unset}"),
+               Ok(["2", "6", "6", "6", "0", "unset"].iter().map(|&w| String::from(w)).collect::<Vec<_>>()));
+    assert_eq!(expander.assignments().get("B").map(|v| &v[..]), Some(&b"6"[..]));
+
+    let closure = |name: &str| if name == "X" { Some(String::from("y")) } else { None };
+    assert_eq!(expand("$X$Y", &closure), Ok(vec![String::from("y")]));
+
+    let mut nested = "\"${A:-".repeat(10_000);
+    nested.push_str(&"}\"".repeat(10_000));
+    assert_eq!(expander.expand(&nested), Err(ExpandError::TooDeep));
+    let nested = |open: &str, close: &str, depth: usize| {
+        let mut expr = String::from("$((");
+        expr.push_str(&open.repeat(depth));
+        expr.push('1');
+        expr.push_str(&close.repeat(depth));
+        expr.push_str("))");
+        expr
+    };
+    assert_eq!(expander.expand(&nested("(", ")", 256)), Ok(vec![String::from("1")]));
+    assert_eq!(expander.expand(&nested("- ", "", 255)), Ok(vec![String::from("-1")]));
+    assert_eq!(expander.expand(&nested("1 ? 1 : ", "", 255)), Ok(vec![String::from("1")]));
+    assert_eq!(expander.expand(&nested("A = ", "", 255)), Ok(vec![String::from("1")]));
+    assert_eq!(expander.expand(&nested("(", ")", 257)), Err(ExpandError::TooDeep));
+    assert_eq!(expander.expand(&nested("(", ")", 50_000)), Err(ExpandError::TooDeep));
+    assert_eq!(expander.expand(&nested("- ", "", 200_000)), Err(ExpandError::TooDeep));
+    assert_eq!(expander.expand(&nested("0 ? 1 : ", "", 50_000)), Err(ExpandError::TooDeep));
+    assert_eq!(expander.expand(&nested("A = ", "", 50_000)), Err(ExpandError::TooDeep));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_arithmetic_nesting_in_variables() {
+    // Variables whose values nest parentheses count toward one limit, so this fails cleanly
+    // instead of overflowing a 2 MB stack, even in a debug build.
+    let thread = std::thread::Builder::new().stack_size(2 * 1024 * 1024).spawn(|| {
+        let chain = |levels: usize, parens: usize| -> BTreeMap<String, String> {
+            (0..levels).map(|i| (format!("V{}", i), format!("{}V{}{}", "(".repeat(parens), i + 1, ")".repeat(parens)))).collect()
+        };
+        let vars = chain(70, 250);
+        assert_eq!(Expander::new(&vars).expand("$((V0))"), Err(ExpandError::TooDeep));
+        let vars = chain(4, 50);
+        assert_eq!(Expander::new(&vars).expand("$((V0 + 1))"), Ok(vec![String::from("1")]));
+    }).unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+fn test_command_substitution() {
+    // Only `echo` is allowed.
+    let mut handler = |subst: &CommandSubstitution| -> Result<String, String> {
+        let command = &subst.command[..];
+        if command == b"echo" || command.starts_with(b"echo ") {
+            let mut output = String::from(String::from_utf8_lossy(&command[4..]).trim_start());
+            output.push_str("\n\n");
+            Ok(output)
+        } else {
+            Err(String::from("not echo"))
+        }
+    };
+    let cases: &[(&str, Result<&[&str], ExpandError>)] = &[
+        ("$(echo a  b) \"$(echo a  b)\" x$(echo)y", Ok(&["a", "b", "a  b", "xy"])),
+        ("$(echo \")\" ')' \\)) $(echo \\(a\\) $((1)))", Ok(&["\")\"", "')'", "\\)", "\\(a\\)", "$((1))"])),
+        ("$(echo (a))", Err(ExpandError::BadSubstitution)),
+        ("$(case x in x) rm -rf ~;; esac)", Err(ExpandError::CommandRejected {
+            command: String::from("case x in x) rm -rf ~;; esac"),
+            reason: String::from("not echo"),
+        })),
+        ("$(echo <<E\n)\nE\n)", Ok(&["<<E", ")", "E"])),
+        ("$(echo a # )\n)", Ok(&["a", "#", ")"])),
+        ("`echo \\`b\\` \\$x \\\\ \\a` \"`echo \\\"a\\\"`\"", Ok(&["`b`", "$x", "\\", "\\a", "\"a\""])),
+        ("${UNSET:-$(echo a b)} $(( $(echo 2) * 3 )) $((echo 1) )", Err(ExpandError::CommandRejected {
+            command: String::from("(echo 1) "),
+            reason: String::from("not echo"),
+        })),
+        ("${UNSET:-$(echo a b)} $(( $(echo 2) * 3 ))", Ok(&["a", "b", "6"])),
+        ("$(echo", Err(ExpandError::Unterminated)),
+        ("`echo", Err(ExpandError::Unterminated)),
+        ("$(echo \"a)", Err(ExpandError::Unterminated)),
+    ];
+    let vars = test_vars();
+    for &(input, ref expected) in cases {
+        let expected = expected.clone().map(|words| words.iter().map(|&w| String::from(w)).collect::<Vec<_>>());
+        let result = Expander::new(&vars).command_substitution(&mut handler).expand(input);
+        assert_eq!(result, expected, "expanding {:?}", input);
+    }
+}
+
+/// Run each of `exprs` through bash (in the C locale, with globbing disabled unless `setup`
+/// enables it, and in the directory `cwd` if given), with the variables `vars` set, and returns
+/// the resulting words.  Returns `None` if bash isn't available.
+#[cfg(all(unix, feature = "std", test))]
+fn bash_expand(setup: &str, vars: &[(&str, &str)], exprs: &[&str], cwd: Option<&std::path::Path>) -> Option<Vec<Vec<String>>> {
+    use std::process::Command;
+
+    let script = format!(r#"set -f; {}; for _expr; do eval "set -- $_expr" && printf '%s\0' "$@"; printf '\1'; done"#, setup);
+    let mut command = Command::new("bash");
+    command.env("LC_ALL", "C").arg("-c").arg(script).arg("bash").args(exprs);
+    for &(name, value) in vars {
+        command.env(name, value);
+    }
+    command.env_remove("u");
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    let output = match command.output() {
+        Ok(output) => output.stdout,
+        // bash isn't installed.
+        Err(_) => { return None; },
+    };
+    let records: Vec<&[u8]> = output.split(|&c| c == 1).collect();
+    assert_eq!(records.len(), exprs.len() + 1);
+    Some(records[..exprs.len()].iter().map(|record| {
+        let mut words: Vec<String> = record.split(|&c| c == 0).map(|word| String::from_utf8_lossy(word).into_owned()).collect();
+        assert_eq!(words.pop().as_deref(), Some(""));
+        words
+    }).collect())
+}
+
+#[test]
+#[cfg(all(unix, feature = "std"))]
+fn test_expand_matches_bash() {
+    let vars: &[(&str, &str)] = &[
+        ("path", "/usr/local/lib/foo.tar.gz"),
+        ("s", " a b  c "),
+        ("star", "a*b[c]"),
+        ("e", ""),
+        ("amp", "&"),
+        ("p", "o*"),
+        ("n", "2"),
+        ("word", "abcabc"),
+        ("expr", "1 + n"),
+    ];
+    let exprs: &[&str] = &[
+        "$path ${path} \"$s\" $s x${s}y ${e} \"$e\" ${u-d} ${e-d} ${e:-d} ${u:+a} ${e+a}",
+        "${path%.tar.gz} ${path%.*} ${path%%.*} ${path#*/} ${path##*/} ${path%%/*}",
+        "${path%'.gz'} \"${path%.gz}\" \"${path%'.gz'}\" ${path%\\.gz} ${path%\".gz\"} ${path#$p}",
+        "${star%[c]} ${star%\\[c\\]} ${star#a\\*} ${star#\"a*\"} ${star#a[*]} ${star%[[:alpha:]]]}",
+        "${word#?} ${word##*b} ${word%b*} ${word%%b*} ${word#[!a]} ${word#[^b]b} ${word%[a-c]}",
+        "${#path} ${#e} ${#u} ${#s}",
+        "${word/b/X} ${word//b/X} ${word/#a/X} ${word/%c/X} ${word/#b/X} ${word/b} ${word//[ac]}",
+        "${word/b*/X} ${word//*/X} ${word/#/X} ${word/%/X} ${word//} ${e//*/X} ${e/#/X} ${u/*/X}",
+        "${word/b/[&]} ${word//b/\\&} ${word/b/\"&\"} \"${word/b/&&}\" ${word/b/$amp} ${word/b/'&'}",
+        "${word/$p/X} ${path/$p/X} ${path/\"$p\"/X} ${s/a/x y} \"${s/a/x y}\" ${word/b/ }",
+        "${word:1} ${word:1:2} ${word: -2} ${word: -2:1} ${word:2:-1} ${word:10} [${word: -10}] ${word:0:0}",
+        "${word:$n} ${word:1:$n} ${word: -$n} \"${s:1:3}\" ${s:1:3}",
+        "${word:n} ${word:n+1:n*2} ${word::2} ${word: } ${word:1:} ${word:(-2)}",
+        "$(( )) $((n)) $((expr * 2)) $((0x1F + 010)) $(( \"1\" + 2 )) $((1 || 1/0)) $((1 ? 2 : 1/0))",
+        "$((5 % -3)) $((-5 / 2)) $((~0 ^ 5)) $((!0 + !7)) $((1 ? 2 : 3 ? 4 : 5)) $((010 == 8)) $((-n))",
+        "$((1 + 2 * 3 - 4 / 2 % 3)) $((1 << 3 >> 1)) $((3 & 5 | 6 ^ 1)) $((1 < 2 == 2 > 1)) $((1 <= 1 && 2 >= 3))",
+        "$((e)) $((u)) $((-9223372036854775807 - 1)) $((n$n)) x$((n))y \"$((n))\" $(($n$n))",
+        "$'a\\tb' x$'$n'y $'it\\'s' \"$'n'\" $\"$n\" ${u-$'\\x41'} ${word#$'a'}",
+        "$(case x in x) echo a;; esac) $(case y in (x) echo a;; (*) echo b c;; esac) \"$(echo \")\")\"",
+        "$(cat <<E\n)\nE\n) x$(cat <<-E\n\t(\n\tE\n)y $(echo a # )\n)",
+    ];
+
+    let expected = match bash_expand("set +B", vars, exprs, None) {
+        Some(expected) => expected,
+        None => { return; },
+    };
+    let vars: BTreeMap<String, String> = vars.iter().map(|&(name, value)| (name.into(), value.into())).collect();
+    let mut run = |subst: &CommandSubstitution| -> Result<String, String> {
+        let command = String::from_utf8_lossy(&subst.command).into_owned();
+        let output = std::process::Command::new("bash").arg("-c").arg(command).output().map_err(|err| err.to_string())?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let mut expander = Expander::new(&vars).command_substitution(&mut run);
+    for (expr, expected) in exprs.iter().zip(expected) {
+        assert_eq!(expander.expand(expr), Ok(expected), "expanding {:?}", expr);
+    }
+}
+
+#[test]
+#[cfg(all(unix, feature = "std"))]
+fn test_ifs_matches_bash() {
+    let vars: &[(&str, &str)] = &[
+        ("a", "a::b"),
+        ("b", ":a"),
+        ("c", "a:"),
+        ("d", " : a "),
+        ("e", " a : : b "),
+        ("f", "a: :b"),
+        ("g", "\t\ta\t:\tb\t"),
+        ("h", ":"),
+        ("s", " a  b "),
+    ];
+    let exprs: &[&str] = &[
+        "$a $b $c $d $e $f $g $h $s",
+        "x$a x${b}y \"\"$c ${c}\"\" $c$b x$h ''$h $h'' \"$a\" '$a' a:b a\\ b",
+        "${u-a b} ${u-a:b} \"${u-a:b}\" ${u-$a} ${u-\"$a\"} ${a%b} ${a/::/ } ${u-'a:b'}x:y",
+    ];
+    let ifs_values: &[&str] = &[":", " :", "\t:", "", " \t\n", "ab"];
+    for ifs in ifs_values {
+        let setup = format!("IFS=$'{}'", ifs.replace('\t', "\\t").replace('\n', "\\n"));
+        let expected = match bash_expand(&setup, vars, exprs, None) {
+            Some(expected) => expected,
+            None => { return; },
+        };
+        let vars: BTreeMap<String, String> = vars.iter().map(|&(name, value)| (name.into(), value.into())).collect();
+        let mut expander = Expander::new(&vars).ifs(ifs);
+        for (expr, expected) in exprs.iter().zip(expected) {
+            assert_eq!(expander.expand(expr), Ok(expected), "expanding {:?} with IFS {:?}", expr, ifs);
+        }
+    }
+}
+
+#[test]
+#[cfg(all(unix, feature = "std"))]
+fn test_braces_match_bash() {
+    let vars: &[(&str, &str)] = &[("x", "X"), ("s", "a b")];
+    let exprs: &[&str] = &[
+        "a{b,c}d {a,b}{1,2} {a,b{1,2}} {x,y{1..3}} {a..c}{1..2} {1..3}x{a,b} pre{a,b}post",
+        "{01..3} {-01..2} {-1..01} {1..-02} {001..10..3} {10..1..-3} {0..10} {-0..3} {00..3} {-00..2}",
+        "{a..e..2} {e..a} {1..3..0} {a..1} {1..a} {ab..c} {1.. 3} {+1..3} {1..3..} {a..c..-1} {-1..-3}",
+        "{9223372036854775806..9223372036854775807} {1..2..9223372036854775807} {99999999999999999999..1}",
+        "{,} x{,} x{a,} {a} {a{b,c}} {a{b,c} -{ab}- {{a,b}} {a,,b} a{b}c{d,e} {} }{a,b}{ {a,b",
+        "\"{a,b}\" \\{a,b} {a\\,b} {a,\"b c\"} '{'a,b} {a,b'}'",
+        "${x}{a,b} {a,b}$x {$x,$s} \"{$x,y}\" ~{a,b}",
+    ];
+    let expected = match bash_expand("set -B", vars, exprs, None) {
+        Some(expected) => expected,
+        None => { return; },
+    };
+    let vars: BTreeMap<String, String> = vars.iter().map(|&(name, value)| (name.into(), value.into())).collect();
+    let mut expander = Expander::new(&vars).braces(true);
+    for (expr, expected) in exprs.iter().zip(expected) {
+        assert_eq!(expander.expand(expr), Ok(expected), "expanding {:?}", expr);
+    }
+}
+
+#[test]
+fn test_braces() {
+    let vars = test_vars();
+    let mut expander = Expander::new(&vars).braces(true);
+    assert_eq!(expander.expand("cp file.{c,h} --ports={08..10..2} {x..z} '{a,b}' {$FOO,\"b c\"}"),
+               Ok(["cp", "file.c", "file.h", "--ports=08", "--ports=10", "x", "y", "z", "{a,b}", "foo", "b c"]
+                  .iter().map(|&w| String::from(w)).collect()));
+    assert_eq!(expand("{a,b}", &vars), Ok(vec![String::from("{a,b}")]));
+
+    assert_eq!(expander.expand("{1..100000}").map(|words| words.len()), Ok(MAX_BRACE_WORDS));
+    assert_eq!(expander.expand("{1..100000000}"), Err(ExpandError::TooManyWords));
+    assert_eq!(expander.expand("{1..50000} {1..50001}"), Err(ExpandError::TooManyWords));
+    assert_eq!(expander.expand(&"{a,b}".repeat(64)), Err(ExpandError::TooManyWords));
+    assert_eq!(expander.expand(&"{a,{b,{c,{d,e}}}}".repeat(8)), Err(ExpandError::TooManyWords));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_braces_pathological() {
+    // Each of these must finish quickly, without overflowing a small stack.
+    let thread = std::thread::Builder::new().stack_size(512 * 1024).spawn(|| {
+        let vars = test_vars();
+        let mut expander = Expander::new(&vars).braces(true);
+        let start = std::time::Instant::now();
+        assert_eq!(expander.expand(&"{,}".repeat(50_000)), Err(ExpandError::TooManyWords));
+        assert_eq!(expander.expand(&"{".repeat(100_000)), Ok(vec!["{".repeat(100_000)]));
+        let nested = format!("{}x{}", "{".repeat(100_000), "}".repeat(100_000));
+        assert_eq!(expander.expand(&nested), Ok(vec![nested.clone()]));
+        let nested = format!("{}{}", "{a,".repeat(10_000), "}".repeat(10_000));
+        assert_eq!(expander.expand(&nested), Err(ExpandError::TooDeep));
+        let nested = format!("{}{}", "{a,".repeat(50), "}".repeat(50));
+        assert_eq!(expander.expand(&nested).map(|words| words.len()), Ok(50));
+        start.elapsed()
+    }).unwrap();
+    assert!(thread.join().unwrap() < std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn test_expand_tilde() {
+    struct Homes;
+    impl TildeResolver for Homes {
+        fn home(&self) -> Option<Cow<'_, [u8]>> {
+            Some(Cow::Borrowed(b"/home/me"))
+        }
+        fn user_home(&self, user: &str) -> Option<Cow<'_, [u8]>> {
+            match user {
+                "alice" => Some(Cow::Borrowed(b"/home/alice")),
+                "spaced" => Some(Cow::Borrowed(b"/a b")),
+                _ => None,
+            }
+        }
+    }
+    let vars = test_vars();
+    let cases: &[(&str, &[&str])] = &[
+        ("~ ~/x ~alice/y ~alice ~bob/z", &["/home/me", "/home/me/x", "/home/alice/y", "/home/alice", "~bob/z"]),
+        ("~\"alice\" \"~\" \\~ '~' x~ ~$FOO ~/$FOO", &["~alice", "~", "~", "~", "x~", "~foo", "/home/me/foo"]),
+        ("~spaced ~spaced/*", &["/a b", "/a b/*"]),
+        ("A=~:~alice/b:x~:~ B=x:~ B=~$FOO:~ B=$FOO:~ \"B\"=~ --opt=~ 1=~", &["A=/home/me:/home/alice/b:x~:/home/me", "B=x:/home/me", "B=~foo:/home/me", "B=foo:/home/me", "B=~", "--opt=~", "1=~"]),
+        ("~/a:~ ${UNSET:-~/d} \"${UNSET:-~}\" ${UNSET:-x~}", &["/home/me/a:~", "/home/me/d", "~", "x~"]),
+    ];
+    for &(input, expected) in cases {
+        let result = Expander::new(&vars).tilde(&Homes).expand(input);
+        let expected = expected.iter().map(|&w| String::from(w)).collect::<Vec<_>>();
+        assert_eq!(result, Ok(expected), "expanding {:?}", input);
+    }
+    // Off by default.
+    assert_eq!(expand("~", &vars), Ok(vec![String::from("~")]));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_passwd_home() {
+    let passwd = b"root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000:Alice,,,:/home/alice:/bin/sh\nbroken\n";
+    assert_eq!(passwd_home(passwd, "root"), Some(&b"/root"[..]));
+    assert_eq!(passwd_home(passwd, "alice"), Some(&b"/home/alice"[..]));
+    assert_eq!(passwd_home(passwd, "bob"), None);
+    assert_eq!(passwd_home(passwd, "broken"), None);
+}
+
+#[test]
+#[cfg(all(unix, feature = "std"))]
+fn test_glob_matches_bash() {
+    use crate::glob::{MemoryFileSystem, StdFileSystem};
+
+    let files = ["a/f.rs", "a/b/g.rs", "a/b/c/h.rs", ".dot.rs", "top.rs", ".hid/x/y.rs", "d/.e", "q*", "sp ace.rs"];
+    let dir = std::env::temp_dir().join(format!("shlex-glob-test-{}", std::process::id()));
+    let mut fs = MemoryFileSystem::new().dir("e");
+    std::fs::create_dir_all(dir.join("e")).unwrap();
+    for file in &files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"").unwrap();
+        fs = fs.file(file);
+    }
+
+    let vars: &[(&str, &str)] = &[("p", "*.rs"), ("s", "* a/*")];
+    let exprs: &[&str] = &[
+        "* .* */ d/* d/.* e/* \"q\"* q\\* nomatch* a/*/ ./*.rs a//*.rs [a-d] *[ .hid/*/* */x",
+        "a/b/../*.rs *.rs '*'.rs $p \"$p\" $s a/*/*.rs ? ???.rs */*/* /nonexistent/* [.]* *\\ *",
+        "** **/ a/** **/*.rs a/**/h.rs a/**/b **/c a**",
+    ];
+    // bash gives `a/**` a trailing slash but not `*/**`, so the latter is left out.
+    let globstar_exprs: &[&str] = &["** **/ a/** **/*.rs a/**/h.rs a/**/b **/c a** **/*/"];
+    let expected = bash_expand("set +f", vars, exprs, Some(&dir));
+    let globstar_expected = bash_expand("set +f; shopt -s globstar", vars, globstar_exprs, Some(&dir));
+    let abs_pattern = format!("{}/*/*.rs", dir.display());
+    let abs_result = Expander::new(&test_vars()).glob(&StdFileSystem).expand(&abs_pattern);
+    std::fs::remove_dir_all(&dir).unwrap();
+    let (expected, globstar_expected) = match (expected, globstar_expected) {
+        (Some(expected), Some(globstar_expected)) => (expected, globstar_expected),
+        _ => { return; },
+    };
+
+    let vars: BTreeMap<String, String> = vars.iter().map(|&(name, value)| (name.into(), value.into())).collect();
+    for (expr, expected) in exprs.iter().zip(expected) {
+        assert_eq!(Expander::new(&vars).glob(&fs).expand(expr), Ok(expected), "expanding {:?}", expr);
+    }
+    for (expr, expected) in globstar_exprs.iter().zip(globstar_expected) {
+        assert_eq!(Expander::new(&vars).glob(&fs).globstar(true).expand(expr), Ok(expected), "expanding {:?}", expr);
+    }
+    assert_eq!(abs_result, Ok(vec![format!("{}/a/f.rs", dir.display())]));
+}
+
+#[test]
+fn test_glob_no_match() {
+    use crate::glob::MemoryFileSystem;
+
+    let fs = MemoryFileSystem::new().file("a.rs");
+    let vars = test_vars();
+    let expand_with = |no_match| Expander::new(&vars).glob(&fs).no_match(no_match).expand("x *.c 'y'*.rs");
+    assert_eq!(expand_with(NoMatch::Literal), Ok(vec![String::from("x"), String::from("*.c"), String::from("y*.rs")]));
+    assert_eq!(expand_with(NoMatch::Null), Ok(vec![String::from("x")]));
+    assert_eq!(expand_with(NoMatch::Fail), Err(ExpandError::NoMatch { pattern: String::from("*.c") }));
+    assert_eq!(Expander::new(&vars).glob(&fs).no_match(NoMatch::Fail).expand("*.rs"), Ok(vec![String::from("a.rs")]));
+}
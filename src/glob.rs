@@ -0,0 +1,348 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Pathname expansion ("globbing") of unquoted `*`, `?` and `[...]`, for
+//! [`Expander::glob`](crate::expand::Expander::glob).
+//!
+//! The file system is accessed through the [`FileSystem`] trait, which is implemented by
+//! [`StdFileSystem`] (for the real file system) and [`MemoryFileSystem`] (for tests).
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+#[cfg(test)]
+use alloc::vec;
+
+use crate::pattern::Pattern;
+
+/// A file system that glob patterns can be matched against.
+///
+/// Paths are byte strings using `/` as the separator.  Relative paths are relative to whatever
+/// the implementation considers the current directory, which is named `.`.
+pub trait FileSystem {
+    /// Returns the names of the entries in the directory `dir`, not including `.` and `..`, or
+    /// `None` if it can't be read.  The order doesn't matter.
+    fn read_dir(&self, dir: &[u8]) -> Option<Vec<Vec<u8>>>;
+
+    /// Returns whether anything exists at `path`, including a symbolic link whose target
+    /// doesn't exist.
+    fn exists(&self, path: &[u8]) -> bool;
+
+    /// Returns whether `path` is a directory, or a symbolic link to one.
+    fn is_dir(&self, path: &[u8]) -> bool;
+
+    /// Returns whether `path` is a symbolic link.  `**` doesn't descend into symbolic links to
+    /// directories.  The default implementation returns `false`.
+    fn is_symlink(&self, _path: &[u8]) -> bool {
+        false
+    }
+}
+
+/// The real file system, accessed with `std::fs`.
+///
+/// On platforms other than Unix, paths that aren't valid UTF-8 are treated as nonexistent.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFileSystem;
+
+#[cfg(feature = "std")]
+fn std_path(path: &[u8]) -> Option<&std::path::Path> {
+    #[cfg(unix)]
+    let path = <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(path);
+    #[cfg(not(unix))]
+    let path = core::str::from_utf8(path).ok()?;
+    Some(std::path::Path::new(path))
+}
+
+#[cfg(feature = "std")]
+impl FileSystem for StdFileSystem {
+    fn read_dir(&self, dir: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let entries = std::fs::read_dir(std_path(dir)?).ok()?;
+        Some(entries.filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            #[cfg(unix)]
+            let name = std::os::unix::ffi::OsStringExt::into_vec(name);
+            #[cfg(not(unix))]
+            let name = name.into_string().ok()?.into_bytes();
+            Some(name)
+        }).collect())
+    }
+
+    fn exists(&self, path: &[u8]) -> bool {
+        std_path(path).map_or(false, |path| std::fs::symlink_metadata(path).is_ok())
+    }
+
+    fn is_dir(&self, path: &[u8]) -> bool {
+        std_path(path).map_or(false, |path| path.is_dir())
+    }
+
+    fn is_symlink(&self, path: &[u8]) -> bool {
+        std_path(path).map_or(false, |path| {
+            std::fs::symlink_metadata(path).map_or(false, |meta| meta.file_type().is_symlink())
+        })
+    }
+}
+
+/// A file system that exists only in memory, which is useful for testing.
+///
+/// ```
+/// use shlex::expand::Expander;
+/// use shlex::glob::MemoryFileSystem;
+/// let fs = MemoryFileSystem::new().file("src/lib.rs").file("src/main.rs").dir("target");
+/// let vars = std::collections::BTreeMap::new();
+/// assert_eq!(Expander::new(&vars).glob(&fs).expand("ls src/*.rs '*'"),
+///            Ok(vec!["ls".into(), "src/lib.rs".into(), "src/main.rs".into(), "*".into()]));
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryFileSystem {
+    /// Maps each normalized path to whether it's a directory.
+    entries: BTreeMap<Vec<u8>, bool>,
+}
+
+impl MemoryFileSystem {
+    /// Create an empty `MemoryFileSystem`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file at `path`, along with any directories containing it.
+    #[inline]
+    pub fn file<P: AsRef<[u8]>>(mut self, path: P) -> Self {
+        self.add(path.as_ref(), false);
+        self
+    }
+
+    /// Add a directory at `path`, along with any directories containing it.
+    #[inline]
+    pub fn dir<P: AsRef<[u8]>>(mut self, path: P) -> Self {
+        self.add(path.as_ref(), true);
+        self
+    }
+
+    fn add(&mut self, path: &[u8], is_dir: bool) {
+        let path = normalize(path);
+        for (i, &c) in path.iter().enumerate() {
+            if c == b'/' && i > 0 {
+                self.entries.insert(path[..i].to_vec(), true);
+            }
+        }
+        self.entries.insert(path, is_dir);
+    }
+}
+
+/// Normalize a path for [`MemoryFileSystem`]: remove empty and `.` components and resolve `..`
+/// components, keeping a leading `/`.  The current directory normalizes to the empty string.
+fn normalize(path: &[u8]) -> Vec<u8> {
+    let mut components: Vec<&[u8]> = Vec::new();
+    for component in path.split(|&c| c == b'/') {
+        match component {
+            b"" | b"." => {},
+            b".." => { components.pop(); },
+            _ => components.push(component),
+        }
+    }
+    let mut res = Vec::new();
+    if path.first() == Some(&b'/') {
+        res.push(b'/');
+    }
+    for (i, component) in components.iter().enumerate() {
+        if i > 0 {
+            res.push(b'/');
+        }
+        res.extend_from_slice(component);
+    }
+    res
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, dir: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let dir = normalize(dir);
+        if !dir.is_empty() && dir != b"/" && self.entries.get(&dir) != Some(&true) {
+            return None;
+        }
+        Some(self.entries.keys().filter_map(|path| {
+            let name = if dir.is_empty() {
+                if path.first() == Some(&b'/') { return None; }
+                &path[..]
+            } else if dir == b"/" {
+                path.get(1..).filter(|_| path[0] == b'/')?
+            } else {
+                path.get(dir.len() + 1..).filter(|_| path.starts_with(&dir) && path[dir.len()] == b'/')?
+            };
+            if name.is_empty() || name.contains(&b'/') { None } else { Some(name.to_vec()) }
+        }).collect())
+    }
+
+    fn exists(&self, path: &[u8]) -> bool {
+        let path = normalize(path);
+        path.is_empty() || path == b"/" || self.entries.contains_key(&path)
+    }
+
+    fn is_dir(&self, path: &[u8]) -> bool {
+        let path = normalize(path);
+        path.is_empty() || path == b"/" || self.entries.get(&path) == Some(&true)
+    }
+}
+
+/// What a glob pattern that doesn't match any files expands to.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NoMatch {
+    /// The pattern itself, with quotes removed, as in POSIX shells.  This is the default.
+    Literal,
+    /// Nothing, like bash's `nullglob` option.
+    Null,
+    /// An error, like bash's `failglob` option.
+    Fail,
+}
+
+impl Default for NoMatch {
+    fn default() -> Self {
+        NoMatch::Literal
+    }
+}
+
+/// Expand `text` as a glob pattern, where `quoted[i]` says whether `text[i]` was quoted.  Returns
+/// `None` if `text` contains no unquoted pattern characters, in which case it's not a pattern,
+/// and otherwise the sorted matching paths.
+pub(crate) fn glob(fs: &dyn FileSystem, text: &[u8], quoted: &[bool], globstar: bool) -> Option<Vec<Vec<u8>>> {
+    enum Component {
+        Literal(Vec<u8>),
+        Pattern { pattern: Pattern, explicit_dot: bool },
+        Globstar,
+    }
+    let mut components = Vec::new();
+    let mut any_magic = false;
+    let mut start = 0;
+    for end in (0..=text.len()).filter(|&i| i == text.len() || text[i] == b'/') {
+        let (text, quoted) = (&text[start..end], &quoted[start..end]);
+        start = end + 1;
+        let component = if globstar && text == b"**" && quoted == [false, false] {
+            Component::Globstar
+        } else {
+            let pattern = Pattern::new(text, quoted);
+            if pattern.is_literal() {
+                Component::Literal(text.to_vec())
+            } else {
+                Component::Pattern { pattern, explicit_dot: text.first() == Some(&b'.') }
+            }
+        };
+        any_magic |= !matches!(component, Component::Literal(_));
+        components.push(component);
+    }
+    if !any_magic {
+        return None;
+    }
+
+    // The paths matched so far, each with a trailing slash unless empty.
+    let mut prefixes: Vec<Vec<u8>> = alloc::vec![Vec::new()];
+    let last = components.len() - 1;
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i == last;
+        let mut next = Vec::new();
+        for prefix in &prefixes {
+            let mut add = |name: &[u8], must_be_dir: bool| {
+                let mut path = prefix.clone();
+                path.extend_from_slice(name);
+                if is_last {
+                    next.push(path);
+                } else if !must_be_dir || fs.is_dir(&path) {
+                    path.push(b'/');
+                    next.push(path);
+                }
+            };
+            match component {
+                Component::Literal(name) => {
+                    if is_last {
+                        // An empty last component comes from a trailing slash.
+                        let path = prefix_path(prefix, name);
+                        if !(if name.is_empty() { fs.is_dir(&path) } else { fs.exists(&path) }) {
+                            continue;
+                        }
+                    }
+                    add(name, false);
+                },
+                Component::Pattern { pattern, explicit_dot } => {
+                    let mut names = fs.read_dir(dir_path(prefix)).unwrap_or_default();
+                    names.retain(|name| (*explicit_dot || name.first() != Some(&b'.')) && pattern.matches(name));
+                    for name in names {
+                        add(&name, true);
+                    }
+                },
+                Component::Globstar => {
+                    // Zero or more directories; as the last component, everything beneath.
+                    let mut found = Vec::new();
+                    walk(fs, prefix, is_last, &mut found);
+                    if !is_last || !prefix.is_empty() {
+                        next.push(prefix.clone());
+                    }
+                    next.extend(found);
+                },
+            }
+        }
+        prefixes = next;
+    }
+    prefixes.retain(|path| !path.is_empty());
+    prefixes.sort();
+    prefixes.dedup();
+    Some(prefixes)
+}
+
+/// The path of the directory `prefix` (which is empty or ends with a slash), for passing to
+/// [`FileSystem::read_dir`].
+fn dir_path(prefix: &[u8]) -> &[u8] {
+    match prefix {
+        b"" => b".",
+        b"/" => b"/",
+        _ => &prefix[..prefix.len() - 1],
+    }
+}
+
+fn prefix_path(prefix: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut path = prefix.to_vec();
+    path.extend_from_slice(name);
+    if path.is_empty() {
+        path.push(b'.');
+    }
+    path
+}
+
+/// Find everything beneath `prefix`, skipping dotfiles and not following symbolic links.  If
+/// `all`, add every file and directory found, without a trailing slash; otherwise, add only
+/// directories, with one.
+fn walk(fs: &dyn FileSystem, prefix: &[u8], all: bool, found: &mut Vec<Vec<u8>>) {
+    let mut names = fs.read_dir(dir_path(prefix)).unwrap_or_default();
+    names.sort();
+    for name in names {
+        if name.first() == Some(&b'.') {
+            continue;
+        }
+        let path = prefix_path(prefix, &name);
+        let descend = fs.is_dir(&path) && !fs.is_symlink(&path);
+        if all {
+            found.push(path.clone());
+        }
+        if descend {
+            let mut dir = path;
+            dir.push(b'/');
+            if !all {
+                found.push(dir.clone());
+            }
+            walk(fs, &dir, all, found);
+        }
+    }
+}
+
+#[test]
+fn test_memory_file_system() {
+    let fs = MemoryFileSystem::new().file("a/b/c").dir("/abs/d").file("./x/../y");
+    let mut names = fs.read_dir(b".").unwrap();
+    names.sort();
+    assert_eq!(names, [&b"a"[..], b"y"]);
+    assert_eq!(fs.read_dir(b"a/"), Some(vec![b"b".to_vec()]));
+    assert_eq!(fs.read_dir(b"/"), Some(vec![b"abs".to_vec()]));
+    assert_eq!(fs.read_dir(b"a/b/c"), None);
+    assert!(fs.is_dir(b"a/b") && !fs.is_dir(b"a/b/c") && fs.exists(b"a/b/c") && !fs.exists(b"a/c"));
+}
@@ -10,6 +10,15 @@
 //! Disabling the `std` feature (which is enabled by default) will allow the crate to work in
 //! `no_std` environments, where the `alloc` crate, and a global allocator, are available.
 //!
+//! The [`expand`] module can also perform parameter expansion (`$FOO` and friends) while
+//! splitting, using variables supplied by the caller.
+//!
+//! With the `std` feature, [`command()`] splits a string straight into a `std::process::Command`,
+//! applying leading `NAME=value` assignments to its environment; see the `process` module.  The
+//! non-default `render-command` feature, which needs Rust 1.57, adds the reverse, rendering a
+//! `Command` as a shell command line, and the `render-tokio` feature does the same for
+//! `tokio::process::Command`s.
+//!
 //! ## <span style="color:red">Warning</span>
 //!
 //! The [`try_quote`]/[`try_join`] family of APIs does not quote control characters (because they
@@ -27,6 +36,14 @@
 //!
 //! For more information, and for information about more minor issues, please see [quoting_warning].
 //!
+//! Quoting also doesn't stop the program being run from taking an argument that starts with `-`
+//! as an option.  Use [`guard_args`] or [`Quoter::join_args`] for arguments from untrusted
+//! sources.
+//!
+//! To build a command line out of both shell syntax and values, use [`CmdLine`], which quotes
+//! every value and only takes syntax through an explicit [`CmdLine::raw`], rather than pasting
+//! quoted values into a `format!` string.
+//!
 //! ## Compatibility
 //!
 //! This crate's quoting functionality tries to be compatible with **any POSIX-compatible shell**;
@@ -34,6 +51,9 @@
 //! POSIX-compatible but close enough).
 //!
 //! It also aims to be compatible with Python `shlex` and C `wordexp`.
+//!
+//! Shells with incompatible quoting rules, such as `csh`/`tcsh` or `rc`, can be targeted by
+//! passing a different [`Dialect`] to [`Quoter::dialect`] and [`Shlex::dialect`].
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -45,8 +65,19 @@ use alloc::string::String;
 use alloc::vec;
 #[cfg(test)]
 use alloc::borrow::ToOwned;
+#[cfg(test)]
+use alloc::string::ToString;
 
+mod arith;
+mod brace;
 pub mod bytes;
+pub mod command;
+pub mod expand;
+pub mod glob;
+pub mod pattern;
+#[cfg(feature = "std")]
+pub mod process;
+pub mod word;
 #[cfg(all(doc, not(doctest)))]
 #[path = "quoting_warning.md"]
 pub mod quoting_warning;
@@ -61,14 +92,39 @@ impl<'a> Shlex<'a> {
     pub fn new(in_str: &'a str) -> Self {
         Self(bytes::Shlex::new(in_str.as_bytes()))
     }
+
+    /// Set which shell [`Dialect`]'s quoting rules to split with.  The default is
+    /// [`Dialect::Posix`].  See [`bytes::Shlex::dialect`].
+    #[inline]
+    pub fn dialect(self, dialect: Dialect) -> Self {
+        Self(self.0.dialect(dialect))
+    }
+
+    /// Like [`Iterator::next`], but also returns which bytes of the word were quoted.  See
+    /// [`bytes::Shlex::next_with_quoting`].
+    ///
+    /// The word is returned as bytes, to match the per-byte mask, but it is always valid UTF-8.
+    pub fn next_with_quoting(&mut self) -> Option<bytes::QuotedWord> {
+        let word = self.0.next_with_quoting()?;
+        if core::str::from_utf8(&word.bytes).is_err() {
+            self.0.had_error = true;
+            return None;
+        }
+        Some(word)
+    }
 }
 
 impl Iterator for Shlex<'_> {
     type Item = String;
     fn next(&mut self) -> Option<String> {
-        self.0.next().map(|byte_word| {
-            // Safety: given valid UTF-8, bytes::Shlex will always return valid UTF-8.
-            unsafe { String::from_utf8_unchecked(byte_word) }
+        self.0.next().and_then(|byte_word| match String::from_utf8(byte_word) {
+            Ok(word) => Some(word),
+            Err(_) => {
+                // Given valid UTF-8, bytes::Shlex only returns invalid UTF-8 if the input
+                // contains escape sequences for arbitrary bytes, like `$'\xff'` in Bash.
+                self.0.had_error = true;
+                None
+            }
         })
     }
 }
@@ -87,6 +143,9 @@ impl core::ops::DerefMut for Shlex<'_> {
     }
 }
 
+#[cfg(feature = "std")]
+pub use process::command;
+
 /// Convenience function that consumes the whole string at once.  Returns None if the input was
 /// erroneous.
 pub fn split(in_str: &str) -> Option<Vec<String>> {
@@ -97,8 +156,9 @@ pub fn split(in_str: &str) -> Option<Vec<String>> {
 
 /// Errors from [`Quoter::quote`], [`Quoter::join`], etc. (and their [`bytes`] counterparts).
 ///
-/// By default, the only error that can be returned is [`QuoteError::Nul`].  If you call
-/// `allow_nul(true)`, then no errors can be returned at all.  Any error variants added in the
+/// By default, the only error that can be returned is [`QuoteError::Nul`], except that quoting
+/// invalid UTF-8 for [`Dialect::Nushell`] returns [`QuoteError::InvalidUtf8`].  If you call
+/// `allow_nul(true)`, then no other errors can be returned.  Any error variants added in the
 /// future will not be enabled by default; they will be enabled through corresponding non-default
 /// [`Quoter`] options.
 ///
@@ -114,12 +174,26 @@ pub enum QuoteError {
     /// you're sure you can handle nul bytes, you can call `allow_nul(true)` on the `Quoter` to let
     /// them pass through.
     Nul,
+    /// The input contained a [control
+    /// character](quoting_warning#control-characters-interactive-contexts-only) other than tab or
+    /// newline, the [`Dialect`] being quoted for has no way to escape it, and
+    /// `forbid_raw_control(true)` was called on the `Quoter`.
+    ///
+    /// The dialects that can escape control characters are [`Dialect::Nushell`], [`Dialect::Fish`],
+    /// and [`Dialect::PowerShell`].
+    Control,
+    /// The input was not valid UTF-8, and the [`Dialect`] being quoted for, [`Dialect::Nushell`],
+    /// can only represent UTF-8 strings.  This can only be returned by [`bytes::Quoter`], since
+    /// `str`s are always valid UTF-8.
+    InvalidUtf8,
 }
 
 impl core::fmt::Display for QuoteError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             QuoteError::Nul => f.write_str("cannot shell-quote string containing nul byte"),
+            QuoteError::Control => f.write_str("cannot shell-quote string containing control character in this dialect"),
+            QuoteError::InvalidUtf8 => f.write_str("cannot shell-quote string containing invalid UTF-8 in this dialect"),
         }
     }
 }
@@ -127,6 +201,182 @@ impl core::fmt::Display for QuoteError {
 #[cfg(feature = "std")]
 impl std::error::Error for QuoteError {}
 
+/// Errors from [`translate`] (and [`bytes::translate`]).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TranslateError {
+    /// The input could not be split, because it ended inside a quotation or right after an
+    /// escape character, or (in some dialects) contained an invalid escape sequence.
+    Split,
+    /// The input contained shell syntax other than quoting, such as a variable, a glob, a brace, or
+    /// a pipe.  Only literal strings can be translated.  The value is the first byte of syntax
+    /// found, or `=` for a leading `NAME=value` assignment.
+    Syntax(u8),
+    /// One of the words could not be quoted for the target dialect.
+    Quote(QuoteError),
+    /// The input contained escape sequences for arbitrary bytes, like `$'\xff'` in Bash, which
+    /// produced invalid UTF-8.  Only returned by [`translate`], not [`bytes::translate`].
+    InvalidUtf8,
+}
+
+impl From<QuoteError> for TranslateError {
+    fn from(err: QuoteError) -> TranslateError {
+        TranslateError::Quote(err)
+    }
+}
+
+impl core::fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TranslateError::Split => f.write_str("unterminated quote or invalid escape in command line"),
+            TranslateError::Syntax(c) => write!(f, "cannot translate shell syntax `{}`", core::ascii::escape_default(*c)),
+            TranslateError::Quote(err) => core::fmt::Display::fmt(err, f),
+            TranslateError::InvalidUtf8 => f.write_str("escape sequences in command line produce invalid UTF-8"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TranslateError {}
+
+/// An argument for [`guard_args`] and [`Quoter::join_args`] (and their [`bytes`] counterparts),
+/// marked with whether it came from a trusted source, like the program's own code, or from an
+/// untrusted one, like a user-supplied file name or URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Arg<T> {
+    Trusted(T),
+    Untrusted(T),
+}
+
+impl<T> Arg<T> {
+    pub(crate) fn map<U>(self, f: impl FnOnce(T) -> U) -> Arg<U> {
+        match self {
+            Arg::Trusted(arg) => Arg::Trusted(f(arg)),
+            Arg::Untrusted(arg) => Arg::Untrusted(f(arg)),
+        }
+    }
+}
+
+/// What [`guard_args`] does about untrusted arguments that start with `-`.
+///
+/// Quoting keeps an argument from being interpreted by the shell, but not by the program: an
+/// untrusted file name of `--upload-pack=evil`, passed to `git clone`, would be taken as an
+/// option however it was quoted.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionPolicy {
+    /// Insert `--` before the first untrusted argument, if any of them starts with `-`, so the
+    /// program takes them all as operands.  This needs a program that follows the POSIX
+    /// convention for `--`, and every argument after the first untrusted one is taken as an
+    /// operand, so trusted options must come before it.  Nothing is inserted if a trusted `--`
+    /// already comes before it, and a trusted `--` after an inserted one is left out.
+    EndOfOptions,
+    /// Prefix `./` to untrusted arguments that start with `-`.  This is only correct for
+    /// arguments that are relative paths.
+    PathPrefix,
+    /// Return [`ArgError::Option`] for untrusted arguments that start with `-`.
+    Reject,
+}
+
+/// Errors from [`guard_args`] and [`Quoter::join_args`] (and their [`bytes`] counterparts).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ArgError {
+    /// The untrusted argument at this index started with `-`, and the policy was
+    /// [`OptionPolicy::Reject`].
+    Option(usize),
+    /// An argument could not be quoted.
+    Quote(QuoteError),
+}
+
+impl From<QuoteError> for ArgError {
+    fn from(err: QuoteError) -> ArgError {
+        ArgError::Quote(err)
+    }
+}
+
+impl core::fmt::Display for ArgError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ArgError::Option(index) => write!(f, "untrusted argument {} looks like an option", index),
+            ArgError::Quote(err) => core::fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ArgError {}
+
+/// The family of shells whose syntax [`Quoter`] and [`Shlex`] should target.
+///
+/// The default is [`Dialect::Posix`].  Other dialects exist for shells whose quoting rules are
+/// incompatible with POSIX; output produced for them should not be expected to work in a POSIX
+/// shell, or vice versa.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dialect {
+    /// Any POSIX-compatible shell.  See [the crate documentation](crate#compatibility) for the
+    /// list of shells this is tested against.  `fish` also accepts this output, but use
+    /// [`Dialect::Fish`] to have control characters escaped rather than written raw.
+    Posix,
+    /// `csh` and `tcsh`.
+    ///
+    /// In these shells, `!` triggers history expansion even inside single quotes, and a newline
+    /// cannot appear inside quotes unless it is preceded by a backslash.  Output for this dialect
+    /// therefore uses single quotes with `\!` and backslash-newline escapes inside them.
+    Csh,
+    /// `rc`, the Plan 9 shell (including the plan9port and Byron Rakitzis versions).
+    ///
+    /// rc has no backslash escapes and no double quotes.  The only form of quoting is single
+    /// quotes, with `''` standing for a literal single quote.
+    Rc,
+    /// Nushell.
+    ///
+    /// Nushell has several kinds of quotes: backticks and single quotes, which have no escapes;
+    /// double quotes, which have backslash escapes; and raw strings like `r#'...'#`, which can hold
+    /// anything other than their own delimiter.  Output for this dialect uses whichever is shortest,
+    /// except that strings containing control characters always use double quotes, with the
+    /// control characters escaped.
+    ///
+    /// When splitting, quotes are only recognized if they surround a whole word.
+    Nushell,
+    /// `fish`.
+    ///
+    /// `fish` understands [`Dialect::Posix`] quoting, so output for this dialect is the same,
+    /// except that control characters are written as backslash escapes like `\n` and `\x1b`.
+    Fish,
+    /// PowerShell.
+    ///
+    /// Output for this dialect uses single quotes, or double quotes with backtick escapes if the
+    /// input contains control characters.  Escapes for control characters other than `\0`, `\r`,
+    /// `\n`, and `\t` use the `` `u{...} `` syntax, which requires PowerShell 6 or later.
+    PowerShell,
+    /// Bash.
+    ///
+    /// Output for this dialect is byte-for-byte identical to Bash 5's `printf %q` in the C locale.
+    /// Special characters are escaped with backslashes, except that if the input contains control
+    /// characters or non-ASCII bytes, the whole string uses ANSI-C quoting (`$'...'`) with escapes
+    /// for those bytes.  (In other locales, Bash would leave printable non-ASCII characters as-is.)
+    ///
+    /// When splitting, `$'...'` and `$"..."` are supported in addition to POSIX quoting.  In the
+    /// [string API](Shlex), escape sequences in `$'...'` that produce invalid UTF-8 are treated as
+    /// errors.
+    Bash,
+    /// Python's `shlex` module.
+    ///
+    /// Output for this dialect is byte-for-byte identical to `shlex.quote` (which produces valid
+    /// POSIX shell syntax, but differs from [`Dialect::Posix`] in the details).  Splitting matches
+    /// `shlex.split` with the default options: `#` is not a comment character, `\r` separates
+    /// words, escaped newlines are kept, and in double quotes only `\"` and `\\` are escapes.
+    Python,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::Posix
+    }
+}
+
 /// A more configurable interface to quote strings.  If you only want the default settings you can
 /// use the convenience functions [`try_quote`] and [`try_join`].
 ///
@@ -151,6 +401,25 @@ impl Quoter {
         self
     }
 
+    /// Set whether to return [`QuoteError::Control`] rather than include [control
+    /// characters](quoting_warning#control-characters-interactive-contexts-only) as-is in the
+    /// output.  By default they are included as-is.
+    ///
+    /// This only matters for dialects where control characters can't be represented using escape
+    /// sequences; see [`QuoteError::Control`].
+    #[inline]
+    pub fn forbid_raw_control(mut self, forbid: bool) -> Self {
+        self.inner = self.inner.forbid_raw_control(forbid);
+        self
+    }
+
+    /// Set which shell [`Dialect`] to quote for.  The default is [`Dialect::Posix`].
+    #[inline]
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.inner = self.inner.dialect(dialect);
+        self
+    }
+
     /// Convenience function that consumes an iterable of words and turns it into a single string,
     /// quoting words when necessary. Consecutive words will be separated by a single space.
     pub fn join<'a, I: IntoIterator<Item = &'a str>>(&self, words: I) -> Result<String, QuoteError> {
@@ -159,6 +428,13 @@ impl Quoter {
             .map(|bytes| unsafe { String::from_utf8_unchecked(bytes) })
     }
 
+    /// Like [`Quoter::join`], but first applies `policy` to the untrusted arguments, as
+    /// [`guard_args`] does.
+    pub fn join_args<'a, I: IntoIterator<Item = Arg<&'a str>>>(&self, args: I, policy: OptionPolicy) -> Result<String, ArgError> {
+        let args = guard_args(args, policy)?;
+        Ok(self.join(args.iter().map(|arg| &arg[..]))?)
+    }
+
     /// Given a single word, return a string suitable to encode it as a shell argument.
     pub fn quote<'a>(&self, in_str: &'a str) -> Result<Cow<'a, str>, QuoteError> {
         Ok(match self.inner.quote(in_str.as_bytes())? {
@@ -186,6 +462,121 @@ impl From<Quoter> for bytes::Quoter {
     }
 }
 
+/// A builder for shell command lines, which keeps shell syntax and the values it operates on
+/// apart.
+///
+/// Values passed to [`CmdLine::arg`] are always quoted, with the [`Quoter`] given to
+/// [`CmdLine::with_quoter`], or a default one.  The only ways to add unquoted syntax, such as a
+/// pipe or a redirection, are [`CmdLine::raw`], which only takes string literals, and
+/// [`CmdLine::raw_trusted`], for trusted text built at run time.  Each part is separated from the
+/// last by a space.
+///
+/// The finished command line is available through [`CmdLine::as_str`], [`CmdLine::into_string`]
+/// or [`Display`](core::fmt::Display).
+///
+/// ```rust
+/// use shlex::CmdLine;
+/// let dir = "my files; rm -rf ~";
+/// let line = CmdLine::new().arg("ls")?.arg(dir)?.raw("2>&1 |").arg("wc")?.arg("-l")?;
+/// assert_eq!(line.to_string(), "ls 'my files; rm -rf ~' 2>&1 | wc -l");
+/// # Ok::<(), shlex::QuoteError>(())
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct CmdLine {
+    quoter: Quoter,
+    line: String,
+}
+
+impl CmdLine {
+    /// Create an empty command line, which quotes values with the default [`Quoter`].
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty command line, which quotes values with `quoter`.
+    #[inline]
+    pub fn with_quoter(quoter: Quoter) -> Self {
+        CmdLine { quoter, line: String::new() }
+    }
+
+    /// Append `syntax` as-is, without quoting.  It must be a `'static` string, normally a
+    /// literal, so that a string built with `format!`, which might contain untrusted values, is
+    /// rejected at compile time:
+    ///
+    /// ```compile_fail
+    /// let user = String::from("x; rm -rf ~");
+    /// shlex::CmdLine::new().raw(&format!("grep {}", user));
+    /// ```
+    pub fn raw(mut self, syntax: &'static str) -> Self {
+        self.push(syntax);
+        self
+    }
+
+    /// Append `syntax` as-is, without quoting, like [`CmdLine::raw`], but taking text built at run
+    /// time.  It must not contain untrusted text; pass values through [`CmdLine::arg`] instead.
+    pub fn raw_trusted(mut self, syntax: &str) -> Self {
+        self.push(syntax);
+        self
+    }
+
+    /// Append `value`, quoted so the shell will take it as a single word with exactly this text.
+    pub fn arg(mut self, value: &str) -> Result<Self, QuoteError> {
+        let quoted = self.quoter.quote(value)?;
+        self.push(&quoted);
+        Ok(self)
+    }
+
+    /// Append each of `values`, quoted as by [`CmdLine::arg`].
+    pub fn args<'a, I: IntoIterator<Item = &'a str>>(mut self, values: I) -> Result<Self, QuoteError> {
+        for value in values {
+            self = self.arg(value)?;
+        }
+        Ok(self)
+    }
+
+    /// Append each of `args`, quoted as by [`CmdLine::arg`], after applying `policy` to the
+    /// untrusted ones so that the program can't mistake them for options.  See [`guard_args`].
+    pub fn guarded_args<'a, I: IntoIterator<Item = Arg<&'a str>>>(self, args: I, policy: OptionPolicy) -> Result<Self, ArgError> {
+        let args = guard_args(args, policy)?;
+        Ok(self.args(args.iter().map(|arg| &arg[..]))?)
+    }
+
+    /// The command line built so far.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.line
+    }
+
+    /// Return the command line.
+    #[inline]
+    pub fn into_string(self) -> String {
+        self.line
+    }
+
+    fn push(&mut self, part: &str) {
+        if part.is_empty() {
+            return;
+        }
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        self.line.push_str(part);
+    }
+}
+
+impl core::fmt::Display for CmdLine {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.line)
+    }
+}
+
+impl From<CmdLine> for String {
+    fn from(line: CmdLine) -> String {
+        line.line
+    }
+}
+
 /// Convenience function that consumes an iterable of words and turns it into a single string,
 /// quoting words when necessary. Consecutive words will be separated by a single space.
 ///
@@ -242,6 +633,51 @@ pub fn try_quote(in_str: &str) -> Result<Cow<'_, str>, QuoteError> {
     Quoter::new().quote(in_str)
 }
 
+/// Apply `policy` to `args`, so that the program they are passed to can't mistake an untrusted
+/// argument for an option.  See [`OptionPolicy`].
+///
+/// ```rust
+/// use shlex::{guard_args, Arg, OptionPolicy};
+/// let args = [Arg::Trusted("git"), Arg::Trusted("clone"), Arg::Untrusted("--upload-pack=evil")];
+/// assert_eq!(guard_args(args.iter().copied(), OptionPolicy::EndOfOptions).unwrap(),
+///            ["git", "clone", "--", "--upload-pack=evil"]);
+/// assert_eq!(guard_args(args.iter().copied(), OptionPolicy::PathPrefix).unwrap(),
+///            ["git", "clone", "./--upload-pack=evil"]);
+/// ```
+///
+/// The bytes equivalent is [bytes::guard_args].
+pub fn guard_args<'a, I: IntoIterator<Item = Arg<&'a str>>>(args: I, policy: OptionPolicy) -> Result<Vec<Cow<'a, str>>, ArgError> {
+    let args = bytes::guard_args(args.into_iter().map(|arg| arg.map(str::as_bytes)), policy)?;
+    // Safety: bytes::guard_args() only adds ASCII characters to whole words.
+    Ok(args.into_iter().map(|arg| match arg {
+        Cow::Borrowed(arg) => unsafe { core::str::from_utf8_unchecked(arg) }.into(),
+        Cow::Owned(arg) => unsafe { String::from_utf8_unchecked(arg) }.into(),
+    }).collect())
+}
+
+/// Split a command line using one shell [`Dialect`]'s rules, then quote each word for another.
+///
+/// For example, this translates a POSIX command into PowerShell:
+///
+/// ```rust
+/// use shlex::{translate, Dialect};
+/// assert_eq!(translate(r#"echo "it's" 'a b'"#, Dialect::Posix, Dialect::PowerShell).unwrap(),
+///            "echo 'it''s' 'a b'");
+/// ```
+///
+/// This only works for command lines made of literal strings.  If the input contains shell
+/// syntax other than quoting, such as a variable, a glob, a brace, a pipe, or a leading
+/// `NAME=value` assignment, this returns [`TranslateError::Syntax`].  Control characters, other than tab and newline, are an error
+/// unless the target dialect has escape sequences for them (see [`QuoteError::Control`]).
+///
+/// The bytes equivalent is [bytes::translate].
+pub fn translate(in_str: &str, from: Dialect, to: Dialect) -> Result<String, TranslateError> {
+    // Given valid UTF-8, bytes::translate() only returns invalid UTF-8 if the input contains
+    // escape sequences for arbitrary bytes, like `$'\xff'` in Bash.
+    String::from_utf8(bytes::translate(in_str.as_bytes(), from, to)?)
+        .map_err(|_| TranslateError::InvalidUtf8)
+}
+
 #[cfg(test)]
 static SPLIT_TEST_ITEMS: &[(&str, Option<&[&str]>)] = &[
     ("foo$baz", Some(&["foo$baz"])),
@@ -273,6 +709,108 @@ fn test_split() {
     }
 }
 
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_CSH: &[(&str, Option<&[&str]>)] = &[
+    ("foo baz", Some(&["foo", "baz"])),
+    ("'a\\!b' \"c\\!d\"", Some(&["a!b", "c!d"])),
+    ("'a\\b' \"c\\$d\"", Some(&["a\\b", "c\\$d"])),
+    ("'a\\\nb'", Some(&["a\nb"])),
+    ("'a\nb'", None),
+    ("a\\\nb", Some(&["a", "b"])),
+    ("\\\n a", Some(&["a"])),
+    ("it\\'s", Some(&["it's"])),
+    ("'it'\\''s'", Some(&["it's"])),
+    ("foo #bar", Some(&["foo"])),
+    ("\"\\\"", Some(&["\\"])),
+];
+
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_RC: &[(&str, Option<&[&str]>)] = &[
+    ("foo baz", Some(&["foo", "baz"])),
+    ("'it''s'", Some(&["it's"])),
+    ("''''", Some(&["'"])),
+    ("''", Some(&[""])),
+    ("a\\b \"c\"", Some(&["a\\b", "\"c\""])),
+    ("a\\\nb", Some(&["a", "b"])),
+    ("'a\\\nb'", Some(&["a\\\nb"])),
+    ("foo#bar\nbaz", Some(&["foo", "baz"])),
+    ("'foo#bar'", Some(&["foo#bar"])),
+    ("'foo", None),
+];
+
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_NUSHELL: &[(&str, Option<&[&str]>)] = &[
+    ("foo baz", Some(&["foo", "baz"])),
+    ("'a\\b' `c d` \"e\\\"\\n\\u{1b}\"", Some(&["a\\b", "c d", "e\"\n\x1b"])),
+    ("r#'a'b'# r##'a'#b'##", Some(&["a'b", "a'#b"])),
+    ("r#'a'#b", None),
+    ("foo'bar' ro#t", Some(&["foo'bar'", "ro#t"])),
+    ("\"\\q\"", None),
+    ("'a", None),
+    ("# comment\nfoo", Some(&["foo"])),
+];
+
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_BASH: &[(&str, Option<&[&str]>)] = &[
+    ("foo $bar", Some(&["foo", "$bar"])),
+    ("$'a\\'b\\\\c\\n\\x41\\101\\u00e9\\cA\\q'", Some(&["a'b\\c\nAAé\x01\\q"])),
+    ("$'a\\0b'c", Some(&["ac"])),
+    ("$'\\xff'", None),
+    ("$\"a\\$b\" \"$'a'\"", Some(&["a$b", "$'a'"])),
+    ("$'a", None),
+];
+
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_FISH: &[(&str, Option<&[&str]>)] = &[
+    ("foo baz", Some(&["foo", "baz"])),
+    ("'it\\'s' 'a\\b\\\\c'", Some(&["it's", "a\\b\\c"])),
+    ("\"a\\\"\\$\\b\"", Some(&["a\"$\\b"])),
+    ("a\\nb\\x41\\101\\u00e9\\e\\ c", Some(&["a\nbAAé\x1b c"])),
+    ("\\x80", None),
+    ("\\cA\\c\u{e9}", None),
+    ("a\\\nb \\\n c", Some(&["ab", "c"])),
+    ("'a", None),
+];
+
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_POWERSHELL: &[(&str, Option<&[&str]>)] = &[
+    ("foo baz", Some(&["foo", "baz"])),
+    ("'it''s' 'a\\b`c'", Some(&["it's", "a\\b`c"])),
+    ("\"a`\"\"\"`$`n`u{1b}\"", Some(&["a\"\"$\n\x1b"])),
+    ("\u{2018}it\u{2019}\u{2019}s\u{2019}", Some(&["it\u{2019}s"])),
+    ("a`\nb `\n c", Some(&["a", "b", "c"])),
+    ("a` b", Some(&["a b"])),
+    ("\"a", None),
+];
+
+#[cfg(test)]
+static SPLIT_TEST_ITEMS_PYTHON: &[(&str, Option<&[&str]>)] = &[
+    ("a\\\nb \"\\$x\" #c d\re", Some(&["a\nb", "\\$x", "#c", "d", "e"])),
+    ("\"\\\"\\\\\"", Some(&["\"\\"])),
+    ("'a", None),
+];
+
+#[test]
+fn test_split_dialects() {
+    for &(dialect, items) in &[
+        (Dialect::Csh, SPLIT_TEST_ITEMS_CSH),
+        (Dialect::Rc, SPLIT_TEST_ITEMS_RC),
+        (Dialect::Nushell, SPLIT_TEST_ITEMS_NUSHELL),
+        (Dialect::Fish, SPLIT_TEST_ITEMS_FISH),
+        (Dialect::PowerShell, SPLIT_TEST_ITEMS_POWERSHELL),
+        (Dialect::Bash, SPLIT_TEST_ITEMS_BASH),
+        (Dialect::Python, SPLIT_TEST_ITEMS_PYTHON),
+    ] {
+        for &(input, output) in items {
+            let mut shl = Shlex::new(input).dialect(dialect);
+            let res: Vec<String> = shl.by_ref().collect();
+            let res = if shl.had_error { None } else { Some(res) };
+            assert_eq!(res, output.map(|o| o.iter().map(|&x| x.to_owned()).collect()),
+                       "{:?} {:?}", dialect, input);
+        }
+    }
+}
+
 #[test]
 fn test_lineno() {
     let mut sh = Shlex::new("\nfoo\nbar");
@@ -342,6 +880,168 @@ fn test_quote() {
     assert!(ok);
 }
 
+#[test]
+fn test_quote_csh() {
+    let quoter = Quoter::new().dialect(Dialect::Csh);
+    assert_eq!(quoter.quote("").unwrap(), "''");
+    assert_eq!(quoter.quote("foobar").unwrap(), "foobar");
+    assert_eq!(quoter.quote("foo bar").unwrap(), "'foo bar'");
+    assert_eq!(quoter.quote("hello!world").unwrap(), "'hello\\!world'");
+    assert_eq!(quoter.quote("it's").unwrap(), "'it'\\''s'");
+    assert_eq!(quoter.quote("a\nb").unwrap(), "'a\\\nb'");
+    assert_eq!(quoter.quote("a\\b$c").unwrap(), "'a\\b$c'");
+    assert_eq!(quoter.quote("\n^x").unwrap(), "'\\\n'\\^'x'");
+    assert_eq!(quoter.quote("^x").unwrap(), "'^x'");
+    assert_eq!(quoter.join(vec!["echo", "!!"]).unwrap(), "echo '\\!\\!'");
+    assert_eq!(quoter.quote("\0"), Err(QuoteError::Nul));
+}
+
+#[test]
+fn test_quote_rc() {
+    let quoter = Quoter::new().dialect(Dialect::Rc);
+    assert_eq!(quoter.quote("").unwrap(), "''");
+    assert_eq!(quoter.quote("foobar").unwrap(), "foobar");
+    assert_eq!(quoter.quote("foo bar").unwrap(), "'foo bar'");
+    assert_eq!(quoter.quote("it's").unwrap(), "'it''s'");
+    assert_eq!(quoter.quote("a\\b\"$c\n").unwrap(), "'a\\b\"$c\n'");
+    assert_eq!(quoter.quote("user@host").unwrap(), "'user@host'");
+    for s in &["it's", "a\\b\"$c\n", "^#x"] {
+        let quoted = quoter.quote(s).unwrap();
+        let split: Vec<String> = Shlex::new(&quoted).dialect(Dialect::Rc).collect();
+        assert_eq!(split, vec![*s]);
+    }
+}
+
+#[test]
+fn test_quote_nushell() {
+    let quoter = Quoter::new().dialect(Dialect::Nushell);
+    let tests: &[(&str, &str)] = &[
+        ("", "''"),
+        ("foobar", "foobar"),
+        ("--foo=bar", "'--foo=bar'"),
+        ("./foo", "./foo"),
+        ("-1", "'-1'"),
+        ("1kb", "'1kb'"),
+        ("a..b", "'a..b'"),
+        ("true", "'true'"),
+        ("foo bar", "'foo bar'"),
+        ("it's", "`it's`"),
+        ("it's `x`", "\"it's `x`\""),
+        ("'`\"", "\"'`\\\"\""),
+        ("'`\"\"\"\"\"", "r#''`\"\"\"\"\"'#"),
+        ("'`'#\"\"\"\"\"\"\"", "r##''`'#\"\"\"\"\"\"\"'##"),
+        ("a\nb\x01", "\"a\\nb\\u{1}\""),
+    ];
+    for &(unquoted, quoted) in tests {
+        assert_eq!(quoter.quote(unquoted).unwrap(), quoted);
+        let split: Vec<String> = Shlex::new(quoted).dialect(Dialect::Nushell).collect();
+        assert_eq!(split, vec![unquoted]);
+    }
+}
+
+#[test]
+fn test_quote_fish() {
+    let quoter = Quoter::new().dialect(Dialect::Fish);
+    assert_eq!(quoter.quote("foo bar").unwrap(), "'foo bar'");
+    assert_eq!(quoter.quote("a\nb c\x01").unwrap(), "a\\n'b c'\\x01");
+    for s in &["a\nb c\x01", "\x7f'\"\\", "\t"] {
+        let quoted = quoter.quote(s).unwrap();
+        let split: Vec<String> = Shlex::new(&quoted).dialect(Dialect::Fish).collect();
+        assert_eq!(split, vec![*s]);
+    }
+}
+
+#[test]
+fn test_quote_powershell() {
+    let quoter = Quoter::new().dialect(Dialect::PowerShell);
+    let tests: &[(&str, &str)] = &[
+        ("", "''"),
+        ("foobar", "foobar"),
+        ("1kb", "'1kb'"),
+        ("-foo", "-foo"),
+        ("-1", "'-1'"),
+        ("it's $x", "'it''s $x'"),
+        ("it\u{2019}s", "'it\u{2019}\u{2019}s'"),
+        ("a\nb\x01\"$`\u{201c}", "\"a`nb`u{1}`\"`$```\u{201c}\""),
+    ];
+    for &(unquoted, quoted) in tests {
+        assert_eq!(quoter.quote(unquoted).unwrap(), quoted);
+        let split: Vec<String> = Shlex::new(quoted).dialect(Dialect::PowerShell).collect();
+        assert_eq!(split, vec![unquoted]);
+    }
+}
+
+#[test]
+fn test_quote_bash() {
+    let quoter = Quoter::new().dialect(Dialect::Bash);
+    let tests: &[(&str, &str)] = &[
+        ("", "''"),
+        ("foo-bar=1", "foo-bar=1"),
+        ("it's a \"test\"!", "it\\'s\\ a\\ \\\"test\\\"\\!"),
+        ("~x a:~ #b c#", "\\~x\\ a:\\~\\ #b\\ c#"),
+        ("a\nb'\\\x1b", "$'a\\nb\\'\\\\\\E'"),
+        ("\u{e9}\x01", "$'\\303\\251\\001'"),
+    ];
+    for &(unquoted, quoted) in tests {
+        assert_eq!(quoter.quote(unquoted).unwrap(), quoted);
+        let split: Vec<String> = Shlex::new(quoted).dialect(Dialect::Bash).collect();
+        assert_eq!(split, vec![unquoted]);
+    }
+}
+
+#[test]
+fn test_quote_python() {
+    let quoter = Quoter::new().dialect(Dialect::Python);
+    let tests: &[(&str, &str)] = &[
+        ("", "''"),
+        ("a=b,c%d@e+f:g.h/i-j_k", "a=b,c%d@e+f:g.h/i-j_k"),
+        ("a b'c", "'a b'\"'\"'c'"),
+        ("\u{e9}", "'\u{e9}'"),
+        ("\\$\n", "'\\$\n'"),
+    ];
+    for &(unquoted, quoted) in tests {
+        assert_eq!(quoter.quote(unquoted).unwrap(), quoted);
+        let split: Vec<String> = Shlex::new(quoted).dialect(Dialect::Python).collect();
+        assert_eq!(split, vec![unquoted]);
+    }
+}
+
+#[test]
+fn test_translate() {
+    use Dialect::*;
+    assert_eq!(translate("", Posix, Fish), Ok("".to_owned()));
+    assert_eq!(translate("ls -l 'my file' \"it's\"", Posix, Fish).unwrap(),
+               "ls -l 'my file' \"it's\"");
+    assert_eq!(translate("ls -l 'my file' \"it's\"", Posix, PowerShell).unwrap(),
+               "ls -l 'my file' 'it''s'");
+    assert_eq!(translate("ls -l 'my file' \"it's\"", Posix, Rc).unwrap(),
+               "ls -l 'my file' 'it''s'");
+    assert_eq!(translate("echo 'a\x1bb'", Posix, Fish).unwrap(), "echo a\\x1bb");
+    assert_eq!(translate("echo 'a\x1bb'", Posix, Csh), Err(TranslateError::Quote(QuoteError::Control)));
+    assert_eq!(translate("echo 'a\nb'", Posix, Csh).unwrap(), "echo 'a\\\nb'");
+    assert_eq!(translate("echo 'a\0b'", Posix, Fish), Err(TranslateError::Quote(QuoteError::Nul)));
+    assert_eq!(translate("echo \"a", Posix, Fish), Err(TranslateError::Split));
+    assert_eq!(translate("echo $HOME", Posix, Fish), Err(TranslateError::Syntax(b'$')));
+    assert_eq!(translate("echo \"$HOME\"", Posix, Fish), Err(TranslateError::Syntax(b'$')));
+    assert_eq!(translate("echo '$HOME' \\$HOME", Posix, Fish).unwrap(), "echo '$HOME' '$HOME'");
+    assert_eq!(translate("ls *.rs", Posix, Fish), Err(TranslateError::Syntax(b'*')));
+    assert_eq!(translate("ls ~/x a~b", Posix, Fish), Err(TranslateError::Syntax(b'~')));
+    assert_eq!(translate("a | b", Posix, Fish), Err(TranslateError::Syntax(b'|')));
+    assert_eq!(translate("a=b", Rc, Posix), Err(TranslateError::Syntax(b'=')));
+    assert_eq!(translate("echo a=b", Posix, Rc).unwrap(), "echo 'a=b'");
+    assert_eq!(translate("echo 'it''s' a\\b", Rc, Nushell).unwrap(), "echo `it's` 'a\\b'");
+    assert_eq!(translate("echo $'\\xff'", Bash, Posix), Err(TranslateError::InvalidUtf8));
+    assert_eq!(translate("echo $'\\xc3\\xa9'", Bash, Posix).unwrap(), "echo '\u{e9}'");
+    assert_eq!(translate("echo {a,b}", Fish, Posix), Err(TranslateError::Syntax(b'{')));
+    assert_eq!(translate("echo a}", Bash, Posix), Err(TranslateError::Syntax(b'}')));
+    assert_eq!(translate("echo '{a,b}' \\{", Bash, Fish).unwrap(), "echo '{a,b}' '{'");
+    assert_eq!(translate("FOO=1 cmd", Posix, PowerShell), Err(TranslateError::Syntax(b'=')));
+    assert_eq!(translate("FOO=1 cmd", Fish, Posix), Err(TranslateError::Syntax(b'=')));
+    assert_eq!(translate("'FOO'=1 cmd F=1", Posix, PowerShell).unwrap(), "'FOO=1' cmd 'F=1'");
+    assert_eq!(translate("1=x a-b=c", Posix, Fish).unwrap(), "'1=x' 'a-b=c'");
+    assert_eq!(translate("FOO=1 cmd", PowerShell, Posix).unwrap(), "'FOO=1' cmd");
+}
+
 #[test]
 #[allow(deprecated)]
 fn test_join() {
@@ -351,6 +1051,50 @@ fn test_join() {
     assert_eq!(join(vec!["foo bar", "baz"]), "'foo bar' baz");
 }
 
+#[test]
+fn test_guard_args() {
+    use Arg::{Trusted, Untrusted};
+    let args = [Trusted("git"), Trusted("-q"), Trusted("clone"), Untrusted("url"), Untrusted("-dir")];
+    let guarded = |policy| guard_args(args.iter().copied(), policy);
+    assert_eq!(guarded(OptionPolicy::EndOfOptions).unwrap(), ["git", "-q", "clone", "--", "url", "-dir"]);
+    assert_eq!(guarded(OptionPolicy::PathPrefix).unwrap(), ["git", "-q", "clone", "url", "./-dir"]);
+    assert_eq!(guarded(OptionPolicy::Reject), Err(ArgError::Option(4)));
+
+    // Nothing is done if no untrusted argument starts with `-`, or if there's already a `--`.
+    let safe = [Trusted("rm"), Trusted("-f"), Untrusted("a"), Untrusted("")];
+    for &policy in &[OptionPolicy::EndOfOptions, OptionPolicy::PathPrefix, OptionPolicy::Reject] {
+        assert_eq!(guard_args(safe.iter().copied(), policy).unwrap(), ["rm", "-f", "a", ""]);
+        assert_eq!(guard_args(vec![Trusted("rm"), Trusted("--"), Untrusted("-rf")], policy).unwrap(), ["rm", "--", "-rf"]);
+    }
+    let late_end = vec![Trusted("rm"), Untrusted("a"), Trusted("--"), Untrusted("-b")];
+    assert_eq!(guard_args(late_end, OptionPolicy::EndOfOptions).unwrap(), ["rm", "--", "a", "-b"]);
+
+    let quoter = Quoter::new();
+    assert_eq!(quoter.join_args(vec![Trusted("cat"), Untrusted("-n x")], OptionPolicy::EndOfOptions).unwrap(), "cat -- '-n x'");
+    assert_eq!(quoter.join_args(vec![Trusted("cat"), Untrusted("-")], OptionPolicy::PathPrefix).unwrap(), "cat ./-");
+    assert_eq!(quoter.join_args(vec![Trusted("cat"), Untrusted("\0")], OptionPolicy::Reject), Err(ArgError::Quote(QuoteError::Nul)));
+}
+
+#[test]
+fn test_cmd_line() {
+    let line = CmdLine::new()
+        .raw("cd").arg("/tmp/a b").unwrap()
+        .raw("&&").args(vec!["grep", "-e", "$USER", ""]).unwrap()
+        .raw(">").arg("out; rm -rf /").unwrap();
+    assert_eq!(line.as_str(), "cd '/tmp/a b' && grep -e '$USER' '' > 'out; rm -rf /'");
+    assert_eq!(line.clone().into_string(), line.to_string());
+    assert_eq!(CmdLine::new().raw("").raw("a").raw("").to_string(), "a");
+    let redirect = String::from("2>&1");
+    assert_eq!(CmdLine::new().raw("cmd").raw_trusted(&redirect).to_string(), "cmd 2>&1");
+    assert_eq!(CmdLine::new().arg("\0").unwrap_err(), QuoteError::Nul);
+
+    let fish = CmdLine::with_quoter(Quoter::new().dialect(Dialect::Fish)).arg("a\\b").unwrap();
+    assert_eq!(fish.as_str(), Quoter::new().dialect(Dialect::Fish).quote("a\\b").unwrap());
+    let guarded = CmdLine::new().raw("cat").guarded_args(vec![Arg::Untrusted("-n")], OptionPolicy::EndOfOptions).unwrap();
+    assert_eq!(String::from(guarded), "cat -- -n");
+    assert_eq!(CmdLine::new().guarded_args(vec![Arg::Untrusted("-n")], OptionPolicy::Reject).unwrap_err(), ArgError::Option(0));
+}
+
 #[test]
 fn test_fallible() {
     assert_eq!(try_join(vec!["\0"]), Err(QuoteError::Nul));
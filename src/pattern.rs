@@ -0,0 +1,458 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Shell pattern matching (`*`, `?` and `[...]`), as used by `case`, globs, and `${FOO%pattern}`
+//! and friends.
+//!
+//! Matching is done on bytes, like a shell running in the C locale.  Quoted characters in a
+//! pattern only match themselves, so patterns are built from words that remember which of their
+//! bytes were quoted, such as the [`QuotedWord`]s returned by [`Shlex::next_with_quoting`]:
+//!
+//! ```rust
+//! use shlex::bytes::Shlex;
+//! use shlex::pattern::Pattern;
+//!
+//! let word = Shlex::new(b"*.log|'*'").next_with_quoting().unwrap();
+//! let pattern = Pattern::from_case_word(&word);
+//! assert!(pattern.matches(b"debug.log"));
+//! assert!(pattern.matches(b"*"));
+//! assert!(!pattern.matches(b"debug.txt"));
+//! assert_eq!(pattern.to_glob(), b"*.log|\\*");
+//! ```
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::bytes::QuotedWord;
+#[cfg(all(doc, not(doctest)))]
+use crate::bytes::Shlex;
+
+/// A compiled shell pattern.
+///
+/// A pattern consists of one or more alternatives, and matches a string if any of them match all
+/// of it.  Only a `case`-style pattern (see [`Pattern::new_case`]) can have more than one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    alternatives: Vec<Vec<Token>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A byte that matches itself.
+    Byte(u8),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `[...]`
+    Bracket { negated: bool, items: Vec<Item> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Byte(u8),
+    Range(u8, u8),
+    /// A character class like `[:alpha:]`.
+    Class(Class),
+    /// A character class whose name was not recognized, which matches nothing.
+    UnknownClass(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Xdigit,
+}
+
+impl Class {
+    fn from_name(name: &[u8]) -> Option<Class> {
+        Some(match name {
+            b"alnum" => Class::Alnum,
+            b"alpha" => Class::Alpha,
+            b"blank" => Class::Blank,
+            b"cntrl" => Class::Cntrl,
+            b"digit" => Class::Digit,
+            b"graph" => Class::Graph,
+            b"lower" => Class::Lower,
+            b"print" => Class::Print,
+            b"punct" => Class::Punct,
+            b"space" => Class::Space,
+            b"upper" => Class::Upper,
+            b"xdigit" => Class::Xdigit,
+            _ => { return None; },
+        })
+    }
+
+    fn name(self) -> &'static [u8] {
+        match self {
+            Class::Alnum => b"alnum",
+            Class::Alpha => b"alpha",
+            Class::Blank => b"blank",
+            Class::Cntrl => b"cntrl",
+            Class::Digit => b"digit",
+            Class::Graph => b"graph",
+            Class::Lower => b"lower",
+            Class::Print => b"print",
+            Class::Punct => b"punct",
+            Class::Space => b"space",
+            Class::Upper => b"upper",
+            Class::Xdigit => b"xdigit",
+        }
+    }
+
+    fn matches(self, c: u8) -> bool {
+        match self {
+            Class::Alnum => c.is_ascii_alphanumeric(),
+            Class::Alpha => c.is_ascii_alphabetic(),
+            Class::Blank => c == b' ' || c == b'\t',
+            Class::Cntrl => c.is_ascii_control(),
+            Class::Digit => c.is_ascii_digit(),
+            Class::Graph => c.is_ascii_graphic(),
+            Class::Lower => c.is_ascii_lowercase(),
+            Class::Print => c.is_ascii_graphic() || c == b' ',
+            Class::Punct => c.is_ascii_punctuation(),
+            // Unlike `u8::is_ascii_whitespace`, this includes vertical tab.
+            Class::Space => matches!(c, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r'),
+            Class::Upper => c.is_ascii_uppercase(),
+            Class::Xdigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+impl Item {
+    fn matches(&self, c: u8) -> bool {
+        match *self {
+            Item::Byte(b) => b == c,
+            Item::Range(lo, hi) => lo <= c && c <= hi,
+            Item::Class(class) => class.matches(c),
+            Item::UnknownClass(_) => false,
+        }
+    }
+}
+
+impl Token {
+    /// Whether this token, which must not be `Star`, matches the byte `c`.
+    fn matches(&self, c: u8) -> bool {
+        match self {
+            Token::Byte(b) => *b == c,
+            Token::Any => true,
+            Token::Star => unreachable!(),
+            Token::Bracket { negated, items } => items.iter().any(|item| item.matches(c)) != *negated,
+        }
+    }
+}
+
+impl Pattern {
+    /// Compile a pattern.  `quoted[i]` says whether `text[i]` was quoted, in which case it only
+    /// matches itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `text` and `quoted` have different lengths.
+    pub fn new(text: &[u8], quoted: &[bool]) -> Self {
+        assert_eq!(text.len(), quoted.len());
+        Pattern { alternatives: alloc::vec![compile(text, quoted)] }
+    }
+
+    /// Compile a pattern from a word split by [`Shlex::next_with_quoting`].
+    pub fn from_word(word: &QuotedWord) -> Self {
+        Self::new(&word.bytes, &word.quoted)
+    }
+
+    /// Compile a pattern in which nothing is quoted.
+    pub fn unquoted(text: &[u8]) -> Self {
+        Self::new(text, &alloc::vec![false; text.len()])
+    }
+
+    /// Compile a pattern like the ones in a shell `case` statement, in which an unquoted `|`
+    /// separates alternatives: `*.log|'*'` matches anything ending in `.log`, as well as `*`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `text` and `quoted` have different lengths.
+    pub fn new_case(text: &[u8], quoted: &[bool]) -> Self {
+        assert_eq!(text.len(), quoted.len());
+        let mut alternatives = Vec::new();
+        let mut start = 0;
+        for end in (0..=text.len()).filter(|&i| i == text.len() || (text[i] == b'|' && !quoted[i])) {
+            alternatives.push(compile(&text[start..end], &quoted[start..end]));
+            start = end + 1;
+        }
+        Pattern { alternatives }
+    }
+
+    /// Compile a `case`-style pattern (see [`Pattern::new_case`]) from a word split by
+    /// [`Shlex::next_with_quoting`].
+    pub fn from_case_word(word: &QuotedWord) -> Self {
+        Self::new_case(&word.bytes, &word.quoted)
+    }
+
+    /// Whether the pattern matches all of `text`.
+    pub fn matches(&self, text: &[u8]) -> bool {
+        self.alternatives.iter().any(|tokens| matches_tokens(tokens, text))
+    }
+
+    /// Render the pattern as a glob, in the syntax understood by shells and by `fnmatch(3)` and
+    /// `glob(3)`: characters that only match themselves but would otherwise be special are
+    /// escaped with backslashes.  Alternatives are separated by `|`, as in a `case` statement.
+    pub fn to_glob(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, tokens) in self.alternatives.iter().enumerate() {
+            if i > 0 {
+                out.push(b'|');
+            }
+            for token in tokens {
+                match token {
+                    Token::Byte(c) => push_escaped(&mut out, *c, b"*?[\\|"),
+                    Token::Any => out.push(b'?'),
+                    Token::Star => out.push(b'*'),
+                    Token::Bracket { negated, items } => {
+                        out.push(b'[');
+                        if *negated {
+                            out.push(b'!');
+                        }
+                        for item in items {
+                            match item {
+                                Item::Byte(c) => push_escaped(&mut out, *c, BRACKET_SPECIAL),
+                                Item::Range(lo, hi) => {
+                                    push_escaped(&mut out, *lo, BRACKET_SPECIAL);
+                                    out.push(b'-');
+                                    push_escaped(&mut out, *hi, BRACKET_SPECIAL);
+                                },
+                                Item::Class(class) => push_class(&mut out, class.name()),
+                                Item::UnknownClass(name) => push_class(&mut out, name),
+                            }
+                        }
+                        out.push(b']');
+                    },
+                }
+            }
+        }
+        out
+    }
+
+    /// Returns the length of the shortest (or, if `longest`, longest) prefix of `text` that the
+    /// pattern matches.
+    pub(crate) fn match_prefix(&self, text: &[u8], longest: bool) -> Option<usize> {
+        let mut lens = 0..=text.len();
+        let pred = |&len: &usize| self.matches(&text[..len]);
+        if longest { lens.rev().find(pred) } else { lens.find(pred) }
+    }
+
+    /// Returns the start of the shortest (or, if `longest`, longest) suffix of `text` that the
+    /// pattern matches.
+    pub(crate) fn match_suffix(&self, text: &[u8], longest: bool) -> Option<usize> {
+        let mut starts = 0..=text.len();
+        let pred = |&start: &usize| self.matches(&text[start..]);
+        if longest { starts.find(pred) } else { starts.rev().find(pred) }
+    }
+
+    /// Whether the pattern has no special characters, and therefore only matches one string.
+    pub(crate) fn is_literal(&self) -> bool {
+        match &self.alternatives[..] {
+            [tokens] => tokens.iter().all(|token| matches!(token, Token::Byte(_))),
+            _ => false,
+        }
+    }
+
+    /// Whether the pattern is empty, and therefore only matches the empty string.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.alternatives.iter().all(Vec::is_empty)
+    }
+}
+
+/// The bytes that need escaping inside a bracket expression.
+const BRACKET_SPECIAL: &[u8] = b"[]!^-\\";
+
+fn push_escaped(out: &mut Vec<u8>, c: u8, special: &[u8]) {
+    if special.contains(&c) {
+        out.push(b'\\');
+    }
+    out.push(c);
+}
+
+fn push_class(out: &mut Vec<u8>, name: &[u8]) {
+    out.extend_from_slice(b"[:");
+    out.extend_from_slice(name);
+    out.extend_from_slice(b":]");
+}
+
+fn compile(text: &[u8], quoted: &[bool]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i];
+        if quoted[i] {
+            tokens.push(Token::Byte(c));
+            i += 1;
+            continue;
+        }
+        match c {
+            b'*' => {
+                // Consecutive stars are equivalent to one.
+                if tokens.last() != Some(&Token::Star) {
+                    tokens.push(Token::Star);
+                }
+            },
+            b'?' => tokens.push(Token::Any),
+            b'[' => {
+                if let Some((token, end)) = parse_bracket(text, quoted, i + 1) {
+                    tokens.push(token);
+                    i = end;
+                    continue;
+                }
+                // An unterminated bracket matches a literal `[`.
+                tokens.push(Token::Byte(c));
+            },
+            _ => tokens.push(Token::Byte(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+fn matches_tokens(tokens: &[Token], text: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume if the current attempt fails: the index of the last `*` seen, and the
+    // position in `text` it was last tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < text.len() {
+        match tokens.get(p) {
+            Some(Token::Star) => {
+                backtrack = Some((p, s));
+                p += 1;
+                continue;
+            },
+            Some(token) if token.matches(text[s]) => {
+                p += 1;
+                s += 1;
+                continue;
+            },
+            _ => {},
+        }
+        match backtrack {
+            Some((star_p, star_s)) => {
+                p = star_p + 1;
+                s = star_s + 1;
+                backtrack = Some((star_p, s));
+            },
+            None => { return false; },
+        }
+    }
+    tokens[p..].iter().all(|token| *token == Token::Star)
+}
+
+/// Parse a bracket expression starting at `text[start]`, just after the opening `[`.  Returns the
+/// token and the index after the closing `]`, or `None` if there is no closing `]`.
+fn parse_bracket(text: &[u8], quoted: &[bool], start: usize) -> Option<(Token, usize)> {
+    let unquoted = |i: usize, c: u8| text.get(i) == Some(&c) && !quoted[i];
+    let mut i = start;
+    let negated = unquoted(i, b'!') || unquoted(i, b'^');
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *text.get(i)?;
+        if unquoted(i, b']') && !first {
+            return Some((Token::Bracket { negated, items }, i + 1));
+        }
+        first = false;
+        if unquoted(i, b'[') && unquoted(i + 1, b':') {
+            let name_start = i + 2;
+            if let Some(len) = text[name_start..].windows(2).position(|w| w == b":]") {
+                let name = &text[name_start..name_start + len];
+                items.push(Class::from_name(name).map_or_else(|| Item::UnknownClass(name.to_vec()), Item::Class));
+                i = name_start + len + 2;
+                continue;
+            }
+        }
+        if unquoted(i + 1, b'-') && i + 2 < text.len() && !unquoted(i + 2, b']') {
+            items.push(Item::Range(c, text[i + 2]));
+            i += 3;
+        } else {
+            items.push(Item::Byte(c));
+            i += 1;
+        }
+    }
+}
+
+#[test]
+fn test_pattern() {
+    let cases: &[(&str, &str, bool)] = &[
+        ("", "", true),
+        ("", "a", false),
+        ("abc", "abc", true),
+        ("a?c", "abc", true),
+        ("a?c", "ac", false),
+        ("*", "", true),
+        ("a*", "abc", true),
+        ("*c", "abc", true),
+        ("*b*", "abc", true),
+        ("a*b*c", "aXbYbZc", true),
+        ("a*b*c", "aXbYbZ", false),
+        ("[abc]", "b", true),
+        ("[!abc]", "b", false),
+        ("[^abc]", "d", true),
+        ("[a-c]x", "bx", true),
+        ("[a-]", "-", true),
+        ("[]a]", "]", true),
+        ("[!]]", "]", false),
+        ("[[:digit:]]*", "5abc", true),
+        ("[[:alpha:][:digit:]]", "_", false),
+        ("[[:bogus:]]", "b", false),
+        ("a[", "a[", true),
+        ("a[b", "ab", false),
+    ];
+    for &(pat, text, expected) in cases {
+        assert_eq!(Pattern::unquoted(pat.as_bytes()).matches(text.as_bytes()), expected, "{:?} vs {:?}", pat, text);
+    }
+    let quoted_star = Pattern::new(b"a*", &[false, true]);
+    assert!(quoted_star.matches(b"a*"));
+    assert!(!quoted_star.matches(b"ab"));
+}
+
+#[test]
+fn test_case_pattern() {
+    let pattern = Pattern::new_case(b"*.c|a|b|", &[false; 8]);
+    for &(text, expected) in &[("x.c", true), ("a", true), ("", true), ("a|b", false), ("ab", false)] {
+        assert_eq!(pattern.matches(text.as_bytes()), expected, "{:?}", text);
+    }
+    // Quoted bars, and bars in non-case patterns, are literal.
+    assert!(Pattern::new_case(b"a|b", &[false, true, false]).matches(b"a|b"));
+    assert!(Pattern::unquoted(b"a|b").matches(b"a|b"));
+    assert!(!Pattern::unquoted(b"a|b").matches(b"a"));
+}
+
+#[test]
+fn test_to_glob() {
+    let cases: &[(&str, &str, &str)] = &[
+        // The pattern, a mask with `q` for quoted bytes, and the expected glob.
+        ("a*b?", "----", "a*b?"),
+        ("a*b?", "-qq-", "a\\*b?"),
+        ("[!a-z]", "------", "[!a-z]"),
+        ("[]!-]x", "------", "[\\]\\!\\-]x"),
+        ("[[:alpha:][:bogus:]]", "--------------------", "[[:alpha:][:bogus:]]"),
+        ("a[b\\", "----", "a\\[b\\\\"),
+        ("x|||[*]", "--q----", "x|\\||[*]"),
+    ];
+    for &(text, mask, expected) in cases {
+        let quoted: Vec<bool> = mask.bytes().map(|c| c == b'q').collect();
+        let pattern = Pattern::new_case(text.as_bytes(), &quoted);
+        let glob = pattern.to_glob();
+        assert_eq!(core::str::from_utf8(&glob).unwrap(), expected, "{:?}", text);
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Building [`std::process::Command`]s from command strings.  Only available with the `std`
+//! feature.
+//!
+//! [`command`] (also available as `shlex::command`) replaces the common pattern of
+//! `Command::new(&argv[0]).args(&argv[1..])` on the output of [`split`](crate::split).  Unlike
+//! that, it applies leading `NAME=value` assignments to the command's environment, as a shell
+//! would, returns an error rather than panicking on an empty command, and by default rejects
+//! unquoted operators such as `;`, `|` and `>`, which a shell would treat as separators or
+//! redirections but `split` passes through as arguments.
+//!
+//! ```
+//! # #[cfg(unix)] {
+//! let output = shlex::command("GREETING='hello there' sh -c 'echo \"$GREETING, $1\"' sh world")
+//!     .unwrap()
+//!     .output()
+//!     .unwrap();
+//! assert_eq!(output.stdout, b"hello there, world\n");
+//! assert!(shlex::command("ls > files.txt").is_err());
+//! # }
+//! ```
+//!
+//! Words are only split and have their quotes removed: expansions such as `$HOME` or `*` are
+//! passed to the program as they were written.  Use the [`expand`](crate::expand) module first
+//! if they should be expanded.
+//!
+//! In the other direction, with the `render-command` feature, [`render`] turns a `Command` back
+//! into a shell command line, such as `cd -- /tmp && env -u HOME FOO='x y' prog arg`, for logging
+//! or for reproducing it by hand.  The `render-tokio` feature adds `render_tokio`, for
+//! `tokio::process::Command`s.
+
+use std::ffi::OsString;
+use std::process::Command;
+
+use crate::command::{self as parser, assignment, SyntaxError};
+#[cfg(feature = "render-command")]
+use crate::command::RESERVED_WORDS;
+use crate::word::{self, ParseError, Word};
+use crate::{guard_args, Arg, ArgError, OptionPolicy};
+#[cfg(feature = "render-command")]
+use crate::{QuoteError, Quoter};
+#[cfg(feature = "render-command")]
+use crate::word::{is_name_char, is_name_start};
+
+/// Errors from [`command`] and [`CommandParser::parse`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// There was no program to run: the input was empty, or only had assignments.
+    Empty,
+    /// The input contained an unquoted operator, such as `;`, `|` or `>`, starting at the given
+    /// byte offset.  Operators are only allowed if [`CommandParser::allow_operators`] was called.
+    Operator { offset: usize },
+    /// The input could not be split, such as because it ended inside a quotation.
+    Syntax(SyntaxError),
+    /// A word contained invalid UTF-8, produced by an escape such as `$'\xff'`, on a platform
+    /// whose arguments must be valid Unicode.
+    InvalidUtf8,
+}
+
+impl core::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CommandError::Empty => f.write_str("empty command"),
+            CommandError::Operator { offset } => write!(f, "unquoted shell operator at byte {}", offset),
+            CommandError::Syntax(err) => err.fmt(f),
+            CommandError::InvalidUtf8 => f.write_str("command contains invalid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<ParseError> for CommandError {
+    fn from(err: ParseError) -> Self {
+        CommandError::Syntax(SyntaxError::Word(err))
+    }
+}
+
+/// Turns command strings into [`Command`]s, with options.
+///
+/// [`command`] uses the default options.
+#[derive(Default, Debug, Clone)]
+pub struct CommandParser {
+    allow_operators: bool,
+}
+
+impl CommandParser {
+    /// Create a new [`CommandParser`] with default settings.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to allow unquoted operators, such as `;`, `|`, `&&` and `>`.  If allowed, they
+    /// are passed to the program as arguments, as [`split`](crate::split) does, rather than
+    /// acting as they would in a shell.  By default they are a [`CommandError::Operator`].
+    #[inline]
+    pub fn allow_operators(mut self, allow: bool) -> Self {
+        self.allow_operators = allow;
+        self
+    }
+
+    /// Split `input` into a [`Command`].  The first word after any leading `NAME=value`
+    /// assignments is the program, the rest are its arguments, and the assignments are set in its
+    /// environment.
+    pub fn parse(&self, input: &str) -> Result<Command, CommandError> {
+        let (assignments, words) = self.split(input)?;
+        let mut words = words.into_iter();
+        let program = words.next().ok_or(CommandError::Empty)?;
+        let mut command = Command::new(os_string(program.cooked())?);
+        for word in words {
+            command.arg(os_string(word.cooked())?);
+        }
+        for assignment in assignments {
+            command.env(assignment.name, os_string(assignment.value.cooked())?);
+        }
+        Ok(command)
+    }
+
+    /// Split `input` into its leading assignments and the words after them.
+    fn split(&self, input: &str) -> Result<(Vec<parser::Assignment>, Vec<Word>), CommandError> {
+        let input = input.as_bytes();
+        if self.allow_operators {
+            let mut words = word::parse(input)?.into_iter().peekable();
+            let mut assignments = Vec::new();
+            while let Some(assignment) = words.peek().and_then(assignment) {
+                assignments.push(assignment);
+                words.next();
+            }
+            return Ok((assignments, words.collect()));
+        }
+        let command = parser::parse_simple_command(input).map_err(|err| match err {
+            // If the unexpected token isn't an operator, it's the start of a command on the next
+            // line, and the newline before it was the operator.
+            SyntaxError::Unexpected { offset } if offset < input.len() => CommandError::Operator {
+                offset: if word::is_operator_start(input[offset]) {
+                    offset
+                } else {
+                    input[..offset].iter().rposition(|&c| c == b'\n').unwrap_or(offset)
+                },
+            },
+            err => CommandError::Syntax(err),
+        })?;
+        if let Some(redirection) = command.redirections.first() {
+            return Err(CommandError::Operator { offset: redirection.span.start });
+        }
+        Ok((command.assignments, command.words))
+    }
+
+    /// Split `input` into arguments for an existing command.  Unlike in [`CommandParser::parse`],
+    /// words that look like assignments are arguments too.
+    fn args(&self, input: &str) -> Result<Vec<OsString>, CommandError> {
+        let (assignments, words) = self.split(input)?;
+        let assignments = assignments.into_iter().map(|assignment| {
+            let mut arg = assignment.name.into_bytes();
+            arg.push(b'=');
+            arg.extend(assignment.value.cooked());
+            arg
+        });
+        assignments.chain(words.iter().map(Word::cooked)).map(os_string).collect()
+    }
+}
+
+/// Split `input` into a [`Command`], with its leading `NAME=value` assignments as environment
+/// variables.  Unquoted operators, such as `;`, `|` or `>`, and empty commands are errors.
+///
+/// This is the same as `CommandParser::new().parse(input)`; see [`CommandParser`] for options.
+pub fn command(input: &str) -> Result<Command, CommandError> {
+    CommandParser::new().parse(input)
+}
+
+/// Extension methods for [`Command`], in the manner of `std::os::unix::process::CommandExt`.
+pub trait CommandExt {
+    /// Split `input` into a new command.  See [`command`].
+    fn from_shell(input: &str) -> Result<Self, CommandError> where Self: Sized;
+
+    /// Split `input` and add the words as arguments, rejecting unquoted operators as [`command`]
+    /// does.  Words that look like assignments are added as arguments, since they come after the
+    /// program.
+    fn shell_args(&mut self, input: &str) -> Result<&mut Self, CommandError>;
+
+    /// Add `args` as arguments, applying `policy` to the untrusted ones so that the program
+    /// can't mistake them for options.  See [`guard_args`].
+    fn guarded_args<'a, I: IntoIterator<Item = Arg<&'a str>>>(&mut self, args: I, policy: OptionPolicy) -> Result<&mut Self, ArgError>;
+}
+
+impl CommandExt for Command {
+    fn from_shell(input: &str) -> Result<Self, CommandError> {
+        command(input)
+    }
+
+    fn shell_args(&mut self, input: &str) -> Result<&mut Self, CommandError> {
+        Ok(self.args(CommandParser::new().args(input)?))
+    }
+
+    fn guarded_args<'a, I: IntoIterator<Item = Arg<&'a str>>>(&mut self, args: I, policy: OptionPolicy) -> Result<&mut Self, ArgError> {
+        Ok(self.args(guard_args(args, policy)?.iter().map(|arg| &arg[..])))
+    }
+}
+
+/// Errors from [`render`] and [`CommandRenderer::render`].
+#[cfg(feature = "render-command")]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderError {
+    /// A word could not be quoted.
+    Quote(QuoteError),
+    /// The program, an argument, an environment variable or the directory was not valid UTF-8.
+    InvalidUtf8,
+    /// The command's environment was changed, so it would have to be run with `env`, but the
+    /// program's name contains `=`, which `env` would take as another variable assignment.
+    EnvProgram,
+}
+
+#[cfg(feature = "render-command")]
+impl From<QuoteError> for RenderError {
+    fn from(err: QuoteError) -> Self {
+        RenderError::Quote(err)
+    }
+}
+
+#[cfg(feature = "render-command")]
+impl core::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RenderError::Quote(err) => err.fmt(f),
+            RenderError::InvalidUtf8 => f.write_str("command contains invalid UTF-8"),
+            RenderError::EnvProgram => f.write_str("program name contains `=`, so it can't be run with env"),
+        }
+    }
+}
+
+#[cfg(feature = "render-command")]
+impl std::error::Error for RenderError {}
+
+/// Renders [`Command`]s as shell command lines, with options.  Only available with the
+/// `render-command` feature, which needs Rust 1.57.
+///
+/// The command line has the form `cd -- dir && env -i -u NAME NAME=value program args...`, where
+/// each part is only present if needed: `cd` if the command has a current directory, and `env`
+/// if its environment was changed.  Every word is quoted with a [`Quoter`], so the line can be
+/// pasted into a POSIX shell to run the same command.  A program named like a reserved word, such
+/// as `if`, is always quoted, and `env` is given `--` before a program starting with `-`.
+/// Rendering fails with [`RenderError::EnvProgram`] if `env` is needed but the program's name
+/// contains `=`.
+///
+/// [`render`] uses the default options.
+#[cfg(feature = "render-command")]
+#[derive(Default, Debug, Clone)]
+pub struct CommandRenderer {
+    quoter: Quoter,
+    env_clear: bool,
+}
+
+#[cfg(feature = "render-command")]
+#[clippy::msrv = "1.57"]
+impl CommandRenderer {
+    /// Create a new [`CommandRenderer`] with default settings.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the [`Quoter`] to quote words with.  Its dialect must accept POSIX `cd`, `&&` and
+    /// `env` syntax.
+    #[inline]
+    pub fn quoter(mut self, quoter: Quoter) -> Self {
+        self.quoter = quoter;
+        self
+    }
+
+    /// Set whether the command's environment was cleared with [`Command::env_clear`], which
+    /// `Command` has no way to report, so that the line starts with `env -i`.  By default it is
+    /// assumed not to have been.
+    #[inline]
+    pub fn env_clear(mut self, cleared: bool) -> Self {
+        self.env_clear = cleared;
+        self
+    }
+
+    /// Render `command` as a shell command line.
+    pub fn render(&self, command: &Command) -> Result<String, RenderError> {
+        let quote = |word: &std::ffi::OsStr| -> Result<String, RenderError> {
+            let word = word.to_str().ok_or(RenderError::InvalidUtf8)?;
+            Ok(self.quoter.quote(word)?.into_owned())
+        };
+        let mut words = Vec::new();
+        if let Some(dir) = command.get_current_dir() {
+            // `--` keeps `cd` from taking `-` as `$OLDPWD`, or a directory starting with `-` as
+            // an option.
+            words.push("cd".to_owned());
+            words.push("--".to_owned());
+            words.push(quote(dir.as_os_str())?);
+            words.push("&&".to_owned());
+        }
+        let mut removed = Vec::new();
+        let mut set = Vec::new();
+        for (name, value) in command.get_envs() {
+            match value {
+                // Removing a variable from a cleared environment does nothing.
+                None if self.env_clear => {},
+                None => {
+                    removed.push("-u".to_owned());
+                    removed.push(quote(name)?);
+                },
+                Some(value) => {
+                    let name = name.to_str().ok_or(RenderError::InvalidUtf8)?;
+                    let is_name = name.bytes().next().map_or(false, is_name_start) && name.bytes().all(is_name_char);
+                    set.push(if is_name {
+                        format!("{}={}", name, quote(value)?)
+                    } else {
+                        // `env` splits at the first `=`, so this is only wrong if `name` has one,
+                        // in which case there's no way to set it.
+                        self.quoter.quote(&format!("{}={}", name, value.to_str().ok_or(RenderError::InvalidUtf8)?))?.into_owned()
+                    });
+                },
+            }
+        }
+        let program = command.get_program().to_str().ok_or(RenderError::InvalidUtf8)?;
+        if self.env_clear || !removed.is_empty() || !set.is_empty() {
+            // `env` would take the program as another assignment, however it's quoted.
+            if program.contains('=') {
+                return Err(RenderError::EnvProgram);
+            }
+            words.push("env".to_owned());
+            if self.env_clear {
+                words.push("-i".to_owned());
+            }
+            words.extend(removed);
+            words.extend(set);
+            if program.starts_with('-') {
+                words.push("--".to_owned());
+            }
+            words.push(quote(program.as_ref())?);
+        } else if RESERVED_WORDS.contains(&program) {
+            // Reserved words don't need quoting as arguments, but do in command position, or the
+            // shell would take them as syntax.  None of them contain `'`.
+            words.push(format!("'{}'", program));
+        } else {
+            words.push(quote(program.as_ref())?);
+        }
+        for arg in command.get_args() {
+            words.push(quote(arg)?);
+        }
+        Ok(words.join(" "))
+    }
+
+    /// Render a `tokio` `command` as a shell command line.  Only available with the
+    /// `render-tokio` feature.
+    #[cfg(feature = "render-tokio")]
+    pub fn render_tokio(&self, command: &tokio::process::Command) -> Result<String, RenderError> {
+        self.render(command.as_std())
+    }
+}
+
+/// Render `command` as a shell command line, such as `cd -- /tmp && env FOO=x ls -l`.  Only
+/// available with the `render-command` feature.
+///
+/// This is the same as `CommandRenderer::new().render(command)`; see [`CommandRenderer`] for
+/// details and options.
+#[cfg(feature = "render-command")]
+pub fn render(command: &Command) -> Result<String, RenderError> {
+    CommandRenderer::new().render(command)
+}
+
+/// Render a `tokio` `command` as a shell command line.  Only available with the
+/// `render-tokio` feature.
+///
+/// This is the same as `CommandRenderer::new().render_tokio(command)`.
+#[cfg(feature = "render-tokio")]
+pub fn render_tokio(command: &tokio::process::Command) -> Result<String, RenderError> {
+    CommandRenderer::new().render_tokio(command)
+}
+
+#[cfg(unix)]
+fn os_string(bytes: Vec<u8>) -> Result<OsString, CommandError> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: Vec<u8>) -> Result<OsString, CommandError> {
+    String::from_utf8(bytes).map(OsString::from).map_err(|_| CommandError::InvalidUtf8)
+}
+
+#[cfg(all(test, unix))]
+fn run(command: &mut Command) -> String {
+    String::from_utf8(command.output().unwrap().stdout).unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command() {
+    let printf = r#"sh -c 'printf "[%s]" "$A" "$B" "$@"' sh"#;
+    assert_eq!(run(&mut command(&format!("A=1 B='x y' {} one 'two three' C=3", printf)).unwrap()), "[1][x y][one][two three][C=3]");
+    assert_eq!(run(&mut command(&format!("{} $'a\\tb' \"$HOME\" '*'", printf)).unwrap()), "[][][a\tb][$HOME][*]");
+
+    let parser = CommandParser::new().allow_operators(true);
+    assert_eq!(run(&mut parser.parse(&format!("A=1 {} '>' a>b c;d | x=1", printf)).unwrap()), "[1][][>][a>b][c;d][|][x=1]");
+
+    let mut sh = Command::from_shell(printf).unwrap();
+    sh.shell_args("A=1 'b c'").unwrap().arg("d");
+    assert_eq!(run(&mut sh), "[][][A=1][b c][d]");
+
+    let mut sh = Command::from_shell(printf).unwrap();
+    sh.guarded_args(vec![Arg::Trusted("a"), Arg::Untrusted("-b"), Arg::Trusted("-c")], OptionPolicy::PathPrefix).unwrap();
+    assert_eq!(run(&mut sh), "[][][a][./-b][-c]");
+    let err = Command::new("rm").guarded_args(vec![Arg::Untrusted("-rf")], OptionPolicy::Reject).unwrap_err();
+    assert_eq!(err, ArgError::Option(0));
+}
+
+#[test]
+fn test_command_errors() {
+    let err = |input: &str| command(input).unwrap_err();
+    assert_eq!(err(""), CommandError::Empty);
+    assert_eq!(err(" \n"), CommandError::Empty);
+    assert_eq!(err("A=1 B=2"), CommandError::Empty);
+    assert_eq!(err("a; b"), CommandError::Operator { offset: 1 });
+    assert_eq!(err("a | b"), CommandError::Operator { offset: 2 });
+    assert_eq!(err("a && b"), CommandError::Operator { offset: 2 });
+    assert_eq!(err("a\nb"), CommandError::Operator { offset: 1 });
+    assert_eq!(err("a >out"), CommandError::Operator { offset: 2 });
+    assert_eq!(err("a 2>&1"), CommandError::Operator { offset: 2 });
+    assert_eq!(err("a 'b"), CommandError::Syntax(SyntaxError::Word(ParseError::Unterminated)));
+    assert_eq!(err("a >"), CommandError::Syntax(SyntaxError::Unexpected { offset: 3 }));
+    assert_eq!(CommandParser::new().allow_operators(true).parse("").unwrap_err(), CommandError::Empty);
+    assert_eq!(Command::new("a").shell_args("b | c").unwrap_err(), CommandError::Operator { offset: 2 });
+}
+
+#[cfg(all(unix, feature = "render-command"))]
+#[test]
+fn test_render() {
+    let mut ls = Command::new("ls");
+    ls.arg("-l").arg("my file");
+    assert_eq!(render(&ls).unwrap(), "ls -l 'my file'");
+    ls.current_dir("/my dir").env("B", "x y").env("A", "1").env_remove("HOME");
+    assert_eq!(render(&ls).unwrap(), "cd -- '/my dir' && env -u HOME A=1 B='x y' ls -l 'my file'");
+    assert_eq!(CommandRenderer::new().env_clear(true).render(&ls).unwrap(), "cd -- '/my dir' && env -i A=1 B='x y' ls -l 'my file'");
+    assert_eq!(render(Command::new("a").current_dir("-")).unwrap(), "cd -- - && a");
+    assert_eq!(render(Command::new("a").current_dir("-n")).unwrap(), "cd -- -n && a");
+    let renderer = CommandRenderer::new().quoter(Quoter::new().forbid_raw_control(true));
+    assert_eq!(renderer.render(Command::new("a").arg("\x01")), Err(RenderError::Quote(QuoteError::Control)));
+    assert_eq!(render(Command::new("a").env("B C", "d").env("E", "")).unwrap(), "env 'B C=d' E='' a");
+    assert_eq!(render(Command::new("if").arg("x")).unwrap(), "'if' x");
+    assert_eq!(render(&Command::new("done")).unwrap(), "'done'");
+    assert_eq!(render(Command::new("if").env("A", "1")).unwrap(), "env A=1 if");
+    assert_eq!(render(&Command::new("a=b")).unwrap(), "'a=b'");
+    assert_eq!(render(Command::new("a=b").env("A", "1")), Err(RenderError::EnvProgram));
+    assert_eq!(render(Command::new("-i").env("A", "1")).unwrap(), "env A=1 -- -i");
+    assert_eq!(render(&Command::new("-i")).unwrap(), "-i");
+
+    // Running the rendered line gives the same result as running the command.
+    let mut sh = Command::new("sh");
+    sh.args(&["-c", "printf '[%s]' \"$PWD\" \"${HOME-unset}\" \"$A\" \"$@\"", "sh", "$x", "a'b", "*"])
+        .current_dir("/")
+        .env("A", "\"quoted\" $var")
+        .env_remove("HOME");
+    let line = render(&sh).unwrap();
+    assert_eq!(run(Command::new("sh").arg("-c").arg(&line)), run(&mut sh));
+    assert_eq!(run(&mut sh), "[/][unset][\"quoted\" $var][$x][a'b][*]");
+}
+
+#[cfg(all(unix, feature = "render-tokio"))]
+#[test]
+fn test_render_tokio() {
+    let mut command = tokio::process::Command::new("echo");
+    command.arg("a b").env("X", "1");
+    assert_eq!(render_tokio(&command).unwrap(), "env X=1 echo 'a b'");
+}
@@ -0,0 +1,883 @@
+// Copyright 2015 Nicholas Allegra (comex).
+// Licensed under the Apache License, Version 2.0 <https://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A parser that splits POSIX shell input into [`Word`]s, keeping track of how each part of each
+//! word was quoted, and of the expansions it contains.
+//!
+//! Unlike [`bytes::Shlex`](crate::bytes::Shlex), which produces the final text of each word, this
+//! produces a syntax tree: a list of [`Segment`]s per word, each with the range of the input it
+//! came from.  This is what [`expand`](crate::expand) works from, and is also useful for tools
+//! that want to inspect or rewrite a command line without losing track of its quoting.
+//!
+//! Besides the POSIX syntax, bash's `$'...'` strings (as [`Segment::AnsiC`]) and `$"..."` strings
+//! (as plain double quotes) are recognized.  Nothing is expanded here.
+//!
+//! ```
+//! use shlex::word::{parse, Segment};
+//! let words = parse(br#"ls "$HOME"/'my dir'"#).unwrap();
+//! assert_eq!(words.len(), 2);
+//! assert_eq!(words[1].span, 3..19);
+//! assert!(matches!(words[1].segments[0].node, Segment::DoubleQuoted(_)));
+//! assert_eq!(words[1].segments[2].span, 11..19);
+//! assert_eq!(words[1].cooked(), b"$HOME/my dir");
+//! ```
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::bytes::decode_ansi_c;
+use crate::command::command_substitution_end;
+
+/// Parse `input` into words.
+pub fn parse(input: &[u8]) -> Result<Vec<Word>, ParseError> {
+    let mut parser = Parser::new(input);
+    let mut words = Vec::new();
+    while let Some(word) = parser.next_word()? {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// A node of the syntax tree, along with the range of bytes of the input it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Range<usize>,
+}
+
+/// One word, as a sequence of segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub segments: Vec<Spanned<Segment>>,
+    /// The range of the input the word was parsed from.  For words inside a `${...}` or
+    /// `$((...))`, this is only the word itself, not the surrounding syntax.
+    pub span: Range<usize>,
+    pub(crate) source: Vec<u8>,
+}
+
+impl Word {
+    /// The text the word was parsed from, which is the part of the input in [`Word::span`].
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// The word after quote removal, but without expanding anything.  This is what
+    /// [`bytes::split`](crate::bytes::split) returns for the word, with expansions kept as they
+    /// were written.
+    ///
+    /// The results can differ where `split`, which doesn't know about expansions, would have
+    /// split a word inside one (as in `$(echo a b)`), or would have ended a double-quoted string
+    /// inside one.  `$'...'` strings are decoded, as by [`Dialect::Bash`](crate::Dialect::Bash).
+    pub fn cooked(&self) -> Vec<u8> {
+        let mut cooked = Vec::new();
+        for segment in &self.segments {
+            match &segment.node {
+                Segment::Literal(text) | Segment::SingleQuoted(text) | Segment::AnsiC(text) => cooked.extend_from_slice(text),
+                Segment::Escaped(c) => cooked.push(*c),
+                Segment::DoubleQuoted(inners) => {
+                    for inner in inners {
+                        match &inner.node {
+                            Inner::Literal(text) => cooked.extend_from_slice(text),
+                            _ => cooked.extend_from_slice(self.text(&inner.span)),
+                        }
+                    }
+                },
+                _ => cooked.extend_from_slice(self.text(&segment.span)),
+            }
+        }
+        cooked
+    }
+
+    /// The source text of a segment of this word.
+    fn text(&self, span: &Range<usize>) -> &[u8] {
+        &self.source[span.start - self.span.start..span.end - self.span.start]
+    }
+}
+
+/// A part of a [`Word`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Segment {
+    /// Unquoted text.
+    Literal(Vec<u8>),
+    /// A byte escaped with a backslash.
+    Escaped(u8),
+    /// The contents of single quotes.
+    SingleQuoted(Vec<u8>),
+    /// The contents of double quotes, or of bash's `$"..."`.
+    DoubleQuoted(Vec<Spanned<Inner>>),
+    /// A parameter expansion outside of double quotes.
+    Parameter(Parameter),
+    /// A command substitution outside of double quotes.
+    CommandSubst(CommandSubstitution),
+    /// An arithmetic expansion, `$((...))`, outside of double quotes.  The expression is parsed as
+    /// if it were in double quotes.
+    Arithmetic(Word),
+    /// The contents of bash's `$'...'`, with the backslash escapes decoded.
+    AnsiC(Vec<u8>),
+}
+
+/// A part of a double-quoted string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Inner {
+    /// Literal text, with any backslash escapes already removed.
+    Literal(Vec<u8>),
+    /// A parameter expansion.
+    Parameter(Parameter),
+    /// A command substitution.
+    CommandSubst(CommandSubstitution),
+    /// An arithmetic expansion.
+    Arithmetic(Word),
+}
+
+/// A command substitution, `$(...)` or `` `...` ``, which this crate never runs itself.  See
+/// [`Expander::command_substitution`](crate::expand::Expander::command_substitution).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommandSubstitution {
+    /// The command: the text between `$(` and `)`, or between the backquotes, with the
+    /// backslashes that escape `` ` ``, `$` and `\` (and, inside double quotes, `"`) removed.
+    pub command: Vec<u8>,
+    /// Whether the substitution used backquotes rather than `$(...)`.
+    pub backquoted: bool,
+}
+
+/// A parameter expansion, such as `$FOO` or `${FOO:-default}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    /// The name of the parameter: a variable name, a positional parameter like `1`, or a special
+    /// parameter like `@`.
+    pub name: String,
+    pub op: ParamOp,
+}
+
+/// What to do with a [`Parameter`]'s value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParamOp {
+    /// `$FOO` or `${FOO}`
+    Plain,
+    /// `${FOO-word}` or (if `colon`) `${FOO:-word}`
+    Default { colon: bool, word: Box<Word> },
+    /// `${FOO=word}` or `${FOO:=word}`
+    Assign { colon: bool, word: Box<Word> },
+    /// `${FOO?word}` or `${FOO:?word}`
+    Error { colon: bool, word: Box<Word> },
+    /// `${FOO+word}` or `${FOO:+word}`
+    Alternative { colon: bool, word: Box<Word> },
+    /// `${#FOO}`
+    Length,
+    /// `${FOO%pattern}` or (if `longest`) `${FOO%%pattern}`
+    RemoveSuffix { longest: bool, pattern: Box<Word> },
+    /// `${FOO#pattern}` or (if `longest`) `${FOO##pattern}`
+    RemovePrefix { longest: bool, pattern: Box<Word> },
+    /// `${FOO/pattern/replacement}` and its variants.  The replacement is `None` if it was
+    /// omitted along with its slash.
+    Replace { kind: ReplaceKind, pattern: Box<Word>, replacement: Option<Box<Word>> },
+    /// `${FOO:offset}` or `${FOO:offset:length}`
+    Substring { offset: Box<Word>, length: Option<Box<Word>> },
+}
+
+/// Which matches of the pattern a [`ParamOp::Replace`] replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceKind {
+    /// `${FOO/pattern/replacement}`: the first, longest match.
+    First,
+    /// `${FOO//pattern/replacement}`: every match.
+    All,
+    /// `${FOO/#pattern/replacement}`: a match at the start.
+    Prefix,
+    /// `${FOO/%pattern/replacement}`: a match at the end.
+    Suffix,
+}
+
+/// How many levels deep `${...}`, `$((...))` and `$(...)`, and double quotes inside them, can be
+/// nested.  Compound commands, such as subshells and `if` statements, count toward the same limit
+/// when [parsing commands](crate::command).
+pub const MAX_NESTING: usize = 100;
+
+/// An error from [`parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The input ended inside a quotation, an expansion, or right after a backslash.
+    Unterminated,
+    /// A `${...}` expression was malformed, or the command in a `$(...)` was not one
+    /// [`parse_script`](crate::command::parse_script) can parse.
+    BadSubstitution,
+    /// A `\u` or `\U` escape in a `$'...'` string was not a valid code point.
+    InvalidEscape,
+    /// Expansions were nested more than [`MAX_NESTING`] levels deep.
+    TooDeep,
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ParseError::Unterminated => "unterminated quote, escape or expansion",
+            ParseError::BadSubstitution => "bad substitution",
+            ParseError::InvalidEscape => "invalid code point in $'...' escape",
+            ParseError::TooDeep => "expansions nested too deeply",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// Where a sequence of segments is being parsed, which determines how it ends and which
+/// characters are special.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// A top-level word, which ends at whitespace, or, if the parser is looking for operators, at
+    /// an operator.
+    Word,
+    /// A word inside `${...}`, which ends at `}` or at `stop`, if any.  `dquoted` is whether the
+    /// word is interpreted as if in double quotes.
+    Brace { dquoted: bool, stop: Option<u8> },
+    /// The expression inside `$((...))`, which ends at a `)` that doesn't match a `(`, and is
+    /// interpreted as if in double quotes.
+    Arithmetic,
+}
+
+/// An expansion starting with `$`.
+enum Dollar {
+    Parameter(Parameter),
+    Arithmetic(Word),
+    Command(CommandSubstitution),
+}
+
+pub(crate) struct Parser<'a> {
+    input: &'a [u8],
+    pub pos: usize,
+    /// Whether unquoted operator characters and newlines end words, as they do in a command, rather
+    /// than being part of them.
+    operators: bool,
+    /// How many words inside `${...}`, `$((...))` or `$(...)` are being parsed.
+    pub nesting: usize,
+    /// Where `$((`s that turned out not to start arithmetic expansions are.  When an outer one is
+    /// parsed again as a command substitution, the inner ones aren't tried both ways again, which
+    /// would take time exponential in how deeply they're nested.
+    pub not_arithmetic: BTreeSet<usize>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Parser { input, pos: 0, operators: false, nesting: 0, not_arithmetic: BTreeSet::new() }
+    }
+
+    /// Make a parser that stops at operators, which the caller must parse itself.
+    pub fn with_operators(input: &'a [u8]) -> Self {
+        Parser { input, pos: 0, operators: true, nesting: 0, not_arithmetic: BTreeSet::new() }
+    }
+
+    pub fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    pub fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.input.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let res = self.peek();
+        if res.is_some() {
+            self.pos += 1;
+        }
+        res
+    }
+
+    /// Parse the next word, skipping whitespace and comments before it.  Returns `None` at the
+    /// end of the input.
+    pub fn next_word(&mut self) -> Result<Option<Word>, ParseError> {
+        self.skip_blanks();
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        self.parse_word().map(Some)
+    }
+
+    /// Skip whitespace and comments.  When looking for operators, newlines aren't skipped.
+    pub fn skip_blanks(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ') | Some(b'\t') => { self.pos += 1; },
+                Some(b'\n') if !self.operators => { self.pos += 1; },
+                Some(b'#') => {
+                    while self.peek().map_or(false, |c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                },
+                _ => { break; },
+            }
+        }
+    }
+
+    /// Parse a word starting at the current position.
+    pub fn parse_word(&mut self) -> Result<Word, ParseError> {
+        let start = self.pos;
+        let segments = self.parse_segments(Context::Word)?;
+        Ok(self.word(segments, start))
+    }
+
+    /// Make a word of `segments`, which were parsed from `start` up to the current position.
+    fn word(&self, segments: Vec<Spanned<Segment>>, start: usize) -> Word {
+        Word { segments, span: start..self.pos, source: self.input[start..self.pos].to_vec() }
+    }
+
+    fn parse_segments(&mut self, ctx: Context) -> Result<Vec<Spanned<Segment>>, ParseError> {
+        if ctx == Context::Word {
+            return self.parse_segments_inner(ctx);
+        }
+        if self.nesting == MAX_NESTING {
+            return Err(ParseError::TooDeep);
+        }
+        self.nesting += 1;
+        let res = self.parse_segments_inner(ctx);
+        self.nesting -= 1;
+        res
+    }
+
+    fn parse_segments_inner(&mut self, ctx: Context) -> Result<Vec<Spanned<Segment>>, ParseError> {
+        let mut segments = Vec::new();
+        let mut literal = Vec::new();
+        let mut literal_start = self.pos;
+        let (dquoted, stop) = match ctx {
+            Context::Word => (false, None),
+            Context::Brace { dquoted, stop } => (dquoted, stop),
+            Context::Arithmetic => (true, None),
+        };
+        // The number of unmatched `(`s, in an arithmetic expression.
+        let mut depth = 0;
+        loop {
+            let start = self.pos;
+            let c = match self.peek() {
+                Some(c) => c,
+                None if ctx == Context::Word => { break; },
+                None => { return Err(ParseError::Unterminated); },
+            };
+            let segment = match c {
+                b' ' | b'\t' | b'\n' if ctx == Context::Word => { break; },
+                _ if ctx == Context::Word && self.operators && is_operator_start(c) => { break; },
+                b'}' if matches!(ctx, Context::Brace { .. }) => { break; },
+                _ if Some(c) == stop => { break; },
+                b'(' | b')' if ctx == Context::Arithmetic => {
+                    if c == b'(' {
+                        depth += 1;
+                    } else if depth == 0 {
+                        break;
+                    } else {
+                        depth -= 1;
+                    }
+                    self.pos += 1;
+                    literal.push(c);
+                    continue;
+                },
+                b'\'' if !dquoted => {
+                    self.pos += 1;
+                    loop {
+                        match self.bump() {
+                            Some(b'\'') => { break; },
+                            Some(_) => {},
+                            None => { return Err(ParseError::Unterminated); },
+                        }
+                    }
+                    Segment::SingleQuoted(self.input[start + 1..self.pos - 1].to_vec())
+                },
+                b'"' => {
+                    self.pos += 1;
+                    Segment::DoubleQuoted(self.parse_double()?)
+                },
+                b'\\' => {
+                    self.pos += 1;
+                    match self.bump() {
+                        // Line continuation.
+                        Some(b'\n') => { continue; },
+                        Some(c2) if !dquoted || matches!(c2, b'$' | b'`' | b'"' | b'\\' | b'}') => {
+                            Segment::Escaped(c2)
+                        },
+                        Some(c2) => {
+                            literal.push(b'\\');
+                            literal.push(c2);
+                            continue;
+                        },
+                        None => { return Err(ParseError::Unterminated); },
+                    }
+                },
+                b'$' if !dquoted && self.peek_at(1) == Some(b'\'') => {
+                    let (text, len) = decode_ansi_c(&self.input[self.pos + 2..])?;
+                    self.pos += 2 + len;
+                    Segment::AnsiC(text)
+                },
+                b'$' if !dquoted && self.peek_at(1) == Some(b'"') => {
+                    self.pos += 2;
+                    Segment::DoubleQuoted(self.parse_double()?)
+                },
+                b'$' => match self.parse_dollar(dquoted)? {
+                    Some(Dollar::Parameter(param)) => Segment::Parameter(param),
+                    Some(Dollar::Arithmetic(word)) => Segment::Arithmetic(word),
+                    Some(Dollar::Command(command)) => Segment::CommandSubst(command),
+                    None => {
+                        literal.push(b'$');
+                        continue;
+                    },
+                },
+                b'`' => {
+                    self.pos += 1;
+                    Segment::CommandSubst(self.parse_backquoted(dquoted)?)
+                },
+                _ => {
+                    self.pos += 1;
+                    literal.push(c);
+                    continue;
+                },
+            };
+            push_literal(&mut segments, &mut literal, literal_start..start, Segment::Literal);
+            segments.push(Spanned { node: segment, span: start..self.pos });
+            literal_start = self.pos;
+        }
+        push_literal(&mut segments, &mut literal, literal_start..self.pos, Segment::Literal);
+        Ok(segments)
+    }
+
+    /// Parse the contents of a double-quoted string, after the opening quote, up to and including
+    /// the closing quote.
+    fn parse_double(&mut self) -> Result<Vec<Spanned<Inner>>, ParseError> {
+        let mut inners = Vec::new();
+        let mut literal = Vec::new();
+        let mut literal_start = self.pos;
+        loop {
+            let start = self.pos;
+            let inner = match self.peek().ok_or(ParseError::Unterminated)? {
+                b'"' => { break; },
+                b'\\' => {
+                    self.pos += 1;
+                    match self.bump().ok_or(ParseError::Unterminated)? {
+                        c @ b'$' | c @ b'`' | c @ b'"' | c @ b'\\' => literal.push(c),
+                        b'\n' => {},
+                        c => {
+                            literal.push(b'\\');
+                            literal.push(c);
+                        },
+                    }
+                    continue;
+                },
+                b'$' => match self.parse_dollar(true)? {
+                    Some(Dollar::Parameter(param)) => Inner::Parameter(param),
+                    Some(Dollar::Arithmetic(word)) => Inner::Arithmetic(word),
+                    Some(Dollar::Command(command)) => Inner::CommandSubst(command),
+                    None => {
+                        literal.push(b'$');
+                        continue;
+                    },
+                },
+                b'`' => {
+                    self.pos += 1;
+                    Inner::CommandSubst(self.parse_backquoted(true)?)
+                },
+                c => {
+                    self.pos += 1;
+                    literal.push(c);
+                    continue;
+                },
+            };
+            push_literal(&mut inners, &mut literal, literal_start..start, Inner::Literal);
+            inners.push(Spanned { node: inner, span: start..self.pos });
+            literal_start = self.pos;
+        }
+        push_literal(&mut inners, &mut literal, literal_start..self.pos, Inner::Literal);
+        self.pos += 1;
+        Ok(inners)
+    }
+
+    /// Parse the body of a here-document with an unquoted delimiter, from the current position
+    /// up to `end`.  Expansions and backslashes work as in double quotes, except that `"` isn't
+    /// special.  If `strip_tabs`, tabs at the start of lines are removed.
+    pub fn parse_here_doc(&mut self, end: usize, strip_tabs: bool) -> Result<Vec<Spanned<Inner>>, ParseError> {
+        let mut inners = Vec::new();
+        let mut literal = Vec::new();
+        let mut literal_start = self.pos;
+        while self.pos < end {
+            let start = self.pos;
+            let inner = match self.input[start] {
+                b'\t' if strip_tabs && self.input[start - 1] == b'\n' => {
+                    while self.peek() == Some(b'\t') {
+                        self.pos += 1;
+                    }
+                    continue;
+                },
+                b'\\' => {
+                    self.pos += 1;
+                    match self.bump() {
+                        Some(c @ b'$') | Some(c @ b'`') | Some(c @ b'\\') => literal.push(c),
+                        Some(b'\n') => {},
+                        Some(c) => {
+                            literal.push(b'\\');
+                            literal.push(c);
+                        },
+                        None => literal.push(b'\\'),
+                    }
+                    continue;
+                },
+                b'$' => match self.parse_dollar(true)? {
+                    Some(Dollar::Parameter(param)) => Inner::Parameter(param),
+                    Some(Dollar::Arithmetic(word)) => Inner::Arithmetic(word),
+                    Some(Dollar::Command(command)) => Inner::CommandSubst(command),
+                    None => {
+                        literal.push(b'$');
+                        continue;
+                    },
+                },
+                b'`' => {
+                    self.pos += 1;
+                    Inner::CommandSubst(self.parse_backquoted(false)?)
+                },
+                c => {
+                    self.pos += 1;
+                    literal.push(c);
+                    continue;
+                },
+            };
+            push_literal(&mut inners, &mut literal, literal_start..start, Inner::Literal);
+            inners.push(Spanned { node: inner, span: start..self.pos });
+            literal_start = self.pos;
+        }
+        // An expansion that continued past the end of the body.
+        if self.pos > end {
+            return Err(ParseError::Unterminated);
+        }
+        push_literal(&mut inners, &mut literal, literal_start..self.pos, Inner::Literal);
+        Ok(inners)
+    }
+
+    /// Parse an expansion starting with `$`.  If the `$` isn't followed by anything that makes it
+    /// an expansion, consume it and return `None`; it's literal.
+    fn parse_dollar(&mut self, dquoted: bool) -> Result<Option<Dollar>, ParseError> {
+        debug_assert_eq!(self.peek(), Some(b'$'));
+        self.pos += 1;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => { return Ok(None); },
+        };
+        let name = match c {
+            b'{' => {
+                self.pos += 1;
+                return self.parse_braced(dquoted).map(|param| Some(Dollar::Parameter(param)));
+            },
+            b'(' => {
+                self.pos += 1;
+                if self.peek() == Some(b'(') && !self.not_arithmetic.contains(&self.pos) {
+                    let start = self.pos;
+                    self.pos += 1;
+                    match self.parse_arithmetic() {
+                        Ok(Some(word)) => { return Ok(Some(Dollar::Arithmetic(word))); },
+                        Err(ParseError::TooDeep) => { return Err(ParseError::TooDeep); },
+                        _ => {},
+                    }
+                    // Something like `$((cmd) | cmd)`, which is a command substitution starting
+                    // with a subshell.
+                    self.not_arithmetic.insert(start);
+                    self.pos = start;
+                }
+                return self.parse_command().map(|command| Some(Dollar::Command(command)));
+            },
+            b'0'..=b'9' | b'@' | b'*' | b'#' | b'?' | b'-' | b'$' | b'!' => {
+                self.pos += 1;
+                String::from(c as char)
+            },
+            c if is_name_start(c) => self.parse_name(),
+            _ => { return Ok(None); },
+        };
+        Ok(Some(Dollar::Parameter(Parameter { name, op: ParamOp::Plain })))
+    }
+
+    /// Parse the rest of a `$((...))` expression, after the opening parentheses, up to and
+    /// including the closing ones.  Returns `None` if the parentheses that end it aren't doubled,
+    /// in which case it's not an arithmetic expansion after all.
+    fn parse_arithmetic(&mut self) -> Result<Option<Word>, ParseError> {
+        let start = self.pos;
+        let segments = self.parse_segments(Context::Arithmetic)?;
+        if self.peek_at(1) != Some(b')') {
+            return Ok(None);
+        }
+        let word = self.word(segments, start);
+        self.pos += 2;
+        Ok(Some(word))
+    }
+
+    /// Parse the rest of a `$(...)` command substitution, after the `$(`, up to and including the
+    /// closing `)`.
+    fn parse_command(&mut self) -> Result<CommandSubstitution, ParseError> {
+        let start = self.pos;
+        self.skip_command()?;
+        Ok(CommandSubstitution { command: self.input[start..self.pos - 1].to_vec(), backquoted: false })
+    }
+
+    /// Skip a command up to and including the `)` that ends it.  The command is parsed with the
+    /// full shell grammar, so that a `)` in a `case` pattern or a here-document doesn't end it.
+    fn skip_command(&mut self) -> Result<(), ParseError> {
+        if self.nesting == MAX_NESTING {
+            return Err(ParseError::TooDeep);
+        }
+        self.pos = command_substitution_end(self.input, self.pos, self.nesting + 1, &mut self.not_arithmetic)?;
+        Ok(())
+    }
+
+    /// Parse the rest of a backquoted command substitution, after the opening backquote, up to
+    /// and including the closing one.  `dquoted` is whether it's inside double quotes.
+    fn parse_backquoted(&mut self, dquoted: bool) -> Result<CommandSubstitution, ParseError> {
+        let mut command = Vec::new();
+        loop {
+            match self.bump().ok_or(ParseError::Unterminated)? {
+                b'`' => { break; },
+                b'\\' => {
+                    let c = self.bump().ok_or(ParseError::Unterminated)?;
+                    if !(matches!(c, b'$' | b'`' | b'\\') || (dquoted && c == b'"')) {
+                        command.push(b'\\');
+                    }
+                    command.push(c);
+                },
+                c => command.push(c),
+            }
+        }
+        Ok(CommandSubstitution { command, backquoted: true })
+    }
+
+    /// Parse a name made of bytes accepted by `pred`, which must only accept ASCII.
+    fn parse_name_with(&mut self, pred: fn(u8) -> bool) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|&c| pred(c)) {
+            name.push(c as char);
+            self.pos += 1;
+        }
+        name
+    }
+
+    fn parse_name(&mut self) -> String {
+        self.parse_name_with(is_name_char)
+    }
+
+    /// Parse the rest of a `${...}` expression, after the opening brace, up to and including the
+    /// closing brace.
+    fn parse_braced(&mut self, dquoted: bool) -> Result<Parameter, ParseError> {
+        if self.peek() == Some(b'#') && !matches!(self.peek_at(1), Some(b'}') | Some(b':')) {
+            // `${#FOO}`, as opposed to `${#}` or `${#:-word}`, which use the special parameter `#`.
+            self.pos += 1;
+            let name = self.parse_parameter_name()?;
+            if self.bump() != Some(b'}') {
+                return Err(ParseError::BadSubstitution);
+            }
+            return Ok(Parameter { name, op: ParamOp::Length });
+        }
+        let name = self.parse_parameter_name()?;
+        let op = match self.bump().ok_or(ParseError::Unterminated)? {
+            b'}' => { return Ok(Parameter { name, op: ParamOp::Plain }); },
+            b':' => match self.peek().ok_or(ParseError::Unterminated)? {
+                // bash allows an empty offset, but not an empty word after the colon.
+                b'}' => { return Err(ParseError::BadSubstitution); },
+                c @ b'-' | c @ b'=' | c @ b'?' | c @ b'+' => {
+                    self.pos += 1;
+                    default_op(c, true, self.parse_brace_word(dquoted, None)?)
+                },
+                _ => {
+                    let offset = self.parse_brace_word(dquoted, Some(b':'))?;
+                    let length = if self.input[self.pos - 1] == b':' {
+                        Some(Box::new(self.parse_brace_word(dquoted, None)?))
+                    } else {
+                        None
+                    };
+                    return Ok(Parameter { name, op: ParamOp::Substring { offset: Box::new(offset), length } });
+                },
+            },
+            c @ b'-' | c @ b'=' | c @ b'?' | c @ b'+' => default_op(c, false, self.parse_brace_word(dquoted, None)?),
+            // Patterns are not affected by surrounding double quotes.
+            b'%' => {
+                let longest = self.eat(b'%');
+                ParamOp::RemoveSuffix { longest, pattern: Box::new(self.parse_brace_word(false, None)?) }
+            },
+            b'#' => {
+                let longest = self.eat(b'#');
+                ParamOp::RemovePrefix { longest, pattern: Box::new(self.parse_brace_word(false, None)?) }
+            },
+            b'/' => {
+                let kind = if self.eat(b'/') {
+                    ReplaceKind::All
+                } else if self.eat(b'#') {
+                    ReplaceKind::Prefix
+                } else if self.eat(b'%') {
+                    ReplaceKind::Suffix
+                } else {
+                    ReplaceKind::First
+                };
+                let pattern = Box::new(self.parse_brace_word(false, Some(b'/'))?);
+                let replacement = if self.input[self.pos - 1] == b'/' {
+                    Some(Box::new(self.parse_brace_word(false, None)?))
+                } else {
+                    None
+                };
+                ParamOp::Replace { kind, pattern, replacement }
+            },
+            _ => { return Err(ParseError::BadSubstitution); },
+        };
+        Ok(Parameter { name, op })
+    }
+
+    /// Parse the name of a parameter inside `${...}`.
+    fn parse_parameter_name(&mut self) -> Result<String, ParseError> {
+        Ok(match self.peek().ok_or(ParseError::Unterminated)? {
+            c if is_name_start(c) => self.parse_name(),
+            b'0'..=b'9' => self.parse_name_with(|c| c.is_ascii_digit()),
+            c @ b'@' | c @ b'*' | c @ b'#' | c @ b'?' | c @ b'-' | c @ b'$' | c @ b'!' => {
+                self.pos += 1;
+                String::from(c as char)
+            },
+            _ => { return Err(ParseError::BadSubstitution); },
+        })
+    }
+
+    /// Consume `c` if it's next.
+    fn eat(&mut self, c: u8) -> bool {
+        let res = self.peek() == Some(c);
+        if res {
+            self.pos += 1;
+        }
+        res
+    }
+
+    /// Parse a word inside `${...}`, up to and including the closing brace or `stop`.
+    fn parse_brace_word(&mut self, dquoted: bool, stop: Option<u8>) -> Result<Word, ParseError> {
+        let start = self.pos;
+        let segments = self.parse_segments(Context::Brace { dquoted, stop })?;
+        let word = self.word(segments, start);
+        self.pos += 1;
+        Ok(word)
+    }
+}
+
+/// Move `literal`, if it's not empty, into a new node made with `make`.
+fn push_literal<T>(nodes: &mut Vec<Spanned<T>>, literal: &mut Vec<u8>, span: Range<usize>, make: fn(Vec<u8>) -> T) {
+    if !literal.is_empty() {
+        nodes.push(Spanned { node: make(core::mem::take(literal)), span });
+    }
+}
+
+fn default_op(c: u8, colon: bool, word: Word) -> ParamOp {
+    let word = Box::new(word);
+    match c {
+        b'-' => ParamOp::Default { colon, word },
+        b'=' => ParamOp::Assign { colon, word },
+        b'?' => ParamOp::Error { colon, word },
+        _ => ParamOp::Alternative { colon, word },
+    }
+}
+
+/// Whether `c` starts an operator, when unquoted in a command.
+pub(crate) fn is_operator_start(c: u8) -> bool {
+    matches!(c, b';' | b'&' | b'|' | b'<' | b'>' | b'(' | b')')
+}
+
+pub(crate) fn is_name_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+pub(crate) fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+#[test]
+fn test_parse_spans() {
+    let words = parse(b"a\\ b \"x$FOO\"${BAR:-y z}$'\\t'").unwrap();
+    assert_eq!(words.len(), 2);
+    assert_eq!(words[0].span, 0..4);
+    let spans: Vec<_> = words[0].segments.iter().map(|segment| segment.span.clone()).collect();
+    assert_eq!(spans, [0..1, 1..3, 3..4]);
+
+    let word = &words[1];
+    assert_eq!(word.span, 5..28);
+    assert_eq!(word.source(), &b"\"x$FOO\"${BAR:-y z}$'\\t'"[..]);
+    let spans: Vec<_> = word.segments.iter().map(|segment| segment.span.clone()).collect();
+    assert_eq!(spans, [5..12, 12..23, 23..28]);
+    match &word.segments[0].node {
+        Segment::DoubleQuoted(inners) => {
+            assert_eq!(inners[0], Spanned { node: Inner::Literal(b"x".to_vec()), span: 6..7 });
+            assert_eq!(inners[1].span, 7..11);
+        },
+        segment => panic!("unexpected {:?}", segment),
+    }
+    match &word.segments[1].node {
+        Segment::Parameter(Parameter { op: ParamOp::Default { word, .. }, .. }) => {
+            assert_eq!(word.span, 19..22);
+            assert_eq!(word.cooked(), b"y z");
+        },
+        segment => panic!("unexpected {:?}", segment),
+    }
+    assert_eq!(word.segments[2].node, Segment::AnsiC(b"\t".to_vec()));
+    assert_eq!(word.cooked(), b"x$FOO${BAR:-y z}\t");
+
+    assert_eq!(parse(b"$'\\ud800'"), Err(ParseError::InvalidEscape));
+}
+
+#[test]
+fn test_parse_nesting() {
+    let nested = |open: &[u8], depth: usize| {
+        let mut input = open.repeat(depth);
+        input.push(b'x');
+        input.extend(b"}".repeat(depth));
+        input
+    };
+    assert!(parse(&nested(b"${a:-", MAX_NESTING)).is_ok());
+    assert_eq!(parse(&nested(b"${a:-", MAX_NESTING + 1)), Err(ParseError::TooDeep));
+    assert_eq!(parse(&nested(b"${a:-", 10_000)), Err(ParseError::TooDeep));
+    assert_eq!(parse(&nested(b"${a#", 10_000)), Err(ParseError::TooDeep));
+    let mut input = b"$(echo ".repeat(MAX_NESTING);
+    input.extend(b")".repeat(MAX_NESTING));
+    assert!(parse(&input).is_ok());
+    let mut input = b"$(echo ".repeat(10_000);
+    input.extend(b")".repeat(10_000));
+    assert_eq!(parse(&input), Err(ParseError::TooDeep));
+    let mut input = b"$((".repeat(10_000);
+    input.extend(b"))".repeat(10_000));
+    assert_eq!(parse(&input), Err(ParseError::TooDeep));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_parse_nested_arithmetic_or_command() {
+    // Each `$((` is first tried as an arithmetic expansion, then as a command substitution
+    // starting with a subshell, but nested ones must not be tried both ways every time.
+    let nested = |depth: usize| {
+        let mut input = b"$(( ".repeat(depth);
+        input.extend(b"x ) )".repeat(depth));
+        input
+    };
+    let start = std::time::Instant::now();
+    let words = parse(&nested(40)).unwrap();
+    assert!(matches!(words[0].segments[0].node, Segment::CommandSubst(_)));
+    assert_eq!(parse(&nested(1000)), Err(ParseError::TooDeep));
+    assert!(start.elapsed() < std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn test_cooked_matches_split() {
+    let inputs: &[&[u8]] = &[
+        b"foo bar",
+        b"'a b' \"c d\" e\\ f",
+        b"\"\\$x \\a \\\" \\\\\" 'it'\\''s'",
+        b"$FOO \"$FOO\"bar ${FOO} x${FOO%.c}y",
+        b"a$ $ \"$\" $1 $@ \"$*\"",
+        b"line\\\ncontinued # comment",
+        b"`cmd` \"`cmd`\" $(cmd) \"$(cmd)\" $((1+2)) \"$((1 + 2))\"",
+        b"#comment\n  after",
+        b"\"\" '' x\"\"y",
+    ];
+    for &input in inputs {
+        let cooked: Vec<Vec<u8>> = parse(input).unwrap().iter().map(Word::cooked).collect();
+        assert_eq!(Some(cooked), crate::bytes::split(input), "{:?}", core::str::from_utf8(input));
+    }
+}